@@ -9,6 +9,25 @@ use aws_sdk_lambda::types::{Environment, FunctionCode, Runtime};
 use aws_sdk_s3::Client as S3Client;
 use aws_sdk_s3::config::{Builder, Credentials, SharedCredentialsProvider};
 
+// Fills the `${partition}`, `${region}`, `${account}` and `${stage}` placeholders
+// policy_macros leaves in ARNs built from a configured template
+pub struct DeployEnvironment<'a> {
+    pub partition: &'a str,
+    pub region: &'a str,
+    pub account: &'a str,
+    pub stage: &'a str,
+}
+
+impl DeployEnvironment<'_> {
+    pub fn fill_policy(&self, policy: &str) -> String {
+        policy
+            .replace("${partition}", self.partition)
+            .replace("${region}", self.region)
+            .replace("${account}", self.account)
+            .replace("${stage}", self.stage)
+    }
+}
+
 pub struct LambdaClient {
     lambda_client: aws_sdk_lambda::Client,
     iam_client: aws_sdk_iam::Client,
@@ -43,8 +62,9 @@ impl LambdaClient {
         function_name: &str,
         zipped_code_path: &std::path::Path,
         policy_path: &std::path::Path,
+        environment: &DeployEnvironment<'_>,
     ) -> Result<String, Box<dyn std::error::Error>> {
-        let role_arn = self.create_or_get_lambda_role(role_name, policy_path).await?;
+        let role_arn = self.create_or_get_lambda_role(role_name, policy_path, environment).await?;
 
         let creds = Credentials::new("test", "test", None, None, "test");
         let creds_provider = SharedCredentialsProvider::new(creds);
@@ -120,7 +140,8 @@ impl LambdaClient {
     async fn create_or_get_lambda_role(
         &self,
         role_name: &str,
-        policy_path: &Path
+        policy_path: &Path,
+        environment: &DeployEnvironment<'_>,
     ) -> Result<String, Box<dyn std::error::Error>> {
         let assume_role_policy = serde_json::json!({
             "Version": "2012-10-17",
@@ -133,7 +154,7 @@ impl LambdaClient {
             }]
         });
 
-        let policy_path_data = fs::read_to_string(policy_path)?;
+        let policy_path_data = environment.fill_policy(&fs::read_to_string(policy_path)?);
 
         let create_role_result = self
             .iam_client
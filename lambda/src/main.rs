@@ -15,6 +15,12 @@ async fn main() -> Result<(), io::Error> {
     let rolename = "test-rolename3";
     let http_method = "GET";
     let policy_path = Path::new("./test_policy.json");
+    let environment = lambda::DeployEnvironment {
+        partition: "aws",
+        region: "us-east-1",
+        account: account_id,
+        stage: "dev",
+    };
 
     let mut args = env::args().skip(1); // skip program name
 
@@ -52,7 +58,7 @@ async fn main() -> Result<(), io::Error> {
 
     // Deploy Lambda function (or update if function already exists) 
     let function_arn = lambda_client
-        .deploy_fn(rolename, &function_name, &zip_path, &policy_path)
+        .deploy_fn(rolename, &function_name, &zip_path, &policy_path, &environment)
         .await
         .expect("deploy lambda");
 
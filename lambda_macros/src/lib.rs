@@ -1,26 +1,44 @@
-use std::{fs, io::{self, Write}, path::Path};
+use std::{fs, io::Write, path::Path};
 
 use proc_macro::TokenStream;
 use quote::ToTokens;
 use serde_json::json;
+#[cfg(feature = "sam")]
+use serde_json::Value;
 use syn::{parse_macro_input, spanned::Spanned, Error, FnArg, ItemFn, ReturnType};
 use toml_edit::{value, DocumentMut, Item};
 mod lambda;
 
+#[cfg(not(any(feature = "terraform", feature = "sam")))]
+compile_error!("lambda_macros needs at least one deployment target, enable the \"terraform\" or \"sam\" feature");
 
-fn add_dependencies(crate_root: &str) -> Result<(), io::Error> {
-    let crate_root_path = Path::new(crate_root);
-    let cargo_toml_path = crate_root_path.join("Cargo.toml");
-    let text = fs::read_to_string(&cargo_toml_path)?;
-    let mut doc = text.parse::<DocumentMut>().unwrap();
-    let dependencies = doc.as_table_mut().entry("dependencies").or_insert(Item::Value(toml_edit::Value::InlineTable(Default::default()))).as_table_mut().unwrap();
+
+fn read_cargo_toml(crate_root: &str) -> Result<DocumentMut, String> {
+    let cargo_toml_path = Path::new(crate_root).join("Cargo.toml");
+    let text = fs::read_to_string(&cargo_toml_path).map_err(|err| format!("Could not read {}: {err}", cargo_toml_path.display()))?;
+    text.parse::<DocumentMut>().map_err(|err| format!("Could not parse {}: {err}", cargo_toml_path.display()))
+}
+
+fn write_cargo_toml(crate_root: &str, doc: &DocumentMut) -> Result<(), String> {
+    let cargo_toml_path = Path::new(crate_root).join("Cargo.toml");
+    fs::write(&cargo_toml_path, doc.to_string()).map_err(|err| format!("Could not write {}: {err}", cargo_toml_path.display()))
+}
+
+fn add_dependencies(crate_root: &str) -> Result<(), String> {
+    let mut doc = read_cargo_toml(crate_root)?;
+    let dependencies = doc
+        .as_table_mut()
+        .entry("dependencies")
+        .or_insert(Item::Table(Default::default()))
+        .as_table_like_mut()
+        .ok_or("[dependencies] in Cargo.toml isn't a table")?;
     if !dependencies.contains_key("lambda_runtime") {
-        dependencies["lambda_runtime"] = value("0.13.0");
+        dependencies.insert("lambda_runtime", value("0.13.0"));
     }
     if !dependencies.contains_key("tokio") {
-        dependencies["tokio"] = value("{ version = \"1\", features = [\"macros\"] }");
+        dependencies.insert("tokio", value("{ version = \"1\", features = [\"macros\"] }"));
     }
-    fs::write(&cargo_toml_path, doc.to_string())
+    write_cargo_toml(crate_root, &doc)
 }
 
 fn return_type(func: &ItemFn) -> String {
@@ -31,24 +49,22 @@ fn return_type(func: &ItemFn) -> String {
 }
 
 fn input_type(func: &ItemFn) -> String {
-    // We already checked that the function has an argument
-    match func.sig.inputs.first().unwrap() {
-        FnArg::Receiver(_) => panic!("Annotated bad function"),
-        FnArg::Typed(pat_type) => pat_type.ty.to_token_stream().to_string()
+    // We already checked that the function has a single typed argument
+    match func.sig.inputs.first() {
+        Some(FnArg::Typed(pat_type)) => pat_type.ty.to_token_stream().to_string(),
+        _ => unreachable!("lambda checks the function's argument"),
     }
 }
 
-fn root_crate_name(crate_root: &str) -> Result<String, io::Error> {
-    let crate_root_path = Path::new(crate_root);
-    let cargo_toml_path = crate_root_path.join("Cargo.toml");
-    let text = fs::read_to_string(&cargo_toml_path)?;
-    let doc = text.parse::<DocumentMut>().unwrap();
-    Ok(doc["package"]["name"].as_str().unwrap().replace("-", "_"))
+fn root_crate_name(crate_root: &str) -> Result<String, String> {
+    let doc = read_cargo_toml(crate_root)?;
+    let name = doc.get("package").and_then(|package| package.get("name")).and_then(|name| name.as_str());
+    Ok(name.ok_or("Cargo.toml has no package.name")?.replace("-", "_"))
 }
 
-fn write_handler(file: &mut fs::File, crate_root: &str, func_name: &str, func: ItemFn) -> Result<(), io::Error> {
+fn write_handler(file: &mut fs::File, crate_root: &str, func_name: &str, func: ItemFn) -> Result<(), std::io::Error> {
     writeln!(file, "use lambda_runtime::{{run, service_fn, LambdaEvent, Error}};")?;
-    writeln!(file, "use {}::*;", root_crate_name(&crate_root)?)?;
+    writeln!(file, "use {}::*;", root_crate_name(crate_root).map_err(std::io::Error::other)?)?;
     writeln!(file, "")?;
     writeln!(
         file, 
@@ -68,7 +84,7 @@ fn write_handler(file: &mut fs::File, crate_root: &str, func_name: &str, func: I
     Ok(())
 }
 
-fn create_binary(crate_root: &str, func: ItemFn) -> Result<(), io::Error> {
+fn create_binary(crate_root: &str, func: ItemFn) -> Result<(), std::io::Error> {
     let crate_root_path = Path::new(&crate_root);
     let crate_bin_path = crate_root_path.join("bin");
     if !crate_bin_path.exists() {
@@ -86,7 +102,8 @@ fn create_binary(crate_root: &str, func: ItemFn) -> Result<(), io::Error> {
     Ok(())
 }
 
-fn generate_terraform(crate_root: &str, func_name: &str, lambda: &lambda::Lambda) -> Result<(), io::Error> {
+#[cfg(feature = "terraform")]
+fn generate_terraform(crate_root: &str, func_name: &str, lambda: &lambda::Lambda) -> Result<(), std::io::Error> {
     let terraform_path = Path::new(crate_root).join("terraform");
     
     if !terraform_path.exists() {
@@ -126,14 +143,147 @@ fn generate_terraform(crate_root: &str, func_name: &str, lambda: &lambda::Lambda
     Ok(())
 }
 
-fn create_bin_and_add_dependencies(crate_root: String, lambda: lambda::Lambda, func: ItemFn) -> Result<(), io::Error> {
+// `${...}` placeholders policy_macros leaves in ARNs -> the CloudFormation value filling them in
+#[cfg(feature = "sam")]
+const SAM_PLACEHOLDERS: [(&str, &str); 4] = [
+    ("partition", "AWS::Partition"),
+    ("region", "AWS::Region"),
+    ("account", "AWS::AccountId"),
+    ("stage", "Stage"),
+];
+
+// Strings holding placeholders become an Fn::Sub, where every other `${` (IAM
+// policy variables and the `${*}` escapes) has to be written as `${!`
+#[cfg(feature = "sam")]
+fn sub_placeholders(policy: Value) -> Value {
+    match policy {
+        Value::String(s) if SAM_PLACEHOLDERS.iter().any(|(placeholder, _)| s.contains(&format!("${{{placeholder}}}"))) => {
+            let escaped = s.replace("${", "${!");
+            let sub = SAM_PLACEHOLDERS.iter().fold(escaped, |s, (placeholder, value)| {
+                s.replace(&format!("${{!{placeholder}}}"), &format!("${{{value}}}"))
+            });
+            json!({ "Fn::Sub": sub })
+        }
+        Value::Array(values) => Value::Array(values.into_iter().map(sub_placeholders).collect()),
+        Value::Object(fields) => Value::Object(fields.into_iter().map(|(key, value)| (key, sub_placeholders(value))).collect()),
+        value => value,
+    }
+}
+
+// my_test -> MyTestFunction, logical ids can only hold letters and digits
+#[cfg(feature = "sam")]
+fn logical_id(func_name: &str) -> String {
+    let mut id: String = func_name
+        .split('_')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect();
+    id.push_str("Function");
+    id
+}
+
+// `sam build` builds the handler with cargo lambda, which only knows about
+// binaries listed in Cargo.toml
+#[cfg(feature = "sam")]
+fn add_bin_target(crate_root: &str, func_name: &str) -> Result<(), String> {
+    let mut doc = read_cargo_toml(crate_root)?;
+    let bins = doc
+        .as_table_mut()
+        .entry("bin")
+        .or_insert(Item::ArrayOfTables(Default::default()))
+        .as_array_of_tables_mut()
+        .ok_or("bin in Cargo.toml isn't a list of [[bin]] tables")?;
+    if bins.iter().any(|bin| bin.get("name").and_then(|name| name.as_str()) == Some(func_name)) {
+        return Ok(());
+    }
+    let mut bin = toml_edit::Table::new();
+    bin["name"] = value(func_name);
+    bin["path"] = value(format!("bin/{func_name}.rs"));
+    bins.push(bin);
+    write_cargo_toml(crate_root, &doc)
+}
+
+// Writes template.yaml for `sam build && sam local start-api`, adding the
+// function to the template already there
+#[cfg(feature = "sam")]
+fn generate_sam(crate_root: &str, func_name: &str, lambda: &lambda::Lambda) -> Result<(), String> {
+    let crate_root_path = Path::new(crate_root);
+    add_bin_target(crate_root, func_name)?;
+
+    let template_path = crate_root_path.join("template.yaml");
+    let mut template: Value = match fs::read_to_string(&template_path) {
+        Ok(text) => serde_yaml::from_str(&text).map_err(|err| format!("Could not parse {}: {err}", template_path.display()))?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => serde_yaml::from_str(SAM_TEMPLATE).expect("SAM_TEMPLATE is valid YAML"),
+        Err(err) => return Err(format!("Could not read {}: {err}", template_path.display())),
+    };
+
+    let mut properties = json!({
+        "CodeUri": ".",
+        "Handler": "bootstrap",
+        "Runtime": "provided.al2023",
+        "Timeout": 30,
+        "Events": {
+            "Api": {
+                "Type": "Api",
+                "Properties": {
+                    "Path": format!("/{}", lambda.path.trim_start_matches('/')),
+                    "Method": lambda.http_action.to_string().to_lowercase()
+                }
+            }
+        }
+    });
+    // policy_attr has already written the policy, lambda makes sure it's listed
+    // above #[lambda]. SAM adds the basic execution role policy itself.
+    let policy_path = crate_root_path.join("policies").join(format!("{func_name}.json"));
+    if policy_path.exists() {
+        let text = fs::read_to_string(&policy_path).map_err(|err| format!("Could not read {}: {err}", policy_path.display()))?;
+        let policy: Value = serde_json::from_str(&text).map_err(|err| format!("Could not parse {}: {err}", policy_path.display()))?;
+        properties["Policies"] = json!([sub_placeholders(policy)]);
+    }
+    template["Resources"][logical_id(func_name)] = json!({
+        "Type": "AWS::Serverless::Function",
+        "Metadata": {
+            "BuildMethod": "rust-cargolambda",
+            "BuildProperties": {
+                "Binary": func_name
+            }
+        },
+        "Properties": properties
+    });
+    let yaml = serde_yaml::to_string(&template).map_err(|err| format!("Could not serialize the SAM template: {err}"))?;
+    fs::write(&template_path, yaml).map_err(|err| format!("Could not write {}: {err}", template_path.display()))
+}
+
+fn create_bin_and_add_dependencies(crate_root: String, lambda: lambda::Lambda, func: ItemFn) -> Result<(), String> {
     let func_name = func.sig.ident.to_string();
-    add_dependencies(&crate_root);
-    create_binary(&crate_root, func);
-    generate_terraform(&crate_root, &func_name, &lambda)?;
+    add_dependencies(&crate_root)?;
+    create_binary(&crate_root, func).map_err(|err| format!("Could not write bin/{func_name}.rs: {err}"))?;
+    #[cfg(feature = "terraform")]
+    generate_terraform(&crate_root, &func_name, &lambda).map_err(|err| format!("Could not write the Terraform files: {err}"))?;
+    #[cfg(feature = "sam")]
+    generate_sam(&crate_root, &func_name, &lambda)?;
     Ok(())
 }
 
+// policy_attr removes itself from the function, so it's still there when it's
+// listed below #[lambda] and hasn't written the policy the template reads yet
+#[cfg(feature = "sam")]
+fn check_policy_attr_order(func: &ItemFn) -> syn::Result<()> {
+    let policy_attr = func.attrs.iter().find(|attr| attr.path().segments.last().is_some_and(|segment| segment.ident == "policy_attr"));
+    match policy_attr {
+        Some(attr) => Err(Error::new(
+            attr.span(),
+            "#[policy_attr] has to be listed above #[lambda], which reads the policy it writes into template.yaml",
+        )),
+        None => Ok(()),
+    }
+}
+
 
 #[proc_macro_attribute]
 pub fn lambda(attr: TokenStream, item: TokenStream) -> TokenStream {
@@ -144,25 +294,48 @@ pub fn lambda(attr: TokenStream, item: TokenStream) -> TokenStream {
             "Only function with exactly one argument can be deployed on Lambda. Consider packaging the arguments into a struct that implements Deserialize"
             ).into_compile_error().into();
     }
+    if let Some(FnArg::Receiver(receiver)) = func.sig.inputs.first() {
+        return Error::new(receiver.span(), "Only free functions can be deployed on Lambda, not methods")
+            .into_compile_error()
+            .into();
+    }
     if func.sig.asyncness.is_none() {
         return Error::new(
             func.sig.asyncness.span(),
             "Only async functions can be deployed on Lambda. Consider marking this function as async."
         ).into_compile_error().into();
     }
-    let crate_root = std::env::var("CARGO_MANIFEST_DIR");
-    if let Err(_) = crate_root {
+    let Ok(crate_root) = std::env::var("CARGO_MANIFEST_DIR") else {
         return Error::new(func.span(), "Could not locate crate root").into_compile_error().into();
-    }
-    let crate_root = crate_root.unwrap();
+    };
     let lambda = parse_macro_input!(attr as lambda::Lambda);
+    #[cfg(feature = "sam")]
+    if let Err(err) = check_policy_attr_order(&func) {
+        return err.into_compile_error().into();
+    }
     if let Err(err) = create_bin_and_add_dependencies(crate_root, lambda, func.clone()) {
-        return Error::new(func.span(), err.to_string()).to_compile_error().into()
+        return Error::new(func.span(), err).to_compile_error().into();
     }
-    return func.to_token_stream().into();
+    func.to_token_stream().into()
 }
 
 
+#[cfg(feature = "sam")]
+const SAM_TEMPLATE: &str = r#"AWSTemplateFormatVersion: "2010-09-09"
+Transform: AWS::Serverless-2016-10-31
+Parameters:
+  Stage:
+    Description: Deployment stage filled into the policies' ARN templates
+    Type: String
+    Default: dev
+Resources: {}
+Outputs:
+  ApiUrl:
+    Description: URL of the API's Prod stage
+    Value:
+      "Fn::Sub": "https://${ServerlessRestApi}.execute-api.${AWS::Region}.amazonaws.com/Prod/"
+"#;
+#[cfg(feature = "terraform")]
 const TERRAFORM_TFVARS_TEMPLATE: &str = r#"{
   "account_id": "000000000000",
   "api_name": "my-new-api-terraform",
@@ -171,6 +344,7 @@ const TERRAFORM_TFVARS_TEMPLATE: &str = r#"{
 }"#;
 
 
+#[cfg(feature = "terraform")]
 const MAIN_TF_TEMPLATE: &str = r#"{
   "terraform": {
     "required_providers": {
@@ -184,7 +358,7 @@ const MAIN_TF_TEMPLATE: &str = r#"{
     "aws": {
       "access_key": "test",
       "secret_key": "test",
-      "region": "us-east-1",
+      "region": "${var.region}",
       "skip_credentials_validation": true,
       "skip_metadata_api_check": true,
       "skip_requesting_account_id": true,
@@ -192,17 +366,39 @@ const MAIN_TF_TEMPLATE: &str = r#"{
         "apigateway": "http://localhost:4566",
         "iam": "http://localhost:4566",
         "lambda": "http://localhost:4566",
-        "s3": "http://localhost:4566"
+        "s3": "http://localhost:4566",
+        "sts": "http://localhost:4566"
       },
       "s3_use_path_style": true
     }
   },
+  "data": {
+    "aws_caller_identity": {
+      "current": {}
+    },
+    "aws_partition": {
+      "current": {}
+    },
+    "aws_region": {
+      "current": {}
+    }
+  },
   "variable": {
     "account_id": {
       "description": "AWS Account ID",
       "type": "string",
       "default": "000000000000"
     },
+    "region": {
+      "description": "AWS region to deploy to",
+      "type": "string",
+      "default": "us-east-1"
+    },
+    "stage": {
+      "description": "Deployment stage filled into the policies' ARN templates",
+      "type": "string",
+      "default": "dev"
+    },
     "api_name": {
       "description": "API Gateway name",
       "type": "string"
@@ -252,10 +448,10 @@ const MAIN_TF_TEMPLATE: &str = r#"{
     },
     "aws_iam_role_policy": {
       "function_policies": {
-        "for_each": "${{ for func in var.lambda_functions : func.name => func }}",
+        "for_each": "${{ for func in var.lambda_functions : func.name => func if !fileexists(\"${path.module}/${func.name}.policy.tf.json\") }}",
         "name": "${each.value.name}-policy",
         "role": "${aws_iam_role.function_roles[each.key].id}",
-        "policy": "${fileexists(each.value.policy_document) ? file(each.value.policy_document) : each.value.policy_document}"
+        "policy": "${replace(replace(replace(replace(fileexists(each.value.policy_document) ? file(each.value.policy_document) : each.value.policy_document, \"$${partition}\", data.aws_partition.current.partition), \"$${region}\", var.region), \"$${account}\", var.account_id), \"$${stage}\", var.stage)}"
       }
     },
     "aws_lambda_function": {
@@ -344,3 +540,55 @@ const MAIN_TF_TEMPLATE: &str = r#"{
   }
 }"#;
 
+
+#[cfg(all(test, feature = "sam"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sam_template_is_yaml_and_collects_functions() {
+        let crate_root = std::env::temp_dir().join(format!("lambda_macros_sam_{}", std::process::id()));
+        fs::create_dir_all(crate_root.join("policies")).unwrap();
+        fs::write(crate_root.join("Cargo.toml"), "[package]\nname = \"my-app\"\n").unwrap();
+        fs::write(
+            crate_root.join("policies").join("get_user.json"),
+            r#"{"Version": "2012-10-17", "Statement": [{"Effect": "Allow", "Action": "dynamodb:GetItem", "Resource": "arn:${partition}:dynamodb:${region}:${account}:table/${stage}-Users"}]}"#,
+        )
+        .unwrap();
+        let crate_root_str = crate_root.to_str().unwrap();
+        let lambda: lambda::Lambda = syn::parse_str(r#"GET "users""#).unwrap();
+        generate_sam(crate_root_str, "get_user", &lambda).unwrap();
+        generate_sam(crate_root_str, "list_users", &lambda).unwrap();
+        generate_sam(crate_root_str, "get_user", &lambda).unwrap();
+
+        let text = fs::read_to_string(crate_root.join("template.yaml")).unwrap();
+        assert!(!text.trim_start().starts_with('{'), "{text}");
+        let template: Value = serde_yaml::from_str(&text).unwrap();
+        let resources = template["Resources"].as_object().unwrap();
+        assert_eq!(resources.keys().collect::<Vec<_>>(), ["GetUserFunction", "ListUsersFunction"]);
+        assert_eq!(
+            resources["GetUserFunction"]["Properties"]["Policies"][0]["Statement"][0]["Resource"],
+            json!({ "Fn::Sub": "arn:${AWS::Partition}:dynamodb:${AWS::Region}:${AWS::AccountId}:table/${Stage}-Users" })
+        );
+        assert!(resources["ListUsersFunction"]["Properties"].get("Policies").is_none());
+        assert_eq!(template["Parameters"]["Stage"]["Default"], "dev");
+
+        let cargo_toml = read_cargo_toml(crate_root_str).unwrap();
+        let bins: Vec<&str> = cargo_toml["bin"]
+            .as_array_of_tables()
+            .unwrap()
+            .iter()
+            .filter_map(|bin| bin.get("name")?.as_str())
+            .collect();
+        assert_eq!(bins, ["get_user", "list_users"]);
+        fs::remove_dir_all(&crate_root).unwrap();
+    }
+
+    #[test]
+    fn policy_attr_has_to_come_first() {
+        let below: ItemFn = syn::parse_str("#[policy_macros::policy_attr(allow get on bucket \"b\")] async fn f(x: u32) {}").unwrap();
+        assert!(check_policy_attr_order(&below).unwrap_err().to_string().contains("above #[lambda]"));
+        let above: ItemFn = syn::parse_str("async fn f(x: u32) {}").unwrap();
+        check_policy_attr_order(&above).unwrap();
+    }
+}
@@ -36,6 +36,10 @@ async fn main() {
     // create app state with db and token map
     let jwks = auth::fetch_jwks().await.unwrap();
 
+    // record the handlers' calls, creating the tables above isn't one of them
+    #[cfg(feature = "record")]
+    let db = policy_runtime::CallRecorder::from_env().client(&db);
+
     let state = Arc::new(AppState {
         db,
         user_table_name: "Users".to_string(),
@@ -100,6 +100,9 @@ pub(crate) async fn update_profile(
         where key_like $pk "USER#*"
         where key_equals $sk "PROFILE"
         with attributes ["full_name" "email"]
+    allow query on table "Users"
+        where key_equals $pk concat("USER#", $user_id)
+        where key_like $sk "FRIEND#*"
 )]
 pub(crate) async fn get_profile(
     State(state): State<Arc<AppState>>,
@@ -108,11 +111,13 @@ pub(crate) async fn get_profile(
 ) -> impl IntoResponse {
     // This should be pulled out of the request
     // let my_user_id = "123";
+    // Accepted friendships are stored under both users, so the requested user's
+    // FRIEND# rows are queried, which is what the policy above allows
     match util::users_are_friends_or_identical(
         &state.db,
         &state.user_table_name,
-        &claims.sub.strip_prefix("auth0|").unwrap(),
         &user_id,
+        &claims.sub.strip_prefix("auth0|").unwrap(),
     )
     .await
     {
@@ -209,6 +209,7 @@ pub(crate) async fn get_profile(
         .key_condition_expression("PK = :pk AND SK = :sk")
         .expression_attribute_values(":pk", AttributeValue::S(user_id(user)))
         .expression_attribute_values(":sk", AttributeValue::S("PROFILE".to_string()))
+        .projection_expression("full_name, email")
         .send()
         .await?;
     let profile: Option<Profile> = match resp.items().first() {
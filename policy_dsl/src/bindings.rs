@@ -0,0 +1,73 @@
+use syn::{Error, FnArg, ItemFn, Pat};
+
+use crate::policy::Policy;
+
+// Collects every name a parameter pattern binds, so `Path(user_id)` binds
+// `user_id` and `AuthUser { claims }` binds `claims`.
+fn bind_pattern(pat: &Pat, names: &mut Vec<String>) {
+    match pat {
+        Pat::Ident(pat_ident) => {
+            names.push(pat_ident.ident.to_string());
+            if let Some((_, subpat)) = &pat_ident.subpat {
+                bind_pattern(subpat, names);
+            }
+        }
+        Pat::TupleStruct(pat_tuple_struct) => {
+            pat_tuple_struct.elems.iter().for_each(|elem| bind_pattern(elem, names))
+        }
+        Pat::Tuple(pat_tuple) => pat_tuple.elems.iter().for_each(|elem| bind_pattern(elem, names)),
+        Pat::Slice(pat_slice) => pat_slice.elems.iter().for_each(|elem| bind_pattern(elem, names)),
+        Pat::Struct(pat_struct) => {
+            pat_struct.fields.iter().for_each(|field| bind_pattern(&field.pat, names))
+        }
+        Pat::Reference(pat_reference) => bind_pattern(&pat_reference.pat, names),
+        Pat::Paren(pat_paren) => bind_pattern(&pat_paren.pat, names),
+        Pat::Type(pat_type) => bind_pattern(&pat_type.pat, names),
+        _ => {}
+    }
+}
+
+pub fn bound_names(func: &ItemFn) -> Vec<String> {
+    let mut names = vec![];
+    for input in &func.sig.inputs {
+        match input {
+            FnArg::Receiver(_) => names.push("self".to_string()),
+            FnArg::Typed(pat_type) => bind_pattern(&pat_type.pat, &mut names),
+        }
+    }
+    names
+}
+
+// Every `$var` in the policy has to be rooted in one of the function's parameters
+pub fn check_variables(policy: &Policy, func: &ItemFn) -> syn::Result<()> {
+    check_bound(policy, &func.sig.ident.to_string(), &bound_names(func))
+}
+
+// Every `$var` in the policy has to be rooted in one of `names`, the parameters
+// of `owner`
+pub fn check_bound(policy: &Policy, owner: &str, names: &[String]) -> syn::Result<()> {
+    let mut errors: Option<Error> = None;
+    for var in policy.atoms().iter().flat_map(|atom| atom.variables()) {
+        if names.contains(&var.path[0]) {
+            continue;
+        }
+        let message = if names.is_empty() {
+            format!("unknown variable '${}': '{owner}' has no parameters", var.name())
+        } else {
+            format!(
+                "unknown variable '${}': expected one of the parameters of '{owner}' ({})",
+                var.name(),
+                names.join(", ")
+            )
+        };
+        let error = Error::new(var.span, message);
+        match &mut errors {
+            Some(errors) => errors.combine(error),
+            None => errors = Some(error),
+        }
+    }
+    match errors {
+        Some(errors) => Err(errors),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,88 @@
+// The IAM condition keys and operators the `when` clause accepts, taken from the
+// lists in aws_iam_info/ so they can be checked while the policy is parsed.
+
+const GLOBAL_CONDITION_KEYS: &str = include_str!("../aws_iam_info/global_condition_context_keys.txt");
+const CONDITION_OPERATORS: &str = include_str!("../aws_iam_info/condition_operators.txt");
+
+// Keys documented as `aws:PrincipalTag/tag-key` accept any tag after the slash
+const TAG_KEY_PLACEHOLDER: &str = "tag-key";
+
+fn global_condition_keys() -> Vec<&'static str> {
+    GLOBAL_CONDITION_KEYS
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        // section headers end with ':' and links contain "://"
+        .filter(|word| word.contains(':') && !word.ends_with(':') && !word.contains("://"))
+        .collect()
+}
+
+fn condition_operators() -> Vec<&'static str> {
+    CONDITION_OPERATORS.lines().map(str::trim).filter(|line| !line.is_empty()).collect()
+}
+
+pub fn is_condition_key(key: &str) -> bool {
+    global_condition_keys().iter().any(|known| match known.strip_suffix(TAG_KEY_PLACEHOLDER) {
+        Some(prefix) => key.len() > prefix.len() && key.starts_with(prefix),
+        None => *known == key,
+    })
+}
+
+// Accepts the operator as IAM spells it (`IpAddress`, `ForAnyValue:StringLike`)
+// or in snake case (`ip_address`, `for_any_value:string_like`), plus the
+// `ip_in`/`not_ip_in` shorthands. Returns the IAM spelling.
+pub fn resolve_operator(operator: &str) -> Option<&'static str> {
+    let operator = match operator {
+        "ip_in" => "IpAddress",
+        "not_ip_in" => "NotIpAddress",
+        _ => operator,
+    };
+    let pascal_case: String = operator
+        .split(':')
+        .map(|part| {
+            part.split('_')
+                .map(|word| {
+                    let mut chars = word.chars();
+                    match chars.next() {
+                        Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+                        None => String::new(),
+                    }
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join(":");
+    condition_operators().into_iter().find(|known| *known == operator || *known == pascal_case)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    // snake and pascal case spellings of an operator should be close to each other
+    let a: Vec<char> = a.to_lowercase().chars().filter(|c| *c != '_').collect();
+    let b: Vec<char> = b.to_lowercase().chars().filter(|c| *c != '_').collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.iter().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+fn closest<'a>(word: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let max_distance = (word.len() / 3).max(2);
+    candidates
+        .map(|candidate| (edit_distance(word, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+pub fn suggest_condition_key(key: &str) -> Option<&'static str> {
+    closest(key, global_condition_keys().into_iter())
+}
+
+pub fn suggest_operator(operator: &str) -> Option<&'static str> {
+    closest(operator, condition_operators().into_iter().chain(["ip_in", "not_ip_in"]))
+}
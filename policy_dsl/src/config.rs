@@ -0,0 +1,269 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use toml_edit::DocumentMut;
+
+use crate::policy::Key;
+
+// Settings read from the `[package.metadata.policy_macros]` table of the
+// annotated crate's Cargo.toml, e.g.
+//
+// [package.metadata.policy_macros]
+// kms_key = "arn:aws:kms:us-east-1:000000000000:key/1234abcd-12ab-34cd-56ef-1234567890ab"
+// policy_size_limit = 8192
+// max_stage_length = 16
+// managed_policies = ["arn:aws:iam::aws:policy/AWSXRayDaemonWriteAccess"]
+// cedar_principal = "claims"
+// widen_variables = ["user_id"]
+// allow_sk_filters = true
+//
+// [package.metadata.policy_macros.arn_templates]
+// table = "arn:${partition}:dynamodb:${region}:${account}:table/${stage}-{name}"
+//
+// [package.metadata.policy_macros.variables]
+// "claims.sub" = "${aws:PrincipalTag/sub}"
+//
+// [package.metadata.policy_macros.tables]
+// "state.user_table_name" = "Users"
+// Messages = { pk = "ChannelId", sk = "SentAt" }
+#[derive(Debug, Clone, Default)]
+pub struct PolicyConfig {
+    // policy `$variable` path -> IAM policy variable it compiles to
+    pub variables: BTreeMap<String, String>,
+    // `$variable` paths without an IAM policy variable that statements may widen
+    // to a wildcard, any other unmapped variable in one is an error
+    pub widen_variables: BTreeSet<String>,
+    // IAM can't restrict sort keys, so `$sk` filters are left out of the IAM
+    // document. They're an error unless the crate accepts that they're only
+    // checked against its DynamoDB calls at compile time.
+    pub allow_sk_filters: bool,
+    // customer managed key that secrets and SecureString parameters are encrypted
+    // with, AWS managed keys don't need a kms:Decrypt grant
+    pub kms_key: Option<String>,
+    // characters the generated document may take up, lower than IAM's limit when
+    // the role has other inline policies sharing it
+    pub policy_size_limit: Option<usize>,
+    // the longest `${stage}` the policy is deployed with, its size is checked
+    // with every placeholder at its longest
+    pub max_stage_length: Option<usize>,
+    // managed policies the deployment attaches to each function's role next to
+    // AWSLambdaBasicExecutionRole, and how many the account allows per role when
+    // its quota was raised
+    pub managed_policies: Vec<String>,
+    pub managed_policy_limit: Option<usize>,
+    // resource kind -> ARN template, `{name}` is replaced by the resource's name
+    // and `${partition}`, `${region}`, `${account}` and `${stage}` are filled in
+    // when the policy is deployed
+    pub arn_templates: BTreeMap<String, String>,
+    // handler parameter standing for the Cedar principal, `$claims.sub` compiles
+    // to `principal.sub` when this is "claims"
+    pub cedar_principal: Option<String>,
+    // expression a handler reads a table name from -> the table it names, for
+    // the check of the handler's DynamoDB calls
+    pub tables: BTreeMap<String, String>,
+    // table name -> the names of its key attributes, for tables whose keys
+    // aren't called PK and SK
+    pub key_schemas: BTreeMap<String, KeySchema>,
+}
+
+// The attributes `$pk` and `$sk` stand for on a table
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeySchema {
+    pub pk: String,
+    pub sk: String,
+}
+
+impl Default for KeySchema {
+    fn default() -> Self {
+        KeySchema { pk: "PK".to_string(), sk: "SK".to_string() }
+    }
+}
+
+impl KeySchema {
+    pub fn attribute(&self, key: Key) -> &str {
+        match key {
+            Key::Pk => &self.pk,
+            Key::Sk => &self.sk,
+        }
+    }
+
+    // The key attribute called `attribute`, if it's one
+    pub fn key(&self, attribute: &str) -> Option<Key> {
+        match attribute {
+            _ if attribute == self.pk => Some(Key::Pk),
+            _ if attribute == self.sk => Some(Key::Sk),
+            _ => None,
+        }
+    }
+
+    pub fn attributes(&self) -> [&str; 2] {
+        [&self.pk, &self.sk]
+    }
+}
+
+// The kinds of resources an ARN template can be given for, indexes use the
+// template of their table
+const ARN_TEMPLATE_KINDS: [&str; 7] = ["table", "bucket", "queue", "topic", "event_bus", "secret", "parameter"];
+
+impl PolicyConfig {
+    pub fn load(crate_root: &Path) -> Result<Self, String> {
+        let cargo_toml_path = crate_root.join("Cargo.toml");
+        let text = fs::read_to_string(&cargo_toml_path)
+            .map_err(|err| format!("Could not read {}: {err}", cargo_toml_path.display()))?;
+        let doc = text
+            .parse::<DocumentMut>()
+            .map_err(|err| format!("Could not parse {}: {err}", cargo_toml_path.display()))?;
+        let mut config = PolicyConfig::default();
+        let Some(settings) = doc
+            .get("package")
+            .and_then(|package| package.get("metadata"))
+            .and_then(|metadata| metadata.get("policy_macros"))
+        else {
+            return Ok(config);
+        };
+        if let Some(kms_key) = settings.get("kms_key") {
+            let Some(kms_key) = kms_key.as_str() else {
+                return Err("policy_macros.kms_key must be a string".to_string());
+            };
+            config.kms_key = Some(kms_key.to_string());
+        }
+        if let Some(policy_size_limit) = settings.get("policy_size_limit") {
+            let Some(policy_size_limit) = policy_size_limit.as_integer().and_then(|limit| usize::try_from(limit).ok()) else {
+                return Err("policy_macros.policy_size_limit must be a positive integer".to_string());
+            };
+            config.policy_size_limit = Some(policy_size_limit);
+        }
+        if let Some(max_stage_length) = settings.get("max_stage_length") {
+            let Some(max_stage_length) = max_stage_length.as_integer().and_then(|length| usize::try_from(length).ok()) else {
+                return Err("policy_macros.max_stage_length must be a positive integer".to_string());
+            };
+            config.max_stage_length = Some(max_stage_length);
+        }
+        if let Some(managed_policies) = settings.get("managed_policies") {
+            let arns = managed_policies.as_array().and_then(|arns| arns.iter().map(|arn| arn.as_str()).collect::<Option<Vec<_>>>());
+            let Some(arns) = arns else {
+                return Err("policy_macros.managed_policies must be an array of strings".to_string());
+            };
+            config.managed_policies = arns.into_iter().map(str::to_string).collect();
+        }
+        if let Some(managed_policy_limit) = settings.get("managed_policy_limit") {
+            let Some(managed_policy_limit) = managed_policy_limit.as_integer().and_then(|limit| usize::try_from(limit).ok()) else {
+                return Err("policy_macros.managed_policy_limit must be a positive integer".to_string());
+            };
+            config.managed_policy_limit = Some(managed_policy_limit);
+        }
+        if let Some(cedar_principal) = settings.get("cedar_principal") {
+            let Some(cedar_principal) = cedar_principal.as_str() else {
+                return Err("policy_macros.cedar_principal must be a string".to_string());
+            };
+            config.cedar_principal = Some(cedar_principal.to_string());
+        }
+        if let Some(variables) = settings.get("variables").and_then(|item| item.as_table_like()) {
+            for (name, value) in variables.iter() {
+                let Some(iam_variable) = value.as_str() else {
+                    return Err(format!("policy_macros.variables.{name} must be a string"));
+                };
+                config.variables.insert(name.to_string(), iam_variable.to_string());
+            }
+        }
+        if let Some(allow_sk_filters) = settings.get("allow_sk_filters") {
+            let Some(allow_sk_filters) = allow_sk_filters.as_bool() else {
+                return Err("policy_macros.allow_sk_filters must be a boolean".to_string());
+            };
+            config.allow_sk_filters = allow_sk_filters;
+        }
+        if let Some(widen_variables) = settings.get("widen_variables") {
+            let names = widen_variables.as_array().and_then(|names| names.iter().map(|name| name.as_str()).collect::<Option<Vec<_>>>());
+            let Some(names) = names else {
+                return Err("policy_macros.widen_variables must be an array of strings".to_string());
+            };
+            config.widen_variables.extend(names.into_iter().map(|name| name.trim_start_matches('$').to_string()));
+        }
+        // a string maps an expression to the table it names, a table gives the
+        // key attributes of the table it's named after
+        if let Some(tables) = settings.get("tables").and_then(|item| item.as_table_like()) {
+            for (name, value) in tables.iter() {
+                if let Some(table) = value.as_str() {
+                    config.tables.insert(name.to_string(), table.to_string());
+                    continue;
+                }
+                let Some(keys) = value.as_table_like() else {
+                    return Err(format!(
+                        "policy_macros.tables.\"{name}\" must be a table name or a table like {{ pk = \"PK\", sk = \"SK\" }}"
+                    ));
+                };
+                let mut key_schema = KeySchema::default();
+                for (key, attribute) in keys.iter() {
+                    let Some(attribute) = attribute.as_str() else {
+                        return Err(format!("policy_macros.tables.\"{name}\".{key} must be a string"));
+                    };
+                    match key {
+                        "pk" => key_schema.pk = attribute.to_string(),
+                        "sk" => key_schema.sk = attribute.to_string(),
+                        _ => return Err(format!("policy_macros.tables.\"{name}\".{key} is not a key, expected 'pk' or 'sk'")),
+                    }
+                }
+                config.key_schemas.insert(name.to_string(), key_schema);
+            }
+        }
+        if let Some(arn_templates) = settings.get("arn_templates").and_then(|item| item.as_table_like()) {
+            for (kind, value) in arn_templates.iter() {
+                if !ARN_TEMPLATE_KINDS.contains(&kind) {
+                    return Err(format!(
+                        "policy_macros.arn_templates.{kind} is not a resource kind, expected one of [{}]",
+                        ARN_TEMPLATE_KINDS.join(", ")
+                    ));
+                }
+                let Some(template) = value.as_str() else {
+                    return Err(format!("policy_macros.arn_templates.{kind} must be a string"));
+                };
+                if !template.contains("{name}") {
+                    return Err(format!("policy_macros.arn_templates.{kind} must contain '{{name}}'"));
+                }
+                config.arn_templates.insert(kind.to_string(), template.to_string());
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn key_schema(&self, table: &str) -> KeySchema {
+        self.key_schemas.get(table).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn load(metadata: &str) -> Result<PolicyConfig, String> {
+        let crate_root = env::temp_dir().join(format!("policy_dsl_config_{}_{}", std::process::id(), metadata.len()));
+        fs::create_dir_all(&crate_root).unwrap();
+        fs::write(crate_root.join("Cargo.toml"), format!("[package]\nname = \"app\"\n\n{metadata}")).unwrap();
+        let config = PolicyConfig::load(&crate_root);
+        fs::remove_dir_all(&crate_root).unwrap();
+        config
+    }
+
+    #[test]
+    fn tables_map_expressions_and_give_key_schemas() {
+        let config = load(
+            "[package.metadata.policy_macros.tables]\n\"state.user_table_name\" = \"Users\"\nMessages = { pk = \"ChannelId\" }\n",
+        )
+        .unwrap();
+        assert_eq!(config.tables["state.user_table_name"], "Users");
+        assert_eq!(config.key_schema("Users"), KeySchema::default());
+        let messages = config.key_schema("Messages");
+        assert_eq!(messages.attributes(), ["ChannelId", "SK"]);
+        assert_eq!(messages.key("ChannelId"), Some(Key::Pk));
+        assert_eq!(messages.key("PK"), None);
+    }
+
+    #[test]
+    fn unknown_key_in_a_key_schema_is_an_error() {
+        let err = load("[package.metadata.policy_macros.tables]\nMessages = { partition = \"ChannelId\" }\n").unwrap_err();
+        assert!(err.contains("policy_macros.tables.\"Messages\".partition is not a key"), "{err}");
+    }
+}
@@ -0,0 +1,11 @@
+// The policy DSL policy_attr and define_policy! take: its AST, how it's parsed
+// and printed back, the checks that only depend on what a policy says, and the
+// crate settings it's compiled with. policy_macros compiles it into the
+// backends' documents, policy_runtime checks requests against it in tests.
+pub mod policy;
+mod parser;
+mod printer;
+pub mod bindings;
+pub mod catalog;
+pub mod config;
+pub mod optimizer;
@@ -0,0 +1,184 @@
+use crate::policy::{Filter, Key, PolicyAtom, StringExpr};
+
+// Drops every atom whose grants (or denials) are already implied by another atom
+// of the policy, e.g. `allow read on table "T" where key_equals $pk "USER#1"`
+// next to `allow read on table "T" where key_like $pk "USER#*"`. Runs before
+// any backend sees the policy, so it only relies on what the DSL means, not on
+// how a backend spells it.
+pub fn minimize(atoms: Vec<PolicyAtom>) -> Vec<PolicyAtom> {
+    let mut kept: Vec<PolicyAtom> = vec![];
+    for atom in atoms {
+        if kept.iter().any(|broader| covers(broader, &atom)) {
+            continue;
+        }
+        kept.retain(|narrower| !covers(&atom, narrower));
+        kept.push(atom);
+    }
+    kept
+}
+
+// Whether every request `narrow` matches is also matched by `broad`. Since both
+// atoms have the same effect, `narrow` adds nothing to the policy in that case.
+fn covers(broad: &PolicyAtom, narrow: &PolicyAtom) -> bool {
+    broad.effect == narrow.effect
+        && broad.action == narrow.action
+        && broad.resource == narrow.resource
+        && broad.conditions == narrow.conditions
+        && attributes_cover(&broad.attributes, &narrow.attributes)
+        && broad
+            .filters
+            .iter()
+            .filter(|filter| filter.key() != Some(Key::Pk))
+            .all(|filter| narrow.filters.contains(filter))
+        && partition_key_covers(partition_key(broad), partition_key(narrow))
+}
+
+fn partition_key(atom: &PolicyAtom) -> Option<&Filter> {
+    atom.filters.iter().find(|filter| filter.key() == Some(Key::Pk))
+}
+
+// An atom limited to some attributes matches fewer requests than one that isn't,
+// for both allow (can only touch those) and deny (only fires on those)
+fn attributes_cover<T: PartialEq>(broad: &Option<Vec<T>>, narrow: &Option<Vec<T>>) -> bool {
+    match (broad, narrow) {
+        (None, _) => true,
+        (Some(_), None) => false,
+        (Some(broad), Some(narrow)) => narrow.iter().all(|field| broad.contains(field)),
+    }
+}
+
+fn partition_key_covers(broad: Option<&Filter>, narrow: Option<&Filter>) -> bool {
+    match (broad, narrow) {
+        (None, _) => true,
+        (Some(_), None) => false,
+        (Some(broad), Some(narrow)) if broad == narrow => true,
+        (Some(Filter::KeyLike(_, pattern)), Some(narrow)) => {
+            // variables aren't known until the request is made, so only fully
+            // literal filters can be compared
+            let (Some(pattern), Some(text)) = (literal_text(pattern), literal_text(narrow.value())) else {
+                return false;
+            };
+            let pattern: Vec<char> = pattern.chars().collect();
+            let template: Vec<(char, bool)> = match narrow {
+                Filter::KeyLike(..) => text.chars().map(|c| (c, c == '*' || c == '?')).collect(),
+                _ => text.chars().map(|c| (c, false)).collect(),
+            };
+            pattern_covers(&pattern, &template)
+        }
+        _ => false,
+    }
+}
+
+fn literal_text(expr: &StringExpr) -> Option<String> {
+    match expr {
+        StringExpr::Literal(lit) => Some(lit.clone()),
+        StringExpr::Variable(_) => None,
+        StringExpr::Concat(left, right) => Some(literal_text(left)? + &literal_text(right)?),
+    }
+}
+
+// Whether the glob `pattern` matches every string `template` can stand for. The
+// template's characters are flagged when they are wildcards themselves: those can
+// only be absorbed by a '*' in the pattern ('?' also by a '?').
+pub fn pattern_covers(pattern: &[char], template: &[(char, bool)]) -> bool {
+    // matches[i][j]: pattern[i..] covers template[j..]
+    let mut matches = vec![vec![false; template.len() + 1]; pattern.len() + 1];
+    matches[pattern.len()][template.len()] = true;
+    for i in (0..pattern.len()).rev() {
+        for j in (0..=template.len()).rev() {
+            matches[i][j] = match (pattern[i], template.get(j)) {
+                ('*', next) => matches[i + 1][j] || (next.is_some() && matches[i][j + 1]),
+                (_, None) => false,
+                ('?', Some(&(c, wildcard))) => !(wildcard && c == '*') && matches[i + 1][j + 1],
+                (p, Some(&(c, wildcard))) => !wildcard && p == c && matches[i + 1][j + 1],
+            };
+        }
+    }
+    matches[0][0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::Policy;
+
+    // The atoms minimize keeps, as the DSL prints them
+    fn minimized(text: &str) -> Vec<String> {
+        let policy: Policy = syn::parse_str(text).unwrap();
+        minimize(policy.atoms().to_vec()).iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn literal_keys_are_dropped_under_patterns_covering_them() {
+        let broad = r#"allow read on table "T" where key_like $pk "USER#*""#;
+        let narrow = r#"allow read on table "T" where key_equals $pk "USER#1""#;
+        assert_eq!(minimized(&format!("{narrow} {broad}")), [broad]);
+        assert_eq!(minimized(&format!("{broad} {narrow}")), [broad]);
+        assert_eq!(minimized(&format!("{broad} {broad}")), [broad]);
+
+        let single = r#"allow read on table "T" where key_like $pk "USER#?""#;
+        assert_eq!(minimized(&format!("{single} {broad}")), [broad]);
+        // '?' stands for a single character, so it doesn't cover '*'
+        let any = r#"allow read on table "T" where key_like $pk "USER#1*""#;
+        assert_eq!(minimized(&format!("{single} {any}")), [single, any]);
+    }
+
+    #[test]
+    fn only_equal_or_broader_atoms_cover() {
+        let unfiltered = r#"allow read on table "T""#;
+        let sorted = r#"allow read on table "T" where key_equals $pk "USER#1" where key_like $sk "FRIEND#*""#;
+        let keyed = r#"allow read on table "T" where key_equals $pk "USER#1""#;
+        assert_eq!(minimized(&format!("{sorted} {keyed} {unfiltered}")), [unfiltered]);
+        assert_eq!(minimized(&format!("{sorted} {keyed}")), [keyed]);
+
+        // a different action, resource, effect or condition is a different grant
+        let kept = [
+            r#"allow read on table "T""#,
+            r#"allow get on table "T""#,
+            r#"allow read on table "U""#,
+            r#"deny read on table "T""#,
+            r#"allow read on table "T" when aws:SourceIp IpAddress "10.0.0.0/8""#,
+        ];
+        assert_eq!(minimized(&kept.join(" ")), kept);
+    }
+
+    #[test]
+    fn variables_are_never_compared() {
+        let pattern = r#"allow read on table "T" where key_like $pk "USER#*""#;
+        let variable = r#"allow read on table "T" where key_equals $pk concat("USER#", $user_id)"#;
+        assert_eq!(minimized(&format!("{pattern} {variable}")), [pattern, variable]);
+        let same = r#"allow read on table "T" where key_equals $pk $user_id"#;
+        assert_eq!(minimized(&format!("{same} {same}")), [same]);
+    }
+
+    #[test]
+    fn fewer_attributes_match_fewer_requests() {
+        let wide = r#"allow read on table "T" with attributes ["email" "name"]"#;
+        let narrow = r#"allow read on table "T" with attributes ["email"]"#;
+        let all = r#"allow read on table "T""#;
+        assert_eq!(minimized(&format!("{narrow} {wide}")), [wide]);
+        assert_eq!(minimized(&format!("{wide} {all}")), [all]);
+        let deny_some = r#"deny update on table "T" with attributes ["role"]"#;
+        let deny_all = r#"deny update on table "T""#;
+        assert_eq!(minimized(&format!("{deny_some} {deny_all}")), [deny_all]);
+    }
+
+    #[test]
+    fn patterns_cover_templates() {
+        let covers = |pattern: &str, template: &str| {
+            let pattern: Vec<char> = pattern.chars().collect();
+            let template: Vec<(char, bool)> = template.chars().map(|c| (c, c == '*' || c == '?')).collect();
+            pattern_covers(&pattern, &template)
+        };
+        assert!(covers("USER#*", "USER#1"));
+        assert!(covers("USER#*", "USER#*"));
+        assert!(covers("*", ""));
+        assert!(covers("A?C", "ABC"));
+        assert!(covers("A?C", "A?C"));
+        assert!(covers("A*", "A?*"));
+        assert!(!covers("A?", "A*"));
+        assert!(!covers("USER#1", "USER#?"));
+        assert!(!covers("USER#*", "POST#1"));
+        assert!(!covers("", "A"));
+    }
+}
@@ -0,0 +1,579 @@
+use proc_macro2::Span;
+use syn::{parse::{Parse, ParseStream}, Ident, Token, Lit, LitStr, parenthesized, bracketed, punctuated::Punctuated};
+use crate::bindings;
+use crate::catalog;
+use crate::policy::*;
+
+fn next_is_string_value(input: ParseStream, expected: &str) -> bool {
+    if !input.peek(Ident) {
+        return false;
+    }
+    let fork = input.fork();
+    match fork.parse::<Ident>() {
+        Ok(lit) => lit.to_string().as_str() == expected,
+        Err(_) => false,
+    }
+}
+
+fn parse_and_ignore(input: ParseStream, word: &str) -> syn::Result<()> {
+    if next_is_string_value(input, word) {
+        input.parse::<Ident>()?;
+        Ok(())
+    } else {
+        Err(input.error(format!("expected '{word}'").as_str()))
+    }
+}
+
+impl Parse for Effect {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if next_is_string_value(input, "allow") {
+            input.parse::<Ident>()?;
+            Ok(Effect::Allow)
+        } else if next_is_string_value(input, "deny") {
+            input.parse::<Ident>()?;
+            Ok(Effect::Deny)
+        } else {
+            Err(input.error("expected 'allow' or 'deny'"))
+        }
+    }
+}
+
+impl Parse for Action {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let expected: Vec<String> = Action::ALL.iter().map(|action| format!("'{}'", action.keyword())).collect();
+        if !input.peek(Ident) {
+            return Err(input.error(format!("expected an action, one of [{}]", expected.join(", "))));
+        }
+        let action_ident: Ident = input.parse()?;
+        let action_str = action_ident.to_string();
+        match Action::ALL.iter().find(|action| action.keyword() == action_str) {
+            Some(action) => Ok(*action),
+            None => Err(syn::Error::new(
+                action_ident.span(),
+                format!("unexpected action: '{action_str}', expected one of [{}]", expected.join(", ")),
+            )),
+        }
+    }
+}
+
+impl Parse for Resource {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let kind = input.fork().parse::<Ident>().map(|ident| ident.to_string()).unwrap_or_default();
+        if kind == "index" {
+            input.parse::<Ident>()?;
+            let index_name: LitStr = input.parse()?;
+            parse_and_ignore(input, "of")?;
+            parse_and_ignore(input, "table")?;
+            let table_name: LitStr = input.parse()?;
+            return Ok(Resource::Index {
+                index: index_name.value(),
+                table: table_name.value(),
+            });
+        }
+        let resource: fn(String) -> Resource = match kind.as_str() {
+            "table" => Resource::Table,
+            "bucket" => Resource::Bucket,
+            "queue" => Resource::Queue,
+            "topic" => Resource::Topic,
+            "event_bus" => Resource::EventBus,
+            "secret" => Resource::Secret,
+            "parameter" => Resource::Parameter,
+            _ => {
+                return Err(input.error(
+                    "expected one of ['table', 'index', 'bucket', 'queue', 'topic', 'event_bus', 'secret', 'parameter']",
+                ))
+            }
+        };
+        input.parse::<Ident>()?;
+        let name: LitStr = input.parse()?;
+        Ok(resource(name.value()))
+    }
+}
+
+impl Parse for Key {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        input.parse::<Token![$]>()?;
+        let key_ident = input.parse::<Ident>()?;
+        let key_str = key_ident.to_string();
+        match key_str.as_str() {
+            "pk" => Ok(Key::Pk),
+            "sk" => Ok(Key::Sk),
+            _ => Err(syn::Error::new(key_ident.span(), format!("unknown key encountered '${key_str}', expected '$pk' or '$sk'")))
+        }
+    }
+}
+
+impl Parse for Var {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        input.parse::<Token![$]>()?;
+        let idents: Punctuated<Ident, Token![.]> =
+            Punctuated::parse_separated_nonempty(input)?;
+        let span = idents.first().unwrap().span();
+        let var_path: Vec<String> = idents.into_iter().map(|ident| ident.to_string()).collect();
+        Ok(Var { path: var_path, span })
+    }
+}
+
+impl Parse for StringExpr {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if input.peek(LitStr) {
+            let lit: LitStr = input.parse()?;
+            return Ok(StringExpr::Literal(lit.value()));
+        }
+
+        if input.peek(Token![$]) {
+            let var = input.parse::<Var>()?;
+            return Ok(StringExpr::Variable(var));
+        }
+
+        if next_is_string_value(input, "concat") {
+            input.parse::<Ident>()?;
+            let content;
+            parenthesized!(content in input);
+            let left = content.parse::<StringExpr>()?;
+            content.parse::<Token![,]>()?;
+            let right = content.parse::<StringExpr>()?;
+            return Ok(StringExpr::Concat(Box::new(left), Box::new(right)));
+        }
+
+        Err(input.error("invalid string expression, expected a string literal, a '$variable' or 'concat(..)'"))
+    }
+}
+
+impl Parse for Filter {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let filter_ident = input.parse::<Ident>()?;
+        match filter_ident.to_string().as_str() {
+            "key_equals" => {
+                let key = input.parse::<Key>()?;
+                let str_expr = input.parse::<StringExpr>()?;
+                Ok(Filter::KeyEquals(key, str_expr))
+            }
+            "key_like" => {
+                let key = input.parse::<Key>()?;
+                let str_expr = input.parse::<StringExpr>()?;
+                Ok(Filter::KeyLike(key, str_expr))
+            }
+            "prefix" => {
+                let str_expr = input.parse::<StringExpr>()?;
+                Ok(Filter::Prefix(str_expr))
+            }
+            _ => {
+                Err(syn::Error::new(filter_ident.span(), "expected one of ['key_equals', 'key_like', 'prefix']"))
+            }
+        }
+    }
+}
+
+impl Parse for Field {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let lit = input.parse::<LitStr>()?;
+        Ok(Field(lit.value()))
+    }
+}
+
+// `aws:SourceIp`, `aws:PrincipalTag/team`, or a string literal for keys that
+// aren't valid tokens
+fn parse_condition_key(input: ParseStream) -> syn::Result<(String, Span)> {
+    if input.peek(LitStr) {
+        let lit: LitStr = input.parse()?;
+        return Ok((lit.value(), lit.span()));
+    }
+    let service: Ident = input.parse()?;
+    input.parse::<Token![:]>()?;
+    let name: Ident = input.parse()?;
+    let mut key = format!("{service}:{name}");
+    if input.peek(Token![/]) {
+        input.parse::<Token![/]>()?;
+        let tag: Ident = input.parse()?;
+        key = format!("{key}/{tag}");
+    }
+    Ok((key, service.span()))
+}
+
+fn parse_condition_value(input: ParseStream) -> syn::Result<String> {
+    match input.parse::<Lit>()? {
+        Lit::Str(lit) => Ok(lit.value()),
+        Lit::Bool(lit) => Ok(lit.value.to_string()),
+        Lit::Int(lit) => Ok(lit.base10_digits().to_string()),
+        Lit::Float(lit) => Ok(lit.base10_digits().to_string()),
+        lit => Err(syn::Error::new(lit.span(), "expected a string, number or boolean")),
+    }
+}
+
+impl Parse for Condition {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let (key, key_span) = parse_condition_key(input)?;
+        if !catalog::is_condition_key(&key) {
+            let message = match catalog::suggest_condition_key(&key) {
+                Some(suggestion) => format!("unknown condition key '{key}', did you mean '{suggestion}'?"),
+                None => format!("unknown condition key '{key}'"),
+            };
+            return Err(syn::Error::new(key_span, message));
+        }
+
+        let operator_ident: Ident = input.parse()?;
+        let mut operator_str = operator_ident.to_string();
+        if input.peek(Token![:]) {
+            input.parse::<Token![:]>()?;
+            operator_str = format!("{operator_str}:{}", input.parse::<Ident>()?);
+        }
+        let Some(operator) = catalog::resolve_operator(&operator_str) else {
+            let message = match catalog::suggest_operator(&operator_str) {
+                Some(suggestion) => format!("unknown condition operator '{operator_str}', did you mean '{suggestion}'?"),
+                None => format!("unknown condition operator '{operator_str}'"),
+            };
+            return Err(syn::Error::new(operator_ident.span(), message));
+        };
+
+        let mut values = Vec::new();
+        if input.peek(syn::token::Bracket) {
+            let content;
+            bracketed!(content in input);
+            while !content.is_empty() {
+                values.push(parse_condition_value(&content)?);
+            }
+        } else {
+            values.push(parse_condition_value(input)?);
+        }
+        Ok(Condition {
+            key,
+            operator: operator.to_string(),
+            values,
+        })
+    }
+}
+
+// `uses` when the atom is in a policy_attr, the only place a `use` can follow it
+fn at_item_boundary(input: ParseStream, uses: bool) -> bool {
+    input.is_empty()
+        || (uses && input.peek(Token![use]))
+        || next_is_string_value(input, "allow")
+        || next_is_string_value(input, "deny")
+}
+
+impl Parse for PolicyAtom {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        PolicyAtom::parse_item(input, false)
+    }
+}
+
+impl PolicyAtom {
+    fn parse_item(input: ParseStream, uses: bool) -> syn::Result<Self> {
+        let effect = input.parse::<Effect>()?;
+        let action_span = input.span();
+        let action = input.parse::<Action>()?;
+        parse_and_ignore(input, "on")?;
+        let resource = input.parse::<Resource>()?;
+        if !resource.supports_action(action) {
+            let supported: Vec<String> = Action::ALL
+                .iter()
+                .filter(|action| resource.supports_action(**action))
+                .map(|action| format!("'{}'", action.keyword()))
+                .collect();
+            return Err(syn::Error::new(
+                action_span,
+                format!(
+                    "'{}' is not supported on {} resources, expected one of [{}]",
+                    action.keyword(),
+                    resource.kind(),
+                    supported.join(", ")
+                ),
+            ));
+        }
+        let mut filters = Vec::new();
+        while input.peek(Token![where]) {
+            input.parse::<Token![where]>()?;
+            let filter_span = input.span();
+            let filter = input.parse::<Filter>()?;
+            if !resource.supports_filter(&filter) {
+                return Err(syn::Error::new(
+                    filter_span,
+                    format!("this filter is not supported on {} resources", resource.kind()),
+                ));
+            }
+            // IAM can only OR LeadingKeys patterns and prefixes together, never intersect them
+            if filter.key() == Some(Key::Pk) && filters.iter().any(|f: &Filter| f.key() == Some(Key::Pk)) {
+                return Err(syn::Error::new(filter_span, "only one filter on '$pk' is allowed per statement"));
+            }
+            if matches!(filter, Filter::Prefix(_)) && filters.iter().any(|f| matches!(f, Filter::Prefix(_))) {
+                return Err(syn::Error::new(filter_span, "only one 'prefix' filter is allowed per statement"));
+            }
+            // a Scan has no leading keys, so IAM would let it read every partition anyway
+            if action == Action::Scan && filter.key() == Some(Key::Pk) {
+                return Err(syn::Error::new(
+                    action_span,
+                    "'scan' can't be restricted by a '$pk' filter, use 'query' instead",
+                ));
+            }
+            filters.push(filter);
+        }
+        let mut conditions = Vec::new();
+        while next_is_string_value(input, "when") {
+            input.parse::<Ident>()?;
+            conditions.push(input.parse::<Condition>()?);
+        }
+        let with_attributes = next_is_string_value(input, "with");
+        let fields = if with_attributes {
+            let with_ident = input.parse::<Ident>()?;
+            if !resource.supports_attributes() {
+                return Err(syn::Error::new(
+                    with_ident.span(),
+                    format!("'with attributes' is not supported on {} resources", resource.kind()),
+                ));
+            }
+            parse_and_ignore(input, "attributes")?;
+            let content;
+            bracketed!(content in input);
+            let mut fields = Vec::new();
+            while !content.is_empty() {
+                fields.push(content.parse::<Field>()?);
+            }
+            Some(fields)
+        } else {
+            None
+        };
+
+        // The atom ends at the next item or the end of the input, anything else
+        // is a mistake in one of the optional clauses above
+        if !at_item_boundary(input, uses) {
+            let mut expected = vec![];
+            if conditions.is_empty() && !with_attributes {
+                expected.push("'where'");
+            }
+            if !with_attributes {
+                expected.push("'when'");
+                if resource.supports_attributes() {
+                    expected.push("'with attributes'");
+                }
+            }
+            expected.extend(["'allow'", "'deny'"]);
+            if uses {
+                expected.push("'use'");
+            }
+            return Err(input.error(format!("unexpected token, expected one of [{}]", expected.join(", "))));
+        }
+
+        Ok(PolicyAtom {
+            effect,
+            action,
+            resource,
+            filters,
+            conditions,
+            attributes: fields
+        })
+    }
+}
+
+impl Parse for Policy {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if input.is_empty() {
+            return Err(input.error("expected a policy, e.g. 'allow read on table \"Users\"'"));
+        }
+        let mut policy_atoms = vec![];
+        while !input.is_empty() {
+            policy_atoms.push(input.parse::<PolicyAtom>()?);
+        }
+        Ok(Policy::from_atoms(policy_atoms))
+    }
+}
+
+impl Parse for PolicyUse {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        input.parse::<Token![use]>()?;
+        let path = input.call(syn::Path::parse_mod_style)?;
+        if let Some(colon) = path.leading_colon {
+            return Err(syn::Error::new(colon.spans[0], "expected a policy defined in this crate, e.g. 'use util::read_profile'"));
+        }
+        let mut module: Vec<String> = path.segments.iter().map(|segment| segment.ident.to_string()).collect();
+        // the path is read from the crate root either way
+        if module.first().is_some_and(|segment| segment == "crate") {
+            module.remove(0);
+        }
+        let name = path.segments.last().unwrap().ident.clone();
+        module.pop();
+        let mut args = vec![];
+        if input.peek(syn::token::Paren) {
+            let content;
+            parenthesized!(content in input);
+            args = Punctuated::<StringExpr, Token![,]>::parse_terminated(&content)?.into_iter().collect();
+        }
+        if !at_item_boundary(input, true) {
+            return Err(input.error("unexpected token, expected one of ['allow', 'deny', 'use']"));
+        }
+        Ok(PolicyUse {
+            module,
+            name: name.to_string(),
+            args,
+            span: name.span(),
+        })
+    }
+}
+
+impl Parse for PolicyAttr {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if input.is_empty() {
+            return Err(input.error("expected a policy, e.g. 'allow read on table \"Users\"' or 'use read_profile'"));
+        }
+        let mut items = vec![];
+        while !input.is_empty() {
+            if input.peek(Token![use]) {
+                items.push(PolicyItem::Use(input.parse::<PolicyUse>()?));
+            } else {
+                items.push(PolicyItem::Atom(PolicyAtom::parse_item(input, true)?));
+            }
+        }
+        Ok(PolicyAttr { items })
+    }
+}
+
+impl Parse for PolicyDefinition {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name: Ident = input.parse()?;
+        let mut params = vec![];
+        if input.peek(syn::token::Paren) {
+            let content;
+            parenthesized!(content in input);
+            params = Punctuated::<Ident, Token![,]>::parse_terminated(&content)?
+                .into_iter()
+                .map(|param| param.to_string())
+                .collect();
+        }
+        input.parse::<Token![=]>()?;
+        let policy = input.parse::<Policy>()?;
+        // the definition only knows its own parameters, the handler's are bound
+        // through the arguments of each `use`
+        bindings::check_bound(&policy, &name.to_string(), &params)?;
+        Ok(PolicyDefinition {
+            name: name.to_string(),
+            params,
+            policy,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(text: &str) -> String {
+        match syn::parse_str::<Policy>(text) {
+            Ok(policy) => panic!("'{text}' parsed as {policy:?}"),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_every_clause() {
+        let policy: Policy = syn::parse_str(
+            r#"allow read on table "Users" where key_equals $pk concat("USER#", $claims.sub) where key_like $sk "FRIEND#*"
+               when aws:SourceIp ip_in ["10.0.0.0/8" "192.168.0.0/16"] with attributes ["email"]
+               deny delete on bucket "uploads" where prefix "admin/""#,
+        )
+        .unwrap();
+        let [read, delete] = policy.atoms() else {
+            panic!("expected two atoms, got {policy:?}");
+        };
+        assert_eq!(read.effect, Effect::Allow);
+        assert_eq!(read.action, Action::Read);
+        assert_eq!(read.resource, Resource::Table("Users".to_string()));
+        assert_eq!(read.filters.len(), 2);
+        assert_eq!(read.conditions[0].operator, "IpAddress");
+        assert_eq!(read.conditions[0].values, ["10.0.0.0/8", "192.168.0.0/16"]);
+        assert_eq!(read.attributes, Some(vec![Field("email".to_string())]));
+        assert_eq!(delete.filters, [Filter::Prefix(StringExpr::Literal("admin/".to_string()))]);
+    }
+
+    #[test]
+    fn empty_policy() {
+        assert_eq!(error(""), "unexpected end of input, expected a policy, e.g. 'allow read on table \"Users\"'");
+    }
+
+    #[test]
+    fn unknown_effect_and_action() {
+        assert_eq!(error(r#"permit read on table "Users""#), "expected 'allow' or 'deny'");
+        assert!(error(r#"allow fly on table "Users""#).starts_with("unexpected action: 'fly', expected one of ['create', "));
+    }
+
+    #[test]
+    fn unknown_resource_and_key() {
+        assert!(error(r#"allow read on database "Users""#).starts_with("expected one of ['table', 'index', "));
+        assert_eq!(
+            error(r#"allow read on table "Users" where key_equals $xk "1""#),
+            "unknown key encountered '$xk', expected '$pk' or '$sk'"
+        );
+    }
+
+    #[test]
+    fn action_and_filter_have_to_fit_the_resource() {
+        assert!(error(r#"allow send on table "Users""#).starts_with("'send' is not supported on table resources"));
+        assert_eq!(
+            error(r#"allow send on queue "jobs" where prefix "a""#),
+            "this filter is not supported on queue resources"
+        );
+        assert_eq!(
+            error(r#"allow read on secret "db" with attributes ["a"]"#),
+            "'with attributes' is not supported on secret resources"
+        );
+    }
+
+    #[test]
+    fn filters_iam_cant_express() {
+        assert_eq!(
+            error(r#"allow read on table "Users" where key_equals $pk "a" where key_like $pk "b*""#),
+            "only one filter on '$pk' is allowed per statement"
+        );
+        assert_eq!(
+            error(r#"allow scan on table "Users" where key_equals $pk "a""#),
+            "'scan' can't be restricted by a '$pk' filter, use 'query' instead"
+        );
+    }
+
+    #[test]
+    fn unknown_condition_key_and_operator() {
+        assert_eq!(
+            error(r#"allow read on table "Users" when aws:SourceIP ip_in "10.0.0.0/8""#),
+            "unknown condition key 'aws:SourceIP', did you mean 'aws:SourceIp'?"
+        );
+        assert!(error(r#"allow read on table "Users" when aws:SourceIp string_equalz "a""#)
+            .starts_with("unknown condition operator 'string_equalz'"));
+    }
+
+    #[test]
+    fn trailing_tokens() {
+        assert_eq!(
+            error(r#"allow read on table "Users" with attributes ["a"] where"#),
+            "unexpected token, expected one of ['allow', 'deny']"
+        );
+        assert_eq!(
+            error(r#"allow read on table "Users" oops"#),
+            "unexpected token, expected one of ['where', 'when', 'with attributes', 'allow', 'deny']"
+        );
+        // only a policy_attr can hold `use`s
+        assert_eq!(
+            error(r#"allow read on table "Users" use read_profile"#),
+            "unexpected token, expected one of ['where', 'when', 'with attributes', 'allow', 'deny']"
+        );
+        let err = syn::parse_str::<PolicyAttr>(r#"allow read on table "Users" oops"#).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unexpected token, expected one of ['where', 'when', 'with attributes', 'allow', 'deny', 'use']"
+        );
+    }
+
+    #[test]
+    fn uses_name_their_module_from_the_crate_root() {
+        let attr: PolicyAttr = syn::parse_str(r#"use read_profile($user_id) use crate::policies::chat::send allow read on table "Users""#).unwrap();
+        let [PolicyItem::Use(bare), PolicyItem::Use(qualified), PolicyItem::Atom(_)] = attr.items.as_slice() else {
+            panic!("expected two uses and an atom, got {:?}", attr.items);
+        };
+        assert!(bare.module.is_empty());
+        assert_eq!(bare.name, "read_profile");
+        assert_eq!(bare.args.len(), 1);
+        assert_eq!(qualified.module, ["policies", "chat"]);
+        assert_eq!(qualified.name, "send");
+        assert!(qualified.args.is_empty());
+        let err = syn::parse_str::<PolicyAttr>("use ::other::read_profile").unwrap_err();
+        assert_eq!(err.to_string(), "expected a policy defined in this crate, e.g. 'use util::read_profile'");
+    }
+}
@@ -0,0 +1,310 @@
+use proc_macro2::Span;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum Effect {
+    #[default]
+    Allow,
+    Deny,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    // CRUD aliases, each standing for a set of the verbs below
+    Create, Read, Update, Delete,
+    Get, Query, Scan, BatchGet, Put, BatchWrite, TransactGet, TransactWrite,
+    Send, Receive, Publish, PutEvents,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Resource {
+    Table(String),
+    Index { index: String, table: String },
+    Bucket(String),
+    Queue(String),
+    Topic(String),
+    EventBus(String),
+    Secret(String),
+    Parameter(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    Pk,
+    Sk,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Var {
+    pub path: Vec<String>,
+    // only meaningful while the macro is expanding, used to point errors at the variable
+    #[serde(skip, default = "Span::call_site")]
+    pub span: Span,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum StringExpr {
+    Literal(String),
+    Variable(Var),
+    Concat(Box<StringExpr>, Box<StringExpr>)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Filter {
+    KeyEquals(Key, StringExpr),
+    KeyLike(Key, StringExpr),
+    Prefix(StringExpr),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Field(pub String);
+
+// A `when` clause, compiled straight into the statement's Condition block
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Condition {
+    pub key: String,
+    pub operator: String,
+    pub values: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PolicyAtom {
+    #[serde(default)]
+    pub effect: Effect,
+    pub action: Action,
+    pub resource: Resource,
+    pub filters: Vec<Filter>,
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    pub attributes: Option<Vec<Field>>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Policy {
+    Atom(PolicyAtom),
+    Composite(Vec<PolicyAtom>)
+}
+
+// `use name(args..)` in a policy_attr, standing for the atoms of the
+// define_policy! definition called `name` with its parameters bound to `args`.
+// `use util::name(args..)` names the module it's defined in, from the crate root.
+#[derive(Debug, Clone)]
+pub struct PolicyUse {
+    pub module: Vec<String>,
+    pub name: String,
+    pub args: Vec<StringExpr>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum PolicyItem {
+    Atom(PolicyAtom),
+    Use(PolicyUse),
+}
+
+// The arguments of policy_attr, before `use` items are resolved
+#[derive(Debug, Clone)]
+pub struct PolicyAttr {
+    pub items: Vec<PolicyItem>,
+}
+
+// `name(params..) = policy`, the input of define_policy!
+#[derive(Debug, Clone)]
+pub struct PolicyDefinition {
+    pub name: String,
+    pub params: Vec<String>,
+    pub policy: Policy,
+}
+
+impl Action {
+    pub const ALL: [Action; 16] = [
+        Action::Create, Action::Read, Action::Update, Action::Delete,
+        Action::Get, Action::Query, Action::Scan, Action::BatchGet, Action::Put, Action::BatchWrite,
+        Action::TransactGet, Action::TransactWrite,
+        Action::Send, Action::Receive, Action::Publish, Action::PutEvents,
+    ];
+
+    // The DSL verb for the action
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            Action::Create => "create",
+            Action::Read => "read",
+            Action::Update => "update",
+            Action::Delete => "delete",
+            Action::Get => "get",
+            Action::Query => "query",
+            Action::Scan => "scan",
+            Action::BatchGet => "batch_get",
+            Action::Put => "put",
+            Action::BatchWrite => "batch_write",
+            Action::TransactGet => "transact_get",
+            Action::TransactWrite => "transact_write",
+            Action::Send => "send",
+            Action::Receive => "receive",
+            Action::Publish => "publish",
+            Action::PutEvents => "put_events",
+        }
+    }
+
+    // Whether the action only reads items, as opposed to writing them
+    pub fn is_read(&self) -> bool {
+        matches!(
+            self,
+            Action::Read | Action::Get | Action::Query | Action::Scan | Action::BatchGet | Action::TransactGet
+        )
+    }
+
+    // The DynamoDB operations the action allows on a table, or on one of its
+    // indexes, which can only be read through Query and Scan
+    pub fn dynamodb_operations(&self, on_index: bool) -> &'static [&'static str] {
+        if on_index {
+            return match self {
+                Action::Read | Action::Query => &["Query"],
+                Action::Scan => &["Scan"],
+                // rejected by the parser
+                _ => &[],
+            };
+        }
+        match self {
+            // Scan is left out of read on purpose: LeadingKeys can't restrict it
+            Action::Read => &["GetItem", "BatchGetItem", "Query"],
+            Action::Create | Action::Put => &["PutItem"],
+            Action::Update => &["UpdateItem"],
+            Action::Delete => &["DeleteItem"],
+            Action::Get => &["GetItem"],
+            Action::Query => &["Query"],
+            Action::Scan => &["Scan"],
+            Action::BatchGet => &["BatchGetItem"],
+            Action::BatchWrite => &["BatchWriteItem"],
+            // transactions have no IAM action of their own, each item in them is
+            // authorized as the equivalent single-item request
+            Action::TransactGet => &["GetItem"],
+            Action::TransactWrite => &["PutItem", "UpdateItem", "DeleteItem", "ConditionCheckItem"],
+            // rejected by the parser
+            Action::Send | Action::Receive | Action::Publish | Action::PutEvents => &[],
+        }
+    }
+}
+
+impl Resource {
+    // The DSL keyword for the kind of resource
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Resource::Table(_) => "table",
+            Resource::Index { .. } => "index",
+            Resource::Bucket(_) => "bucket",
+            Resource::Queue(_) => "queue",
+            Resource::Topic(_) => "topic",
+            Resource::EventBus(_) => "event_bus",
+            Resource::Secret(_) => "secret",
+            Resource::Parameter(_) => "parameter",
+        }
+    }
+
+    pub fn supports_action(&self, action: Action) -> bool {
+        match self {
+            Resource::Table(_) => !matches!(
+                action,
+                Action::Send | Action::Receive | Action::Publish | Action::PutEvents
+            ),
+            // indexes are maintained by DynamoDB, they can only be read through Query and Scan
+            Resource::Index { .. } => matches!(action, Action::Read | Action::Query | Action::Scan),
+            Resource::Bucket(_) => matches!(
+                action,
+                Action::Create | Action::Read | Action::Update | Action::Delete | Action::Get | Action::Put
+            ),
+            Resource::Queue(_) => matches!(action, Action::Send | Action::Receive),
+            Resource::Topic(_) => action == Action::Publish,
+            Resource::EventBus(_) => action == Action::PutEvents,
+            Resource::Secret(_) | Resource::Parameter(_) => action == Action::Read,
+        }
+    }
+
+    pub fn supports_filter(&self, filter: &Filter) -> bool {
+        match self {
+            Resource::Table(_) | Resource::Index { .. } => filter.key().is_some(),
+            Resource::Bucket(_) => matches!(filter, Filter::Prefix(_)),
+            Resource::Queue(_)
+            | Resource::Topic(_)
+            | Resource::EventBus(_)
+            | Resource::Secret(_)
+            | Resource::Parameter(_) => false,
+        }
+    }
+
+    pub fn supports_attributes(&self) -> bool {
+        matches!(self, Resource::Table(_) | Resource::Index { .. })
+    }
+}
+
+// A composite of one atom says the same as the atom, and prints the same
+impl PartialEq for Policy {
+    fn eq(&self, other: &Self) -> bool {
+        self.atoms() == other.atoms()
+    }
+}
+
+// Two occurrences of a variable are the same variable wherever they're written
+impl PartialEq for Var {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path
+    }
+}
+
+impl Var {
+    pub fn name(&self) -> String {
+        self.path.join(".")
+    }
+}
+
+impl StringExpr {
+    pub fn variables(&self) -> Vec<&Var> {
+        match self {
+            StringExpr::Literal(_) => vec![],
+            StringExpr::Variable(var) => vec![var],
+            StringExpr::Concat(left, right) => {
+                let mut vars = left.variables();
+                vars.extend(right.variables());
+                vars
+            }
+        }
+    }
+}
+
+impl Filter {
+    pub fn key(&self) -> Option<Key> {
+        match self {
+            Filter::KeyEquals(key, _) | Filter::KeyLike(key, _) => Some(*key),
+            Filter::Prefix(_) => None,
+        }
+    }
+
+    pub fn value(&self) -> &StringExpr {
+        match self {
+            Filter::KeyEquals(_, expr) | Filter::KeyLike(_, expr) | Filter::Prefix(expr) => expr,
+        }
+    }
+}
+
+impl PolicyAtom {
+    pub fn variables(&self) -> Vec<&Var> {
+        self.filters.iter().flat_map(|filter| filter.value().variables()).collect()
+    }
+}
+
+impl Policy {
+    pub fn from_atoms(mut atoms: Vec<PolicyAtom>) -> Self {
+        if atoms.len() == 1 {
+            Policy::Atom(atoms.pop().unwrap())
+        } else {
+            Policy::Composite(atoms)
+        }
+    }
+
+    pub fn atoms(&self) -> &[PolicyAtom] {
+        match self {
+            Policy::Atom(atom) => std::slice::from_ref(atom),
+            Policy::Composite(atoms) => atoms,
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use std::fmt::{self, Display, Formatter};
+
+use syn::Ident;
+
+use crate::policy::*;
+
+// Prints the AST back as the DSL the parser accepts, one atom per line with every
+// clause written out, so parsing the output gives back the same policy.
+
+// Rust's escaping is also what the parser reads string literals with
+fn write_str_literal(f: &mut Formatter, value: &str) -> fmt::Result {
+    write!(f, "{value:?}")
+}
+
+impl Display for Effect {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Effect::Allow => write!(f, "allow"),
+            Effect::Deny => write!(f, "deny"),
+        }
+    }
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.keyword())
+    }
+}
+
+impl Display for Resource {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Resource::Index { index, table } => {
+                write!(f, "index ")?;
+                write_str_literal(f, index)?;
+                write!(f, " of table ")?;
+                write_str_literal(f, table)
+            }
+            Resource::Table(name)
+            | Resource::Bucket(name)
+            | Resource::Queue(name)
+            | Resource::Topic(name)
+            | Resource::EventBus(name)
+            | Resource::Secret(name)
+            | Resource::Parameter(name) => {
+                write!(f, "{} ", self.kind())?;
+                write_str_literal(f, name)
+            }
+        }
+    }
+}
+
+impl Display for Key {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Key::Pk => write!(f, "$pk"),
+            Key::Sk => write!(f, "$sk"),
+        }
+    }
+}
+
+impl Display for Var {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "${}", self.name())
+    }
+}
+
+impl Display for StringExpr {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            StringExpr::Literal(lit) => write_str_literal(f, lit),
+            StringExpr::Variable(var) => write!(f, "{var}"),
+            StringExpr::Concat(left, right) => write!(f, "concat({left}, {right})"),
+        }
+    }
+}
+
+impl Display for Filter {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Filter::KeyEquals(key, expr) => write!(f, "key_equals {key} {expr}"),
+            Filter::KeyLike(key, expr) => write!(f, "key_like {key} {expr}"),
+            Filter::Prefix(expr) => write!(f, "prefix {expr}"),
+        }
+    }
+}
+
+impl Display for Field {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write_str_literal(f, &self.0)
+    }
+}
+
+// `aws:SourceIp` and `aws:PrincipalTag/team` are written as tokens, keys with
+// parts that aren't identifiers have to be string literals
+fn is_token_key(key: &str) -> bool {
+    let Some((service, name)) = key.split_once(':') else {
+        return false;
+    };
+    let (name, tag) = match name.split_once('/') {
+        Some((name, tag)) => (name, Some(tag)),
+        None => (name, None),
+    };
+    [Some(service), Some(name), tag]
+        .into_iter()
+        .flatten()
+        .all(|part| syn::parse_str::<Ident>(part).is_ok())
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if is_token_key(&self.key) {
+            write!(f, "{} {}", self.key, self.operator)?;
+        } else {
+            write_str_literal(f, &self.key)?;
+            write!(f, " {}", self.operator)?;
+        }
+        match self.values.as_slice() {
+            [value] => {
+                write!(f, " ")?;
+                write_str_literal(f, value)
+            }
+            values => {
+                write!(f, " [")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write_str_literal(f, value)?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+impl Display for PolicyAtom {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} {} on {}", self.effect, self.action, self.resource)?;
+        for filter in &self.filters {
+            write!(f, " where {filter}")?;
+        }
+        for condition in &self.conditions {
+            write!(f, " when {condition}")?;
+        }
+        if let Some(fields) = &self.attributes {
+            write!(f, " with attributes [")?;
+            for (i, field) in fields.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " ")?;
+                }
+                write!(f, "{field}")?;
+            }
+            write!(f, "]")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Policy {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for (i, atom) in self.atoms().iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{atom}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proc_macro2::Span;
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn ident() -> impl Strategy<Value = String> {
+        "[a-z][a-z0-9_]{0,6}".prop_filter("not a keyword", |name| syn::parse_str::<Ident>(name).is_ok())
+    }
+
+    fn string_expr() -> impl Strategy<Value = StringExpr> {
+        let leaf = prop_oneof![
+            any::<String>().prop_map(StringExpr::Literal),
+            prop::collection::vec(ident(), 1..3)
+                .prop_map(|path| StringExpr::Variable(Var { path, span: Span::call_site() })),
+        ];
+        leaf.prop_recursive(3, 8, 2, |inner| {
+            (inner.clone(), inner).prop_map(|(left, right)| StringExpr::Concat(Box::new(left), Box::new(right)))
+        })
+    }
+
+    fn condition() -> impl Strategy<Value = Condition> {
+        let key = prop_oneof![
+            Just("aws:SourceIp".to_string()),
+            Just("aws:username".to_string()),
+            Just("aws:SecureTransport".to_string()),
+            // tags that aren't identifiers are printed as string literals
+            "[A-Za-z][A-Za-z0-9-]{0,5}".prop_map(|tag| format!("aws:PrincipalTag/{tag}")),
+        ];
+        let operator = prop_oneof![
+            Just("StringEquals"),
+            Just("StringLike"),
+            Just("ForAnyValue:StringLike"),
+            Just("IpAddress"),
+            Just("Bool"),
+        ];
+        (key, operator, prop::collection::vec(any::<String>(), 0..3))
+            .prop_map(|(key, operator, values)| Condition { key, operator: operator.to_string(), values })
+    }
+
+    fn resource() -> impl Strategy<Value = Resource> {
+        // any::<String>() is a regex, so it can be used more than once
+        let name = any::<String>();
+        prop_oneof![
+            name.prop_map(Resource::Table),
+            (name, name).prop_map(|(index, table)| Resource::Index { index, table }),
+            name.prop_map(Resource::Bucket),
+            name.prop_map(Resource::Queue),
+            name.prop_map(Resource::Topic),
+            name.prop_map(Resource::EventBus),
+            name.prop_map(Resource::Secret),
+            name.prop_map(Resource::Parameter),
+        ]
+    }
+
+    // Only atoms the parser accepts: the action and filters have to fit the
+    // resource, and there's at most one '$pk' filter and one prefix
+    fn atom() -> impl Strategy<Value = PolicyAtom> {
+        (resource(), any::<prop::sample::Index>())
+            .prop_flat_map(|(resource, action)| {
+                let actions: Vec<Action> =
+                    Action::ALL.into_iter().filter(|action| resource.supports_action(*action)).collect();
+                let action = *action.get(&actions);
+                let filters = match &resource {
+                    Resource::Table(_) | Resource::Index { .. } if action != Action::Scan => (
+                        prop::option::of((any::<bool>(), string_expr())),
+                        prop::collection::vec((any::<bool>(), string_expr()), 0..2),
+                    )
+                        .prop_map(|(pk, sk)| {
+                            let filter = |key, (like, expr)| match like {
+                                true => Filter::KeyLike(key, expr),
+                                false => Filter::KeyEquals(key, expr),
+                            };
+                            pk.map(|pk| filter(Key::Pk, pk)).into_iter().chain(sk.into_iter().map(|sk| filter(Key::Sk, sk))).collect()
+                        })
+                        .boxed(),
+                    Resource::Bucket(_) => prop::option::of(string_expr())
+                        .prop_map(|prefix| prefix.map(Filter::Prefix).into_iter().collect())
+                        .boxed(),
+                    _ => Just(vec![]).boxed(),
+                };
+                let attributes = match resource.supports_attributes() {
+                    true => prop::option::of(prop::collection::vec(any::<String>().prop_map(Field), 0..3)).boxed(),
+                    false => Just(None).boxed(),
+                };
+                (
+                    prop_oneof![Just(Effect::Allow), Just(Effect::Deny)],
+                    Just(action),
+                    Just(resource),
+                    filters,
+                    prop::collection::vec(condition(), 0..2),
+                    attributes,
+                )
+            })
+            .prop_map(|(effect, action, resource, filters, conditions, attributes)| PolicyAtom {
+                effect,
+                action,
+                resource,
+                filters,
+                conditions,
+                attributes,
+            })
+    }
+
+    fn policy() -> impl Strategy<Value = Policy> {
+        prop_oneof![
+            atom().prop_map(Policy::Atom),
+            prop::collection::vec(atom(), 1..4).prop_map(Policy::Composite),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn parsing_the_printed_policy_gives_it_back(policy in policy()) {
+            let printed = policy.to_string();
+            let parsed: Policy = syn::parse_str(&printed).map_err(|err| TestCaseError::fail(format!("{err} in\n{printed}")))?;
+            prop_assert_eq!(parsed, policy);
+        }
+    }
+
+    #[test]
+    fn single_atom_composite_equals_the_atom() {
+        let policy: Policy = syn::parse_str(r#"allow read on table "Users""#).unwrap();
+        assert_eq!(Policy::Composite(policy.atoms().to_vec()), policy);
+    }
+}
@@ -0,0 +1,446 @@
+use proc_macro2::Span;
+
+use crate::compiler::PolicyCompiler;
+use crate::config::PolicyConfig;
+use crate::policy::{Action, Condition, Effect, Filter, Key, Policy, PolicyAtom, Resource, StringExpr, Var};
+
+// Compiles a policy into Cedar, one permit/forbid per atom. The entities follow
+// the DSL rather than AWS:
+//
+// - actions are `Action::"<verb>"`, and an alias like `read` matches itself and
+//   the verbs it stands for, `get`, `query`, ..., listed in the policy so the
+//   entities don't need an action hierarchy
+// - tables, buckets, ... are entities of the matching type, DynamoDB items and S3
+//   objects are their children with `pk`/`sk` and `key` attributes
+// - `$vars` are read from `context`, or from `principal` for the parameter named
+//   by `cedar_principal` in the crate's config
+// - `with attributes` is checked against `context.attributes`, the attributes the
+//   request names, and `context.return_values` for writes
+
+// Cedar keywords can't be used after a '.'
+const RESERVED: [&str; 9] = ["true", "false", "if", "then", "else", "in", "like", "has", "is"];
+
+fn str_literal(value: &str) -> String {
+    format!("{value:?}")
+}
+
+// Whether `name` can be written as is after a '.' or `has`, or has to be quoted
+fn is_attribute_ident(name: &str) -> bool {
+    name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !RESERVED.contains(&name)
+}
+
+fn attribute_access(record: &str, name: &str) -> String {
+    if is_attribute_ident(name) {
+        format!("{record}.{name}")
+    } else {
+        format!("{record}[{}]", str_literal(name))
+    }
+}
+
+fn variable_record<'v>(var: &'v Var, config: &PolicyConfig) -> (String, &'v [String]) {
+    match &config.cedar_principal {
+        Some(principal) if *principal == var.path[0] && var.path.len() > 1 => ("principal".to_string(), &var.path[1..]),
+        _ => ("context".to_string(), &var.path[..]),
+    }
+}
+
+fn variable(var: &Var, config: &PolicyConfig) -> String {
+    let (mut access, fields) = variable_record(var, config);
+    for field in fields {
+        access = attribute_access(&access, field);
+    }
+    access
+}
+
+// Reading a missing attribute is an error, which makes Cedar skip the policy,
+// so a forbid checks each step of the path is there first, e.g.
+// `context has claims && context.claims has sub`
+fn variable_guard(var: &Var, config: &PolicyConfig) -> String {
+    let (mut access, fields) = variable_record(var, config);
+    let mut guards = vec![];
+    for field in fields {
+        match is_attribute_ident(field) {
+            true => guards.push(format!("{access} has {field}")),
+            false => guards.push(format!("{access} has {}", str_literal(field))),
+        }
+        access = attribute_access(&access, field);
+    }
+    guards.join(" && ")
+}
+
+fn resource_entity(resource: &Resource) -> String {
+    match resource {
+        Resource::Table(name) => format!("Table::{}", str_literal(name)),
+        Resource::Index { index, table } => format!("Index::{}", str_literal(&format!("{table}/{index}"))),
+        Resource::Bucket(name) => format!("Bucket::{}", str_literal(name)),
+        Resource::Queue(name) => format!("Queue::{}", str_literal(name)),
+        Resource::Topic(name) => format!("Topic::{}", str_literal(name)),
+        Resource::EventBus(name) => format!("EventBus::{}", str_literal(name)),
+        Resource::Secret(name) => format!("Secret::{}", str_literal(name)),
+        Resource::Parameter(name) => format!("Parameter::{}", str_literal(name)),
+    }
+}
+
+// Cedar can't build strings, so a `like` pattern is the closest it gets to a
+// concatenation with a variable: the variable widens to '*'. `?` has no Cedar
+// equivalent and widens the same way. That's only done in a forbid,
+// check_widening rejects permits that would need it.
+fn like_pattern(expr: &StringExpr, wildcards: bool) -> String {
+    match expr {
+        StringExpr::Literal(lit) => lit
+            .chars()
+            .map(|c| match c {
+                '?' if wildcards => "*".to_string(),
+                '*' if !wildcards => "\\*".to_string(),
+                c => c.escape_default().to_string(),
+            })
+            .collect(),
+        StringExpr::Variable(_) => "*".to_string(),
+        StringExpr::Concat(left, right) => like_pattern(left, wildcards) + &like_pattern(right, wildcards),
+    }
+}
+
+fn equals(attribute: &str, expr: &StringExpr, config: &PolicyConfig) -> String {
+    match expr {
+        StringExpr::Literal(lit) => format!("{attribute} == {}", str_literal(lit)),
+        StringExpr::Variable(var) => format!("{attribute} == {}", variable(var, config)),
+        StringExpr::Concat(..) => format!("{attribute} like \"{}\"", like_pattern(expr, false)),
+    }
+}
+
+// `guard` puts the variable_guard of a variable the filter reads in front of it
+fn compile_filter(filter: &Filter, config: &PolicyConfig, guard: bool) -> String {
+    let key_attribute = |key: &Key| match key {
+        Key::Pk => "resource.pk",
+        Key::Sk => "resource.sk",
+    };
+    match filter {
+        Filter::KeyEquals(key, StringExpr::Variable(var)) if guard => {
+            format!("{} && {} == {}", variable_guard(var, config), key_attribute(key), variable(var, config))
+        }
+        Filter::KeyEquals(key, expr) => equals(key_attribute(key), expr, config),
+        Filter::KeyLike(key, expr) => format!("{} like \"{}\"", key_attribute(key), like_pattern(expr, true)),
+        Filter::Prefix(expr) => {
+            let pattern = like_pattern(expr, false);
+            // a prefix ending in a variable already ends in a wildcard
+            if pattern.ends_with('*') && !pattern.ends_with("\\*") {
+                format!("resource.key like \"{pattern}\"")
+            } else {
+                format!("resource.key like \"{pattern}*\"")
+            }
+        }
+    }
+}
+
+// IAM condition keys are passed in `context` under their IAM name. Operators
+// without a Cedar counterpart are an error, which compile_atom turns into a comment.
+fn compile_condition(condition: &Condition) -> Result<String, String> {
+    let key = attribute_access("context", &condition.key);
+    let any = |check: &dyn Fn(&String) -> String| {
+        let checks: Vec<String> = condition.values.iter().map(check).collect();
+        match checks.len() {
+            0 => "false".to_string(),
+            _ => format!("({})", checks.join(" || ")),
+        }
+    };
+    let like = |value: &String| format!("{key} like \"{}\"", like_pattern(&StringExpr::Literal(value.clone()), true));
+    let check = match condition.operator.as_str() {
+        "StringEquals" => any(&|value| format!("{key} == {}", str_literal(value))),
+        "StringNotEquals" => format!("!{}", any(&|value| format!("{key} == {}", str_literal(value)))),
+        "StringLike" => any(&like),
+        "StringNotLike" => format!("!{}", any(&like)),
+        "Bool" => any(&|value| match value.as_str() {
+            "true" | "false" => format!("{key} == {value}"),
+            // never equal to a boolean, as IAM never matches it either
+            value => format!("{key} == {}", str_literal(value)),
+        }),
+        "IpAddress" => any(&|value| format!("{key}.isInRange(ip({}))", str_literal(value))),
+        "NotIpAddress" => format!("!{}", any(&|value| format!("{key}.isInRange(ip({}))", str_literal(value)))),
+        operator => return Err(format!("when {} {operator}: no Cedar equivalent", condition.key)),
+    };
+    let has = match key.strip_prefix("context.") {
+        Some(name) => format!("context has {name}"),
+        None => format!("context has {}", str_literal(&condition.key)),
+    };
+    Ok(format!("{has} && {check}"))
+}
+
+fn attribute_set(fields: &[String]) -> String {
+    let fields: Vec<String> = fields.iter().map(|field| str_literal(field)).collect();
+    format!("[{}]", fields.join(", "))
+}
+
+fn is_alias(action: Action) -> bool {
+    matches!(action, Action::Create | Action::Read | Action::Update | Action::Delete)
+}
+
+// The action and, for an alias, the verbs it covers on the resource: those whose
+// requests it allows all of
+fn actions(action: Action, resource: &Resource) -> Vec<Action> {
+    let covers = |verb: Action| match resource {
+        Resource::Table(_) | Resource::Index { .. } => {
+            let on_index = matches!(resource, Resource::Index { .. });
+            let operations = action.dynamodb_operations(on_index);
+            let verb_operations = verb.dynamodb_operations(on_index);
+            !verb_operations.is_empty() && verb_operations.iter().all(|operation| operations.contains(operation))
+        }
+        Resource::Bucket(_) => matches!((action, verb), (Action::Read, Action::Get) | (Action::Create | Action::Update, Action::Put)),
+        _ => false,
+    };
+    let verbs = Action::ALL
+        .into_iter()
+        .filter(|verb| is_alias(action) && !is_alias(*verb) && resource.supports_action(*verb) && covers(*verb));
+    std::iter::once(action).chain(verbs).collect()
+}
+
+fn literals(expr: &StringExpr) -> Vec<&str> {
+    match expr {
+        StringExpr::Literal(lit) => vec![lit],
+        StringExpr::Variable(_) => vec![],
+        StringExpr::Concat(left, right) => [literals(left), literals(right)].concat(),
+    }
+}
+
+fn compile_atom(atom: &PolicyAtom, config: &PolicyConfig) -> String {
+    let effect = match atom.effect {
+        Effect::Allow => "permit",
+        Effect::Deny => "forbid",
+    };
+    let resource = match &atom.resource {
+        // items and objects are checked, not the table or bucket itself
+        Resource::Table(_) | Resource::Index { .. } | Resource::Bucket(_) => {
+            format!("resource in {}", resource_entity(&atom.resource))
+        }
+        _ => format!("resource == {}", resource_entity(&atom.resource)),
+    };
+    let forbid = atom.effect == Effect::Deny;
+    let mut clauses: Vec<String> = atom.filters.iter().map(|filter| compile_filter(filter, config, forbid)).collect();
+    let mut comments = vec![];
+    for condition in &atom.conditions {
+        match compile_condition(condition) {
+            Ok(clause) => clauses.push(clause),
+            Err(comment) => {
+                comments.push(format!("// {comment}\n"));
+                // leaving the check out widens the statement, which is only safe for a forbid
+                if atom.effect == Effect::Allow {
+                    clauses.push("false".to_string());
+                }
+            }
+        }
+    }
+    // Same rules as the IAM backend: reads have to name their attributes, since
+    // a read that doesn't gets all of them, and writes can't return whole items
+    if let Some(fields) = &atom.attributes {
+        let fields: Vec<String> = fields.iter().map(|field| field.0.clone()).collect();
+        let keys = match &atom.resource {
+            Resource::Table(table) | Resource::Index { table, .. } => config.key_schema(table),
+            // rejected by the parser
+            _ => Default::default(),
+        };
+        match (atom.effect, atom.action.is_read()) {
+            (Effect::Allow, true) => {
+                let allowed: Vec<String> = keys.attributes().iter().map(|key| key.to_string()).chain(fields).collect();
+                clauses.push(format!("context has attributes && {}.containsAll(context.attributes)", attribute_set(&allowed)));
+            }
+            (Effect::Allow, false) => {
+                let allowed: Vec<String> = keys.attributes().iter().map(|key| key.to_string()).chain(fields).collect();
+                clauses.push(format!(
+                    "context has attributes && {}.containsAll(context.attributes) && (!(context has return_values) || {}.contains(context.return_values))",
+                    attribute_set(&allowed),
+                    attribute_set(&["NONE".to_string(), "UPDATED_OLD".to_string(), "UPDATED_NEW".to_string()])
+                ));
+            }
+            (Effect::Deny, true) => clauses.push(format!(
+                "(!(context has attributes) || context.attributes.containsAny({}))",
+                attribute_set(&fields)
+            )),
+            (Effect::Deny, false) => clauses.push(format!(
+                "((context has attributes && context.attributes.containsAny({})) || (context has return_values && {}.contains(context.return_values)))",
+                attribute_set(&fields),
+                attribute_set(&["ALL_OLD".to_string(), "ALL_NEW".to_string()])
+            )),
+        }
+    }
+    let mut statement = comments.concat();
+    let action = match actions(atom.action, &atom.resource).as_slice() {
+        [action] => format!("action == Action::{}", str_literal(action.keyword())),
+        actions => {
+            let actions: Vec<String> = actions.iter().map(|action| format!("Action::{}", str_literal(action.keyword()))).collect();
+            format!("action in [{}]", actions.join(", "))
+        }
+    };
+    statement.push_str(&format!("{effect} (\n    principal,\n    {action},\n    {resource}\n)"));
+    if !clauses.is_empty() {
+        statement.push_str(&format!("\nwhen {{ {} }}", clauses.join(" && ")));
+    }
+    statement.push(';');
+    statement
+}
+
+pub struct CedarPolicyCompiler {
+    pub config: PolicyConfig,
+}
+
+impl CedarPolicyCompiler {
+    // A permit that Cedar could only express by widening a string to '*' would
+    // allow more than the policy says, so it's an error rather than compiled.
+    // Variables compared on their own are fine, anything built around one isn't.
+    pub fn check_widening(&self, policy: &Policy) -> syn::Result<()> {
+        let mut errors: Option<syn::Error> = None;
+        let mut error = |span: Span, message: String| {
+            let error = syn::Error::new(span, message);
+            match &mut errors {
+                Some(errors) => errors.combine(error),
+                None => errors = Some(error),
+            }
+        };
+        for atom in policy.atoms().iter().filter(|atom| atom.effect == Effect::Allow) {
+            for filter in &atom.filters {
+                let compared = matches!(filter, Filter::KeyEquals(_, StringExpr::Variable(_)));
+                for var in filter.value().variables().into_iter().filter(|_| !compared) {
+                    error(
+                        var.span,
+                        format!(
+                            "Cedar can't build strings, so '${}' in '{filter}' would allow any value in the permit: \
+                             compare the variable on its own, e.g. 'key_equals $pk ${}'",
+                            var.name(),
+                            var.name()
+                        ),
+                    );
+                }
+                if matches!(filter, Filter::KeyLike(..)) && literals(filter.value()).iter().any(|lit| lit.contains('?')) {
+                    error(
+                        Span::call_site(),
+                        format!("Cedar's like has no single character wildcard, so the '?' in '{filter}' would match any number of characters in the permit"),
+                    );
+                }
+            }
+            for condition in atom.conditions.iter().filter(|condition| condition.operator == "StringLike") {
+                if condition.values.iter().any(|value| value.contains('?')) {
+                    error(
+                        Span::call_site(),
+                        format!(
+                            "Cedar's like has no single character wildcard, so the '?' in '{condition}' would match any number of characters in the permit"
+                        ),
+                    );
+                }
+            }
+        }
+        match errors {
+            Some(errors) => Err(errors),
+            None => Ok(()),
+        }
+    }
+}
+
+impl PolicyCompiler for CedarPolicyCompiler {
+    fn compile_policy(&self, policy: &Policy) -> String {
+        let statements: Vec<String> = policy.atoms().iter().map(|atom| compile_atom(atom, &self.config)).collect();
+        format!("{}\n", statements.join("\n\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(text: &str) -> Policy {
+        syn::parse_str(text).unwrap()
+    }
+
+    fn compile(text: &str, config: PolicyConfig) -> String {
+        CedarPolicyCompiler { config }.compile_policy(&policy(text))
+    }
+
+    fn widening_error(text: &str) -> String {
+        let compiler = CedarPolicyCompiler { config: PolicyConfig::default() };
+        compiler.check_widening(&policy(text)).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn read_with_principal_and_attributes() {
+        let config = PolicyConfig { cedar_principal: Some("claims".to_string()), ..Default::default() };
+        let cedar = compile(
+            r#"allow read on table "Users" where key_equals $pk $claims.sub where key_like $sk "FRIEND#*" with attributes ["email"]"#,
+            config,
+        );
+        assert_eq!(
+            cedar,
+            r#"permit (
+    principal,
+    action in [Action::"read", Action::"get", Action::"query", Action::"batch_get", Action::"transact_get"],
+    resource in Table::"Users"
+)
+when { resource.pk == principal.sub && resource.sk like "FRIEND#*" && context has attributes && ["PK", "SK", "email"].containsAll(context.attributes) };
+"#
+        );
+    }
+
+    #[test]
+    fn forbid_widens_built_strings() {
+        let cedar = compile(
+            r#"allow get on bucket "photos" where prefix "public/"
+               deny put on table "Users" where key_equals $pk concat("USER#", $user_id) where key_like $sk "A?""#,
+            PolicyConfig::default(),
+        );
+        assert_eq!(
+            cedar,
+            r#"permit (
+    principal,
+    action == Action::"get",
+    resource in Bucket::"photos"
+)
+when { resource.key like "public/*" };
+
+forbid (
+    principal,
+    action == Action::"put",
+    resource in Table::"Users"
+)
+when { resource.pk like "USER#*" && resource.sk like "A*" };
+"#
+        );
+    }
+
+    #[test]
+    fn forbids_check_the_variables_they_read_are_there() {
+        let config = PolicyConfig { cedar_principal: Some("claims".to_string()), ..Default::default() };
+        let cedar = compile(
+            r#"deny read on table "Users" where key_equals $pk $blocked.user where key_equals $sk $claims.sub"#,
+            config,
+        );
+        assert_eq!(
+            cedar,
+            r#"forbid (
+    principal,
+    action in [Action::"read", Action::"get", Action::"query", Action::"batch_get", Action::"transact_get"],
+    resource in Table::"Users"
+)
+when { context has blocked && context.blocked has user && resource.pk == context.blocked.user && principal has sub && resource.sk == principal.sub };
+"#
+        );
+    }
+
+    #[test]
+    fn bool_conditions_quote_anything_but_booleans() {
+        let cedar = compile(r#"allow send on queue "jobs" when aws:SecureTransport bool [true "yes"]"#, PolicyConfig::default());
+        assert!(
+            cedar.contains(r#"(context["aws:SecureTransport"] == true || context["aws:SecureTransport"] == "yes")"#),
+            "{cedar}"
+        );
+    }
+
+    #[test]
+    fn permits_that_would_widen_are_errors() {
+        assert!(widening_error(r#"allow read on table "Users" where key_equals $pk concat("USER#", $user_id)"#)
+            .contains("'$user_id'"));
+        assert!(widening_error(r#"allow get on bucket "photos" where prefix $user_id"#).contains("'$user_id'"));
+        assert!(widening_error(r#"allow read on table "Users" where key_like $sk "A?""#).contains("'?'"));
+
+        let compiler = CedarPolicyCompiler { config: PolicyConfig::default() };
+        let compared = policy(r#"allow read on table "Users" where key_equals $pk $user_id where key_like $sk "A*""#);
+        assert!(compiler.check_widening(&compared).is_ok());
+    }
+}
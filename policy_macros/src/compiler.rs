@@ -1,5 +1,8 @@
 use crate::policy::Policy;
 
+// policy_attr compiles the Cedar and Rego backends through this, the IAM
+// document is built and checked before it's serialized
+#[cfg_attr(not(any(feature = "cedar", feature = "rego")), allow(dead_code))]
 pub trait PolicyCompiler {
     fn compile_policy(&self, policy: &Policy) -> String;
-}
\ No newline at end of file
+}
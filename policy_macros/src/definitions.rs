@@ -0,0 +1,209 @@
+use std::path::{Path, PathBuf};
+
+use syn::Error;
+
+use crate::policy::{Filter, PolicyAtom, PolicyDefinition, PolicyItem, PolicyUse, StringExpr, Var};
+use crate::sources::Sources;
+
+// define_policy! invocations can be anywhere in the crate and expand in no
+// particular order relative to the handlers using them, so `use` items are
+// resolved against the definitions read from the crate's sources.
+
+fn substitute(expr: &StringExpr, params: &[String], policy_use: &PolicyUse) -> syn::Result<StringExpr> {
+    match expr {
+        StringExpr::Literal(_) => Ok(expr.clone()),
+        StringExpr::Variable(var) => {
+            // definitions are checked to only use their own parameters
+            let index = params.iter().position(|param| *param == var.path[0]).unwrap();
+            match (&policy_use.args[index], &var.path[1..]) {
+                (arg, []) => Ok(arg.clone()),
+                (StringExpr::Variable(arg), fields) => Ok(StringExpr::Variable(Var {
+                    path: arg.path.iter().chain(fields).cloned().collect(),
+                    span: arg.span,
+                })),
+                (_, _) => Err(Error::new(
+                    policy_use.span,
+                    format!(
+                        "'{}' uses '${}' of parameter '{}', so its argument has to be a '$variable'",
+                        policy_use.name,
+                        var.name(),
+                        var.path[0]
+                    ),
+                )),
+            }
+        }
+        StringExpr::Concat(left, right) => Ok(StringExpr::Concat(
+            Box::new(substitute(left, params, policy_use)?),
+            Box::new(substitute(right, params, policy_use)?),
+        )),
+    }
+}
+
+fn instantiate(definition: &PolicyDefinition, policy_use: &PolicyUse) -> syn::Result<Vec<PolicyAtom>> {
+    if definition.params.len() != policy_use.args.len() {
+        return Err(Error::new(
+            policy_use.span,
+            format!(
+                "'{}' takes {} argument(s) ({}) but {} were given",
+                definition.name,
+                definition.params.len(),
+                definition.params.join(", "),
+                policy_use.args.len()
+            ),
+        ));
+    }
+    definition
+        .policy
+        .atoms()
+        .iter()
+        .map(|atom| {
+            let filters = atom
+                .filters
+                .iter()
+                .map(|filter| {
+                    Ok(match filter {
+                        Filter::KeyEquals(key, expr) => Filter::KeyEquals(*key, substitute(expr, &definition.params, policy_use)?),
+                        Filter::KeyLike(key, expr) => Filter::KeyLike(*key, substitute(expr, &definition.params, policy_use)?),
+                        Filter::Prefix(expr) => Filter::Prefix(substitute(expr, &definition.params, policy_use)?),
+                    })
+                })
+                .collect::<syn::Result<Vec<Filter>>>()?;
+            Ok(PolicyAtom { filters, ..atom.clone() })
+        })
+        .collect()
+}
+
+// The definition a `use` names. A bare name is looked up in every module of the
+// crate, and has to be defined in only one of them, a path is read from the
+// crate root.
+fn find_definition(sources: &Sources, policy_use: &PolicyUse) -> syn::Result<PolicyDefinition> {
+    let mut found: Vec<(PathBuf, String, _)> = vec![];
+    for target in sources.targets_in_scope() {
+        let modules: Vec<usize> = match policy_use.module.is_empty() {
+            true => (0..sources.modules.len()).filter(|module| sources.modules[*module].target == target).collect(),
+            false => sources
+                .module_at(target, &[])
+                .and_then(|root| sources.resolve(root, &policy_use.module))
+                .and_then(|(target, path)| sources.module_at(target, &path))
+                .into_iter()
+                .collect(),
+        };
+        for module in modules {
+            let module = &sources.modules[module];
+            let path = module.display_path();
+            for definition in module.definitions.iter().filter(|definition| definition.name == policy_use.name) {
+                // a binary and its library can include the same file
+                if !found.iter().any(|(file, known, _)| *file == module.file && *known == path) {
+                    found.push((module.file.clone(), path.clone(), definition));
+                }
+            }
+        }
+    }
+    match found.as_slice() {
+        [] => Err(Error::new(
+            policy_use.span,
+            match policy_use.module.as_slice() {
+                [] => format!("unknown policy '{}', expected a name given to define_policy! in this crate", policy_use.name),
+                module => format!("no policy '{}' is defined in crate::{}", policy_use.name, module.join("::")),
+            },
+        )),
+        [(_, _, definition)] => definition.parse(),
+        _ => {
+            let paths: Vec<String> = found.iter().map(|(_, module, _)| format!("{module}::{}", policy_use.name)).collect();
+            Err(Error::new(
+                policy_use.span,
+                format!(
+                    "policy '{}' is defined more than once ({}), name the one to use with its module, e.g. 'use {}'",
+                    policy_use.name,
+                    paths.join(", "),
+                    paths[0].trim_start_matches("crate::")
+                ),
+            ))
+        }
+    }
+}
+
+// Replaces every `use` with the atoms of its definition, keeping the order
+// the items were written in
+pub fn resolve(items: Vec<PolicyItem>, crate_root: &Path) -> syn::Result<Vec<PolicyAtom>> {
+    let mut sources = None;
+    let mut atoms = vec![];
+    for item in items {
+        let policy_use = match item {
+            PolicyItem::Atom(atom) => {
+                atoms.push(atom);
+                continue;
+            }
+            PolicyItem::Use(policy_use) => policy_use,
+        };
+        let sources = sources.get_or_insert_with(|| Sources::load(crate_root));
+        let definition = find_definition(sources, &policy_use)?;
+        atoms.extend(instantiate(&definition, &policy_use)?);
+    }
+    Ok(atoms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::PolicyAttr;
+    use crate::sources::tests::TempCrate;
+
+    fn policies(test: &str) -> TempCrate {
+        TempCrate::new(
+            test,
+            &[
+                ("Cargo.toml", "[package]\nname = \"app\"\n"),
+                ("src/main.rs", "mod profile;\nmod chat;\n"),
+                (
+                    "src/profile.rs",
+                    r#"define_policy!(read_profile(user) = allow read on table "Users" where key_equals $pk concat("USER#", $user));
+                       define_policy!(read = allow read on table "Users");"#,
+                ),
+                ("src/chat/mod.rs", "mod inner { define_policy!(read = allow read on table \"Messages\"); }"),
+            ],
+        )
+    }
+
+    fn resolve_str(krate: &TempCrate, attr: &str) -> Result<Vec<PolicyAtom>, String> {
+        let attr: PolicyAttr = syn::parse_str(attr).unwrap();
+        resolve(attr.items, &krate.root).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn bare_names_are_looked_up_in_every_module() {
+        let krate = policies("bare_names");
+        let atoms = resolve_str(&krate, "use read_profile($claims.sub)").unwrap();
+        assert_eq!(atoms.len(), 1);
+        assert_eq!(atoms[0].filters[0].value().to_string(), "concat(\"USER#\", $claims.sub)");
+    }
+
+    #[test]
+    fn paths_pick_one_of_several_definitions() {
+        let krate = policies("paths");
+        assert_eq!(
+            resolve_str(&krate, "use read").unwrap_err(),
+            "policy 'read' is defined more than once (crate::profile::read, crate::chat::inner::read), \
+             name the one to use with its module, e.g. 'use profile::read'"
+        );
+        let atoms = resolve_str(&krate, "use crate::chat::inner::read").unwrap();
+        assert_eq!(atoms[0].resource.to_string(), "table \"Messages\"");
+        assert_eq!(
+            resolve_str(&krate, "use chat::read").unwrap_err(),
+            "no policy 'read' is defined in crate::chat"
+        );
+    }
+
+    #[test]
+    fn arguments_have_to_fit_the_parameters() {
+        let krate = policies("arguments");
+        assert_eq!(
+            resolve_str(&krate, "use read_profile").unwrap_err(),
+            "'read_profile' takes 1 argument(s) (user) but 0 were given"
+        );
+        assert_eq!(
+            resolve_str(&krate, "use unknown").unwrap_err(),
+            "unknown policy 'unknown', expected a name given to define_policy! in this crate"
+        );
+    }
+}
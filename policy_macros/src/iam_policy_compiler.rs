@@ -1,10 +1,841 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+
 use crate::compiler::PolicyCompiler;
-use crate::policy::Policy;
+use crate::config::{KeySchema, PolicyConfig};
+use crate::policy::{Action, Effect, Field, Filter, Key, Policy, PolicyAtom, Resource, StringExpr};
+
+const POLICY_VERSION: &str = "2012-10-17";
+const LEADING_KEYS: &str = "dynamodb:LeadingKeys";
+const ATTRIBUTES: &str = "dynamodb:Attributes";
+const SELECT: &str = "dynamodb:Select";
+const RETURN_VALUES: &str = "dynamodb:ReturnValues";
+
+// IAM counts a role's inline policies without whitespace, and they all share this limit
+pub const INLINE_ROLE_POLICY_LIMIT: usize = 10_240;
+// IAM's default quota of managed policies attached to a role
+pub const MANAGED_POLICIES_PER_ROLE: usize = 10;
+// the one attached to every function's role
+const BASIC_EXECUTION_ROLE: &str = "arn:aws:iam::aws:policy/service-role/AWSLambdaBasicExecutionRole";
+
+// The ARN placeholders filled in at deployment, at their longest: the partition
+// and region with the longest names, and an account ID
+const DEPLOYED_PLACEHOLDERS: [(&str, &str); 3] =
+    [("${partition}", "aws-us-gov"), ("${region}", "ap-southeast-5"), ("${account}", "123456789012")];
+// `${stage}` is only as long as the crate says
+pub const DEFAULT_MAX_STAGE_LENGTH: usize = 32;
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct PolicyDocument {
+    pub version: String,
+    pub statement: Vec<Statement>,
+}
+
+// operator -> condition key -> values, kept sorted so output is stable between builds
+pub(crate) type ConditionBlock = BTreeMap<String, BTreeMap<String, Vec<String>>>;
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct Statement {
+    pub effect: String,
+    pub action: Vec<String>,
+    pub resource: Vec<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub condition: ConditionBlock,
+}
+
+impl Statement {
+    fn new(effect: Effect, actions: Vec<impl Into<String>>, resources: Vec<String>) -> Self {
+        Statement {
+            effect: match effect {
+                Effect::Allow => "Allow".to_string(),
+                Effect::Deny => "Deny".to_string(),
+            },
+            action: actions.into_iter().map(Into::into).collect(),
+            resource: resources,
+            condition: BTreeMap::new(),
+        }
+    }
+
+    fn add_condition(&mut self, operator: &str, key: &str, value: String) {
+        let values = self
+            .condition
+            .entry(operator.to_string())
+            .or_default()
+            .entry(key.to_string())
+            .or_default();
+        if !values.contains(&value) {
+            values.push(value);
+        }
+    }
+}
+
+// A compiled string expression. Variables mapped in the crate's config become
+// IAM policy variables; any other variable can't be known when the policy is
+// written, so it widens to a wildcard. check_variables makes sure statements
+// only widen the variables the crate lists in widen_variables.
+enum Segment {
+    Literal(String),
+    PolicyVariable(String),
+    Wildcard,
+}
+
+fn segments(expr: &StringExpr, config: &PolicyConfig) -> Vec<Segment> {
+    match expr {
+        StringExpr::Literal(lit) => vec![Segment::Literal(lit.clone())],
+        StringExpr::Variable(var) => match config.variables.get(&var.name()) {
+            Some(iam_variable) => vec![Segment::PolicyVariable(iam_variable.clone())],
+            None => vec![Segment::Wildcard],
+        },
+        StringExpr::Concat(left, right) => {
+            let mut segs = segments(left, config);
+            segs.extend(segments(right, config));
+            segs
+        }
+    }
+}
+
+fn has_wildcard(segs: &[Segment]) -> bool {
+    segs.iter().any(|seg| matches!(seg, Segment::Wildcard))
+}
+
+// Renders segments for a StringLike comparison. When `escape` is set, '*' and
+// '?' inside literals are matched literally instead of as wildcards.
+fn render_like(segs: &[Segment], escape: bool) -> String {
+    segs.iter()
+        .map(|seg| match seg {
+            Segment::Literal(lit) if escape => lit.replace('*', "${*}").replace('?', "${?}"),
+            Segment::Literal(lit) => lit.clone(),
+            Segment::PolicyVariable(iam_variable) => iam_variable.clone(),
+            Segment::Wildcard => "*".to_string(),
+        })
+        .collect()
+}
+
+fn render_equals(segs: &[Segment]) -> String {
+    segs.iter()
+        .map(|seg| match seg {
+            Segment::Literal(lit) => lit.clone(),
+            Segment::PolicyVariable(iam_variable) => iam_variable.clone(),
+            Segment::Wildcard => "*".to_string(),
+        })
+        .collect()
+}
+
+fn dynamodb_actions(action: &Action, on_index: bool) -> Vec<String> {
+    action.dynamodb_operations(on_index).iter().map(|operation| format!("dynamodb:{operation}")).collect()
+}
+
+// Where a resource lives when the crate doesn't configure an ARN template for its
+// kind: any region and account, named exactly as in the policy
+fn default_arn_template(kind: &str) -> &'static str {
+    match kind {
+        "table" => "arn:aws:dynamodb:*:*:table/{name}",
+        "bucket" => "arn:aws:s3:::{name}",
+        "queue" => "arn:aws:sqs:*:*:{name}",
+        "topic" => "arn:aws:sns:*:*:{name}",
+        "event_bus" => "arn:aws:events:*:*:event-bus/{name}",
+        "secret" => "arn:aws:secretsmanager:*:*:secret:{name}",
+        "parameter" => "arn:aws:ssm:*:*:parameter/{name}",
+        _ => unreachable!("no ARN template for {kind} resources"),
+    }
+}
+
+// Only `{name}` is filled in here, any `${partition}`, `${region}`, `${account}`
+// or `${stage}` in a configured template is left for deployment to fill in
+fn resource_arn(kind: &str, name: &str, config: &PolicyConfig) -> String {
+    config
+        .arn_templates
+        .get(kind)
+        .map(String::as_str)
+        .unwrap_or(default_arn_template(kind))
+        .replace("{name}", name)
+}
+
+fn table_arn(table: &str, config: &PolicyConfig) -> String {
+    resource_arn("table", table, config)
+}
+
+fn index_arn(index: &str, table: &str, config: &PolicyConfig) -> String {
+    format!("{}/index/{index}", table_arn(table, config))
+}
+
+fn s3_object_actions(action: &Action) -> Vec<&'static str> {
+    match action {
+        Action::Read | Action::Get => vec!["s3:GetObject"],
+        Action::Create | Action::Put | Action::Update => vec!["s3:PutObject"],
+        Action::Delete => vec!["s3:DeleteObject"],
+        // rejected by the parser
+        _ => vec![],
+    }
+}
+
+// Multi-valued keys like LeadingKeys and Attributes need a set operator. An
+// allow has to hold for every value in the request, a deny fires as soon as
+// any value matches.
+fn set_operator(effect: Effect, operator: &str) -> String {
+    match effect {
+        Effect::Allow => format!("ForAllValues:{operator}"),
+        Effect::Deny => format!("ForAnyValue:{operator}"),
+    }
+}
+
+fn compile_filter(statement: &mut Statement, effect: Effect, filter: &Filter, config: &PolicyConfig) {
+    // IAM only exposes the partition key (as dynamodb:LeadingKeys), so sort key
+    // filters can't be expressed here. check_sort_keys only lets them through
+    // when the crate sets allow_sk_filters.
+    match filter {
+        Filter::KeyEquals(Key::Pk, expr) => {
+            let segs = segments(expr, config);
+            if has_wildcard(&segs) {
+                statement.add_condition(&set_operator(effect, "StringLike"), LEADING_KEYS, render_like(&segs, true));
+            } else {
+                statement.add_condition(&set_operator(effect, "StringEquals"), LEADING_KEYS, render_equals(&segs));
+            }
+        }
+        Filter::KeyLike(Key::Pk, expr) => {
+            let segs = segments(expr, config);
+            statement.add_condition(&set_operator(effect, "StringLike"), LEADING_KEYS, render_like(&segs, false));
+        }
+        Filter::KeyEquals(Key::Sk, _) | Filter::KeyLike(Key::Sk, _) => {}
+        // only valid on buckets, see compile_bucket_atom
+        Filter::Prefix(_) => {}
+    }
+}
+
+// Key attributes are always part of the item, so they're always allowed in an
+// attribute list
+fn allow_attributes(statement: &mut Statement, action: &Action, fields: &[Field], keys: &KeySchema) {
+    for attribute in keys.attributes().iter().map(|key| key.to_string()).chain(fields.iter().map(|field| field.0.clone())) {
+        statement.add_condition("ForAllValues:StringEquals", ATTRIBUTES, attribute);
+    }
+    if action.is_read() {
+        // projection: the request has to name its attributes instead of asking for the whole item
+        statement.add_condition("StringEqualsIfExists", SELECT, "SPECIFIC_ATTRIBUTES".to_string());
+    } else {
+        // writeable attributes: returning ALL_OLD/ALL_NEW would leak the attributes we can't touch
+        for return_value in ["NONE", "UPDATED_OLD", "UPDATED_NEW"] {
+            statement.add_condition("StringEqualsIfExists", RETURN_VALUES, return_value.to_string());
+        }
+    }
+}
+
+// Denying attributes takes two statements: one for requests that name a denied
+// attribute, and one for requests that would get it without naming it (reads
+// of the whole item, or writes returning the whole item).
+fn deny_attributes(statement: Statement, action: &Action, fields: &[Field]) -> Vec<Statement> {
+    let mut named = statement.clone();
+    for field in fields {
+        named.add_condition("ForAnyValue:StringEquals", ATTRIBUTES, field.0.clone());
+    }
+    let mut unnamed = statement;
+    if action.is_read() {
+        unnamed.add_condition("Null", ATTRIBUTES, "true".to_string());
+    } else {
+        for return_value in ["ALL_OLD", "ALL_NEW"] {
+            unnamed.add_condition("StringEquals", RETURN_VALUES, return_value.to_string());
+        }
+    }
+    vec![named, unnamed]
+}
+
+fn compile_table_atom(atom: &PolicyAtom, table: &str, actions: Vec<String>, arn: String, config: &PolicyConfig) -> Vec<Statement> {
+    let mut statement = Statement::new(atom.effect, actions, vec![arn]);
+    for filter in &atom.filters {
+        compile_filter(&mut statement, atom.effect, filter, config);
+    }
+    match (&atom.attributes, atom.effect) {
+        (None, _) => vec![statement],
+        (Some(fields), Effect::Allow) => {
+            allow_attributes(&mut statement, &atom.action, fields, &config.key_schema(table));
+            vec![statement]
+        }
+        (Some(fields), Effect::Deny) => deny_attributes(statement, &atom.action, fields),
+    }
+}
+
+fn messaging_actions(action: &Action) -> Vec<&'static str> {
+    match action {
+        // the SDKs resolve queue names to URLs before sending or receiving
+        Action::Send => vec!["sqs:SendMessage", "sqs:GetQueueUrl"],
+        // consuming a message means deleting it or extending its visibility once it's handled
+        Action::Receive => vec![
+            "sqs:ReceiveMessage",
+            "sqs:DeleteMessage",
+            "sqs:ChangeMessageVisibility",
+            "sqs:GetQueueAttributes",
+            "sqs:GetQueueUrl",
+        ],
+        Action::Publish => vec!["sns:Publish"],
+        Action::PutEvents => vec!["events:PutEvents"],
+        // rejected by the parser
+        _ => vec![],
+    }
+}
+
+// Object actions are scoped by putting the prefix in the object ARN, for allows
+// and denies alike, listing is scoped with the s3:prefix condition on the bucket
+// ARN. A listing that doesn't pass a prefix lists the denied objects too, so a
+// deny applies to it as well.
+fn compile_bucket_atom(atom: &PolicyAtom, bucket: &str, config: &PolicyConfig) -> Vec<Statement> {
+    let prefix = atom.filters.iter().find_map(|filter| match filter {
+        Filter::Prefix(expr) => Some(render_like(&segments(expr, config), true)),
+        _ => None,
+    });
+    let object_pattern = match &prefix {
+        Some(prefix) if prefix.ends_with('*') => prefix.clone(),
+        Some(prefix) => format!("{prefix}*"),
+        None => "*".to_string(),
+    };
+    let bucket_arn = resource_arn("bucket", bucket, config);
+    let mut statements = vec![Statement::new(
+        atom.effect,
+        s3_object_actions(&atom.action),
+        vec![format!("{bucket_arn}/{object_pattern}")],
+    )];
+    if atom.action == Action::Read {
+        let mut list = Statement::new(atom.effect, vec!["s3:ListBucket"], vec![bucket_arn]);
+        match (prefix.is_some(), atom.effect) {
+            (true, Effect::Allow) => list.add_condition("StringLike", "s3:prefix", object_pattern),
+            (true, Effect::Deny) => list.add_condition("StringLikeIfExists", "s3:prefix", object_pattern),
+            (false, _) => {}
+        }
+        statements.push(list);
+    }
+    statements
+}
+
+// Reading a secret or parameter encrypted with a customer managed key also needs
+// kms:Decrypt, but only when the service asks for it on our behalf. Denying the
+// read is enough for a deny, denying the decrypt would also deny every other
+// secret or parameter encrypted with the key.
+fn compile_secret_atom(atom: &PolicyAtom, actions: Vec<&str>, arn: String, service: &str, config: &PolicyConfig) -> Vec<Statement> {
+    let mut statements = vec![Statement::new(atom.effect, actions, vec![arn])];
+    if let (Some(kms_key), Effect::Allow) = (&config.kms_key, atom.effect) {
+        let mut decrypt = Statement::new(atom.effect, vec!["kms:Decrypt"], vec![kms_key.clone()]);
+        decrypt.add_condition("StringLike", "kms:ViaService", format!("{service}.*.amazonaws.com"));
+        statements.push(decrypt);
+    }
+    statements
+}
+
+pub(crate) fn compile_atom(atom: &PolicyAtom, config: &PolicyConfig) -> Vec<Statement> {
+    let mut statements = match &atom.resource {
+        Resource::Table(table) => {
+            compile_table_atom(atom, table, dynamodb_actions(&atom.action, false), table_arn(table, config), config)
+        }
+        Resource::Index { index, table } => {
+            compile_table_atom(atom, table, dynamodb_actions(&atom.action, true), index_arn(index, table, config), config)
+        }
+        Resource::Bucket(bucket) => compile_bucket_atom(atom, bucket, config),
+        Resource::Queue(queue) => vec![Statement::new(
+            atom.effect,
+            messaging_actions(&atom.action),
+            vec![resource_arn("queue", queue, config)],
+        )],
+        Resource::Topic(topic) => vec![Statement::new(
+            atom.effect,
+            messaging_actions(&atom.action),
+            vec![resource_arn("topic", topic, config)],
+        )],
+        Resource::EventBus(event_bus) => vec![Statement::new(
+            atom.effect,
+            messaging_actions(&atom.action),
+            vec![resource_arn("event_bus", event_bus, config)],
+        )],
+        // Secrets Manager appends a random six character suffix to secret ARNs
+        Resource::Secret(secret) => compile_secret_atom(
+            atom,
+            vec!["secretsmanager:GetSecretValue"],
+            format!("{}-??????", resource_arn("secret", secret, config)),
+            "secretsmanager",
+            config,
+        ),
+        Resource::Parameter(parameter) => compile_secret_atom(
+            atom,
+            vec!["ssm:GetParameter", "ssm:GetParameters"],
+            resource_arn("parameter", parameter.trim_start_matches('/'), config),
+            "ssm",
+            config,
+        ),
+    };
+    for statement in &mut statements {
+        for condition in &atom.conditions {
+            for value in &condition.values {
+                statement.add_condition(&condition.operator, &condition.key, value.clone());
+            }
+        }
+    }
+    statements
+}
+
+// The condition block as sets, so the order values were added in doesn't matter
+// when comparing two statements
+fn condition_sets(condition: &ConditionBlock) -> BTreeMap<&String, BTreeMap<&String, BTreeSet<&String>>> {
+    condition
+        .iter()
+        .map(|(operator, keys)| (operator, keys.iter().map(|(key, values)| (key, values.iter().collect())).collect()))
+        .collect()
+}
+
+fn leading_keys_operator(statement: &Statement) -> Option<&String> {
+    let mut operators = statement.condition.iter().filter(|(_, keys)| keys.contains_key(LEADING_KEYS));
+    match (operators.next(), operators.next()) {
+        (Some((operator, _)), None) => Some(operator),
+        _ => None,
+    }
+}
+
+fn without_leading_keys(condition: &ConditionBlock) -> ConditionBlock {
+    let mut condition = condition.clone();
+    for keys in condition.values_mut() {
+        keys.remove(LEADING_KEYS);
+    }
+    condition.retain(|_, keys| !keys.is_empty());
+    condition
+}
+
+// Statements are OR'ed together, so two statements that only differ in their
+// actions are the same as one statement with both sets of actions
+fn union_actions(into: &mut Statement, from: &Statement) -> bool {
+    if into.effect != from.effect
+        || into.resource != from.resource
+        || condition_sets(&into.condition) != condition_sets(&from.condition)
+    {
+        return false;
+    }
+    for action in &from.action {
+        if !into.action.contains(action) {
+            into.action.push(action.clone());
+        }
+    }
+    true
+}
+
+// Two statements that only differ in their LeadingKeys patterns are one statement
+// allowing (or denying) either pattern. A deny fires on any matching key, so that
+// always holds. An allow needs every key of the request to match, and a batch
+// request could mix keys of both patterns that neither statement allows on its own.
+fn fold_leading_keys(into: &mut Statement, from: &Statement) -> bool {
+    let Some(operator) = leading_keys_operator(into) else {
+        return false;
+    };
+    let single_key_request = into
+        .action
+        .iter()
+        .all(|action| !matches!(action.as_str(), "dynamodb:BatchGetItem" | "dynamodb:BatchWriteItem"));
+    if into.effect != from.effect
+        || (into.effect == "Allow" && !single_key_request)
+        || into.resource != from.resource
+        || into.action.iter().collect::<BTreeSet<_>>() != from.action.iter().collect::<BTreeSet<_>>()
+        || leading_keys_operator(from) != Some(operator)
+        || condition_sets(&without_leading_keys(&into.condition)) != condition_sets(&without_leading_keys(&from.condition))
+    {
+        return false;
+    }
+    let operator = operator.clone();
+    for value in &from.condition[&operator][LEADING_KEYS] {
+        into.add_condition(&operator, LEADING_KEYS, value.clone());
+    }
+    true
+}
+
+// Merges statements until no two can be merged anymore, keeping the document's
+// meaning while getting it under IAM's size limits for longer
+fn merge_statements(statements: Vec<Statement>) -> Vec<Statement> {
+    let mut merged: Vec<Statement> = vec![];
+    for mut statement in statements {
+        let mut i = 0;
+        while i < merged.len() {
+            // merging into the earlier statement keeps actions and values in the order they were written
+            let mut existing = merged[i].clone();
+            if union_actions(&mut existing, &statement) || fold_leading_keys(&mut existing, &statement) {
+                // the merged statement might now match one that was checked before
+                merged.remove(i);
+                statement = existing;
+                i = 0;
+            } else {
+                i += 1;
+            }
+        }
+        merged.push(statement);
+    }
+    merged
+}
+
+pub(crate) fn compile_document(policy: &Policy, config: &PolicyConfig) -> PolicyDocument {
+    PolicyDocument {
+        version: POLICY_VERSION.to_string(),
+        statement: merge_statements(policy.atoms().iter().flat_map(|atom| compile_atom(atom, config)).collect()),
+    }
+}
+
+
+pub struct IamPolicyCompiler {
+    pub config: PolicyConfig,
+}
+
+impl IamPolicyCompiler {
+    // Leaving a `$sk` filter out makes an allow grant the whole partition and a
+    // deny deny it, so the crate has to say it knows
+    pub fn check_sort_keys(&self, policy: &Policy) -> Result<(), String> {
+        if self.config.allow_sk_filters {
+            return Ok(());
+        }
+        let atoms: Vec<String> = policy
+            .atoms()
+            .iter()
+            .filter(|atom| atom.filters.iter().any(|filter| filter.key() == Some(Key::Sk)))
+            .map(|atom| format!("\n  '{atom}'"))
+            .collect();
+        if atoms.is_empty() {
+            return Ok(());
+        }
+        Err(format!(
+            "IAM can't restrict sort keys, the '$sk' filters of these atoms would be left out of the IAM policy:{}\n\
+             remove them, or set allow_sk_filters = true in [package.metadata.policy_macros] to only check them against the handler's DynamoDB calls",
+            atoms.concat()
+        ))
+    }
+
+    // A variable IAM can't resolve widens a statement to any value in its place,
+    // which is only done for the variables the crate opted in with widen_variables.
+    // A widened deny refuses every value, e.g. a deny of one blocked user's key
+    // would deny every user's.
+    pub fn check_variables(&self, policy: &Policy) -> syn::Result<()> {
+        let mut errors: Option<syn::Error> = None;
+        for atom in policy.atoms() {
+            // $sk filters aren't compiled, see compile_filter
+            let compiled = atom.filters.iter().filter(|filter| filter.key() != Some(Key::Sk));
+            for var in compiled.flat_map(|filter| filter.value().variables()) {
+                let name = var.name();
+                if self.config.variables.contains_key(&name) || self.config.widen_variables.contains(&name) {
+                    continue;
+                }
+                let error = syn::Error::new(
+                    var.span,
+                    format!(
+                        "'${name}' has no IAM policy variable: map it in [package.metadata.policy_macros.variables], \
+                         or add \"{name}\" to widen_variables to {} any value in its place in '{atom}'",
+                        match atom.effect {
+                            Effect::Allow => "allow",
+                            Effect::Deny => "deny",
+                        }
+                    ),
+                );
+                match &mut errors {
+                    Some(errors) => errors.combine(error),
+                    None => errors = Some(error),
+                }
+            }
+        }
+        match errors {
+            Some(errors) => Err(errors),
+            None => Ok(()),
+        }
+    }
+
+    pub fn document(&self, policy: &Policy) -> PolicyDocument {
+        compile_document(policy, &self.config)
+    }
 
-pub struct IamPolicyCompiler {}
+    pub fn to_json(&self, document: &PolicyDocument) -> String {
+        serde_json::to_string_pretty(document).expect("Failed to serialize policy document")
+    }
+
+    // The size IAM counts once deployment filled the ARN placeholders in
+    fn deployed_size<T: Serialize>(&self, value: &T) -> usize {
+        let minified = serde_json::to_string(value).expect("Failed to serialize policy document");
+        let stage = "s".repeat(self.config.max_stage_length.unwrap_or(DEFAULT_MAX_STAGE_LENGTH));
+        DEPLOYED_PLACEHOLDERS
+            .iter()
+            .fold(minified, |text, (placeholder, value)| text.replace(placeholder, value))
+            .replace("${stage}", &stage)
+            .chars()
+            .count()
+    }
+
+    // Deploying a document over the limit fails at put_role_policy or terraform
+    // apply, this catches it while the policy is still being written. The error
+    // lists what each atom costs on its own, before statements are merged.
+    pub fn check_size(&self, policy: &Policy, document: &PolicyDocument) -> Result<(), String> {
+        let limit = self.config.policy_size_limit.unwrap_or(INLINE_ROLE_POLICY_LIMIT);
+        let size = self.deployed_size(document);
+        if size <= limit {
+            return Ok(());
+        }
+        let mut message = format!(
+            "the IAM policy is {size} characters long once minified and deployed, over the {limit} character limit for the role's inline policies"
+        );
+        for (i, atom) in policy.atoms().iter().enumerate() {
+            let atom_size: usize = compile_atom(atom, &self.config).iter().map(|statement| self.deployed_size(statement)).sum();
+            message.push_str(&format!("\n  atom {}: {atom_size} characters for '{atom}'", i + 1));
+        }
+        Err(message)
+    }
+
+    // The function's role gets AWSLambdaBasicExecutionRole and the crate's
+    // managed policies, which attach_role_policy or terraform apply would refuse
+    // past the quota
+    pub fn check_managed_policies(&self) -> Result<(), String> {
+        let limit = self.config.managed_policy_limit.unwrap_or(MANAGED_POLICIES_PER_ROLE);
+        let mut attached: Vec<&str> = vec![BASIC_EXECUTION_ROLE];
+        attached.extend(self.config.managed_policies.iter().map(String::as_str).filter(|arn| *arn != BASIC_EXECUTION_ROLE));
+        if attached.len() <= limit {
+            return Ok(());
+        }
+        Err(format!(
+            "the function's role would have {} managed policies attached, over the quota of {limit} per role:\n  {}\n\
+             remove some of managed_policies, or set managed_policy_limit if the account's quota was raised",
+            attached.len(),
+            attached.join("\n  ")
+        ))
+    }
+}
 
 impl PolicyCompiler for IamPolicyCompiler {
     fn compile_policy(&self, policy: &Policy) -> String {
-        String::from("")
+        self.to_json(&self.document(policy))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn policy(text: &str) -> Policy {
+        syn::parse_str(text).unwrap()
+    }
+
+    fn compiler(config: PolicyConfig) -> IamPolicyCompiler {
+        IamPolicyCompiler { config }
+    }
+
+    fn document(policy: &Policy, config: PolicyConfig) -> serde_json::Value {
+        serde_json::from_str(&compiler(config).compile_policy(policy)).unwrap()
+    }
+
+    #[test]
+    fn read_with_attributes_and_mapped_variable() {
+        let mut config = PolicyConfig::default();
+        config.variables.insert("claims.sub".to_string(), "${aws:PrincipalTag/sub}".to_string());
+        let policy = policy(
+            r#"allow read on table "Users" where key_equals $pk concat("USER#", $claims.sub) with attributes ["full_name" "email"]"#,
+        );
+        assert_eq!(
+            document(&policy, config),
+            json!({
+                "Version": "2012-10-17",
+                "Statement": [{
+                    "Effect": "Allow",
+                    "Action": ["dynamodb:GetItem", "dynamodb:BatchGetItem", "dynamodb:Query"],
+                    "Resource": ["arn:aws:dynamodb:*:*:table/Users"],
+                    "Condition": {
+                        "ForAllValues:StringEquals": {
+                            "dynamodb:Attributes": ["PK", "SK", "full_name", "email"],
+                            "dynamodb:LeadingKeys": ["USER#${aws:PrincipalTag/sub}"]
+                        },
+                        "StringEqualsIfExists": {"dynamodb:Select": ["SPECIFIC_ATTRIBUTES"]}
+                    }
+                }]
+            })
+        );
+    }
+
+    #[test]
+    fn write_with_attributes_next_to_a_deny() {
+        let policy = policy(
+            r#"allow update on table "Users" where key_like $pk "USER#*" with attributes ["email"]
+               deny delete on table "Users" where key_equals $pk "USER#admin""#,
+        );
+        assert_eq!(
+            document(&policy, PolicyConfig::default()),
+            json!({
+                "Version": "2012-10-17",
+                "Statement": [
+                    {
+                        "Effect": "Allow",
+                        "Action": ["dynamodb:UpdateItem"],
+                        "Resource": ["arn:aws:dynamodb:*:*:table/Users"],
+                        "Condition": {
+                            "ForAllValues:StringEquals": {"dynamodb:Attributes": ["PK", "SK", "email"]},
+                            "ForAllValues:StringLike": {"dynamodb:LeadingKeys": ["USER#*"]},
+                            "StringEqualsIfExists": {"dynamodb:ReturnValues": ["NONE", "UPDATED_OLD", "UPDATED_NEW"]}
+                        }
+                    },
+                    {
+                        "Effect": "Deny",
+                        "Action": ["dynamodb:DeleteItem"],
+                        "Resource": ["arn:aws:dynamodb:*:*:table/Users"],
+                        "Condition": {"ForAnyValue:StringEquals": {"dynamodb:LeadingKeys": ["USER#admin"]}}
+                    }
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn deny_attributes_on_an_index() {
+        let policy = policy(r#"deny read on index "by_email" of table "Users" with attributes ["password"]"#);
+        let statement = |condition| {
+            json!({
+                "Effect": "Deny",
+                "Action": ["dynamodb:Query"],
+                "Resource": ["arn:aws:dynamodb:*:*:table/Users/index/by_email"],
+                "Condition": condition
+            })
+        };
+        assert_eq!(
+            document(&policy, PolicyConfig::default()),
+            json!({
+                "Version": "2012-10-17",
+                "Statement": [
+                    statement(json!({"ForAnyValue:StringEquals": {"dynamodb:Attributes": ["password"]}})),
+                    statement(json!({"Null": {"dynamodb:Attributes": ["true"]}}))
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn key_names_come_from_the_table_key_schema() {
+        let mut config = PolicyConfig::default();
+        config.key_schemas.insert("Messages".to_string(), KeySchema { pk: "ChannelId".to_string(), sk: "SentAt".to_string() });
+        let policy = policy(r#"allow put on table "Messages" with attributes ["body"]"#);
+        let document = document(&policy, config);
+        assert_eq!(
+            document["Statement"][0]["Condition"]["ForAllValues:StringEquals"]["dynamodb:Attributes"],
+            json!(["ChannelId", "SentAt", "body"])
+        );
+    }
+
+    #[test]
+    fn sort_key_filters_need_allow_sk_filters() {
+        let policy = policy(r#"allow read on table "Users" where key_equals $pk "USER#1" where key_equals $sk "PROFILE""#);
+        let err = compiler(PolicyConfig::default()).check_sort_keys(&policy).unwrap_err();
+        assert!(err.starts_with("IAM can't restrict sort keys"), "{err}");
+        let config = PolicyConfig { allow_sk_filters: true, ..PolicyConfig::default() };
+        compiler(config).check_sort_keys(&policy).unwrap();
+    }
+
+    #[test]
+    fn kms_decrypt_is_only_granted_with_an_allow() {
+        let config = PolicyConfig { kms_key: Some("arn:aws:kms:us-east-1:000000000000:key/1".to_string()), ..PolicyConfig::default() };
+        let allow = document(&policy(r#"allow read on secret "db""#), config.clone());
+        assert_eq!(
+            allow["Statement"][1],
+            json!({
+                "Effect": "Allow",
+                "Action": ["kms:Decrypt"],
+                "Resource": ["arn:aws:kms:us-east-1:000000000000:key/1"],
+                "Condition": {"StringLike": {"kms:ViaService": ["secretsmanager.*.amazonaws.com"]}}
+            })
+        );
+        let deny = document(&policy(r#"deny read on parameter "db""#), config);
+        let actions: Vec<&serde_json::Value> = deny["Statement"].as_array().unwrap().iter().map(|statement| &statement["Action"]).collect();
+        assert_eq!(actions, [&json!(["ssm:GetParameter", "ssm:GetParameters"])]);
+    }
+
+    #[test]
+    fn unmapped_variable_in_an_allow_is_an_error() {
+        let policy = policy(r#"allow read on table "Users" where key_equals $pk concat("USER#", $user_id)"#);
+        let err = compiler(PolicyConfig::default()).check_variables(&policy).unwrap_err();
+        assert!(err.to_string().starts_with("'$user_id' has no IAM policy variable"), "{err}");
+    }
+
+    #[test]
+    fn bucket_prefixes_scope_the_objects_of_denies_too() {
+        let deny = document(&policy(r#"deny read on bucket "uploads" where prefix "private/""#), PolicyConfig::default());
+        assert_eq!(
+            deny["Statement"],
+            json!([
+                {
+                    "Effect": "Deny",
+                    "Action": ["s3:GetObject"],
+                    "Resource": ["arn:aws:s3:::uploads/private/*"]
+                },
+                {
+                    "Effect": "Deny",
+                    "Action": ["s3:ListBucket"],
+                    "Resource": ["arn:aws:s3:::uploads"],
+                    "Condition": {"StringLikeIfExists": {"s3:prefix": ["private/*"]}}
+                }
+            ])
+        );
+        let allow = document(&policy(r#"allow read on bucket "uploads" where prefix "public/""#), PolicyConfig::default());
+        assert_eq!(allow["Statement"][0]["Resource"], json!(["arn:aws:s3:::uploads/public/*"]));
+        assert_eq!(allow["Statement"][1]["Condition"], json!({"StringLike": {"s3:prefix": ["public/*"]}}));
+    }
+
+    #[test]
+    fn unmapped_variable_in_a_prefix_is_an_error() {
+        let policy = policy(r#"allow read on bucket "uploads" where prefix concat($user_id, "/")"#);
+        assert!(compiler(PolicyConfig::default()).check_variables(&policy).is_err());
+    }
+
+    #[test]
+    fn unmapped_variable_in_a_deny_is_an_error() {
+        let policy = policy(r#"deny read on table "Users" where key_equals $pk $blocked"#);
+        let err = compiler(PolicyConfig::default()).check_variables(&policy).unwrap_err();
+        assert!(err.to_string().ends_with("to deny any value in its place in 'deny read on table \"Users\" where key_equals $pk $blocked'"), "{err}");
+    }
+
+    #[test]
+    fn mapped_and_widened_variables_are_accepted() {
+        let policy = policy(
+            r#"allow read on table "Users" where key_equals $pk concat("USER#", $claims.sub)
+               allow query on table "Users" where key_equals $pk $user_id
+               deny read on table "Users" where key_equals $pk $blocked"#,
+        );
+        let mut config = PolicyConfig::default();
+        config.variables.insert("claims.sub".to_string(), "${aws:PrincipalTag/sub}".to_string());
+        config.widen_variables.insert("user_id".to_string());
+        config.widen_variables.insert("blocked".to_string());
+        let compiler = compiler(config);
+        compiler.check_variables(&policy).unwrap();
+        let document = compiler.compile_policy(&policy);
+        assert!(document.contains(r#""USER#${aws:PrincipalTag/sub}""#), "{document}");
+        assert!(document.contains(r#""*""#), "{document}");
+    }
+
+    #[test]
+    fn size_is_measured_with_the_placeholders_deployed() {
+        let policy = policy(r#"allow read on table "Users""#);
+        let mut config = PolicyConfig::default();
+        config
+            .arn_templates
+            .insert("table".to_string(), "arn:${partition}:dynamodb:${region}:${account}:table/${stage}-{name}".to_string());
+        let compiler = compiler(config.clone());
+        let document = compiler.document(&policy);
+        let minified = serde_json::to_string(&document).unwrap().len();
+        // "aws-us-gov", "ap-southeast-5", 12 digits and 32 characters of stage
+        // in place of 12, 9, 10 and 8 characters of placeholders
+        let deployed = minified + (10 + 14 + 12 + 32) - (12 + 9 + 10 + 8);
+        assert_eq!(compiler.deployed_size(&document), deployed);
+
+        config.policy_size_limit = Some(deployed - 1);
+        let err = IamPolicyCompiler { config: config.clone() }.check_size(&policy, &document).unwrap_err();
+        assert!(err.starts_with(&format!("the IAM policy is {deployed} characters long once minified and deployed")), "{err}");
+        assert!(err.contains("atom 1: "), "{err}");
+        config.max_stage_length = Some(8);
+        IamPolicyCompiler { config }.check_size(&policy, &document).unwrap();
+    }
+
+    #[test]
+    fn managed_policies_are_limited_per_role() {
+        let mut config = PolicyConfig {
+            managed_policies: (0..9).map(|i| format!("arn:aws:iam::123456789012:policy/p{i}")).collect(),
+            ..PolicyConfig::default()
+        };
+        compiler(config.clone()).check_managed_policies().unwrap();
+        config.managed_policies.push("arn:aws:iam::123456789012:policy/p9".to_string());
+        let err = compiler(config.clone()).check_managed_policies().unwrap_err();
+        assert!(err.starts_with("the function's role would have 11 managed policies attached, over the quota of 10 per role"), "{err}");
+        config.managed_policy_limit = Some(20);
+        compiler(config).check_managed_policies().unwrap();
     }
-}
\ No newline at end of file
+}
@@ -1,69 +1,231 @@
-mod parser;
-mod policy;
 mod compiler;
+#[cfg(feature = "iam")]
 mod iam_policy_compiler;
+#[cfg(feature = "cedar")]
+mod cedar_policy_compiler;
+#[cfg(feature = "rego")]
+mod rego_policy_compiler;
+#[cfg(feature = "terraform")]
+mod terraform_policy_compiler;
+mod output;
+mod definitions;
+mod sources;
+mod sdk_calls;
+
+// the DSL is shared with policy_runtime
+use policy_dsl::{bindings, config, optimizer, policy};
 
 use proc_macro::TokenStream;
-use serde_json::json;
+use quote::quote;
 use syn::spanned::Spanned;
-use std::fs::{self, OpenOptions};
-use std::io::{Write};
-use std::path::Path;
-use syn::{parse_macro_input, ItemFn, Error};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use syn::{parse_macro_input, Attribute, ItemFn, Error};
 
+#[cfg(any(feature = "cedar", feature = "rego"))]
 use compiler::PolicyCompiler;
+use config::PolicyConfig;
+#[cfg(feature = "iam")]
 use iam_policy_compiler::IamPolicyCompiler;
+#[cfg(feature = "cedar")]
+use cedar_policy_compiler::CedarPolicyCompiler;
+#[cfg(feature = "rego")]
+use rego_policy_compiler::RegoPolicyCompiler;
+#[cfg(feature = "terraform")]
+use terraform_policy_compiler::TerraformPolicyCompiler;
+
+#[cfg(not(any(feature = "iam", feature = "cedar", feature = "rego")))]
+compile_error!("policy_macros needs at least one backend, enable the \"iam\", \"cedar\" or \"rego\" feature");
+
+fn is_policy_attr(attr: &Attribute) -> bool {
+    attr.path().segments.last().is_some_and(|segment| segment.ident == "policy_attr")
+}
 
 #[proc_macro_attribute]
 pub fn policy_attr(attr: TokenStream, item: TokenStream) -> TokenStream {
-    // parse attr to PolicyForFn struct
-    let item_clone = item.clone();  // we need to return this unchanged at end, so cloning
-    let func = parse_macro_input!(item_clone as ItemFn);
+    let mut func = parse_macro_input!(item as ItemFn);
     let func_name = func.sig.ident.to_string();
 
-    let policy = parse_macro_input!(attr as policy::Policy);
+    let policy_attr = parse_macro_input!(attr as policy::PolicyAttr);
+    let mut items = policy_attr.items;
+
+    // The outermost policy_attr expands first and still sees the ones stacked
+    // below it, so it merges them all into one document and removes them
+    let mut stacked_attrs = vec![];
+    func.attrs.retain(|attr| {
+        if is_policy_attr(attr) {
+            stacked_attrs.push(attr.clone());
+            false
+        } else {
+            true
+        }
+    });
+    for attr in stacked_attrs {
+        match attr.parse_args::<policy::PolicyAttr>() {
+            Ok(stacked_policy) => items.extend(stacked_policy.items),
+            Err(err) => return err.into_compile_error().into(),
+        }
+    }
 
-    // get existing policies
     let crate_root_res = std::env::var("CARGO_MANIFEST_DIR");
-    if let Err(_) = crate_root_res {
+    if crate_root_res.is_err() {
         return Error::new(func.span(), "Could not locate crate root").into_compile_error().into();
     }
     let crate_root = crate_root_res.unwrap();
     let crate_root_path = Path::new(&crate_root);
+
+    let atoms = match definitions::resolve(items, crate_root_path) {
+        Ok(atoms) => atoms,
+        Err(err) => return err.into_compile_error().into(),
+    };
+    let policy = policy::Policy::from_atoms(atoms);
+    if let Err(err) = bindings::check_variables(&policy, &func) {
+        return err.into_compile_error().into();
+    }
+    let written_policy = policy.clone();
+    let policy = policy::Policy::from_atoms(optimizer::minimize(policy.atoms().to_vec()));
+
     let crate_policies_path = crate_root_path.join("policies");
     if !crate_policies_path.exists() {
         let create_dir_result = fs::create_dir(&crate_policies_path);
-        if let Err(_) = create_dir_result {
+        if create_dir_result.is_err() {
             return Error::new(func.span(), "Could not create policies directory").into_compile_error().into();
         }
     }
-    let policy_file = crate_policies_path.join(format!("{}.json", func_name));
-    if let Some(parent) = policy_file.parent() {
-       fs::create_dir_all(parent).expect("Failed to create output directory");
-    }
-    let mut policies: Vec<policy::Policy> = vec![];
-    if policy_file.exists() {
-        let content = fs::read_to_string(&policy_file).unwrap_or_else(|_| "[]".to_string());
-        policies = serde_json::from_str(&content).unwrap_or_else(|_| vec![])
+    let config = match PolicyConfig::load(crate_root_path) {
+        Ok(config) => config,
+        Err(err) => return Error::new(func.span(), err).into_compile_error().into(),
+    };
+    let file = proc_macro::Span::call_site().local_file();
+    let warnings = match sdk_calls::check_calls(&policy, &func, file.as_deref(), crate_root_path, &config) {
+        Ok(warnings) => warnings,
+        Err(err) => return err.into_compile_error().into(),
     };
 
-    policies.push(policy.clone());
-
-    let compiler = IamPolicyCompiler {};
-    let iam_json = compiler.compile_policy(&policy);
-
-    // write back to file as iam json
-    let json_content =
-        serde_json::to_string_pretty(&iam_json).expect("Failed to serialize policies");
-        
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .open(&policy_file)
-        .expect("Failed to open file");
-    file.write_all(json_content.as_bytes()).expect("Failed to write to file");
-    // Return function unchanged
-    return item;
-}
+    // The policy as written (with `use` items resolved) for tooling that works on
+    // the DSL instead of IAM, it prints back to DSL text through its Display impl
+    let mut outputs = vec![(
+        crate_policies_path.join(format!("{}.policy.json", func_name)),
+        serde_json::to_string_pretty(&written_policy).expect("Failed to serialize policy"),
+    )];
 
+    #[cfg(feature = "iam")]
+    {
+        let compiler = IamPolicyCompiler { config: config.clone() };
+        if let Err(err) = compiler.check_variables(&policy) {
+            return err.into_compile_error().into();
+        }
+        if let Err(err) = compiler.check_sort_keys(&policy) {
+            return Error::new(func.span(), err).into_compile_error().into();
+        }
+        let document = compiler.document(&policy);
+        if let Err(err) = compiler.check_size(&policy, &document).and_then(|_| compiler.check_managed_policies()) {
+            return Error::new(func.span(), err).into_compile_error().into();
+        }
+        outputs.push((crate_policies_path.join(format!("{}.json", func_name)), compiler.to_json(&document)));
 
+        // Picked up by the Terraform template generated by lambda_macros, which then
+        // leaves the function out of its own role policies. It wraps the document
+        // checked above rather than compiling the policy again.
+        #[cfg(feature = "terraform")]
+        {
+            let compiler = TerraformPolicyCompiler { config: config.clone(), function: func_name.clone() };
+            outputs.push((
+                crate_root_path.join("terraform").join(format!("{}.policy.tf.json", func_name)),
+                compiler.compile_iam_document(&document),
+            ));
+        }
+    }
+
+    #[cfg(feature = "cedar")]
+    {
+        let compiler = CedarPolicyCompiler { config: config.clone() };
+        if let Err(err) = compiler.check_widening(&policy) {
+            return err.into_compile_error().into();
+        }
+        outputs.push((crate_policies_path.join(format!("{}.cedar", func_name)), compiler.compile_policy(&policy)));
+    }
+
+    #[cfg(feature = "rego")]
+    {
+        let compiler = RegoPolicyCompiler { config: config.clone(), package: format!("policies.{}", func_name) };
+        outputs.push((crate_policies_path.join(format!("{}.rego", func_name)), compiler.compile_policy(&policy)));
+    }
+
+    // The files only depend on the attributes, so they're rewritten from scratch
+    // on every expansion instead of being merged with what's on disk. The
+    // manifest is held while they're written, since other targets of the crate
+    // expand in parallel and may remove files.
+    let written = output::Manifest::lock(crate_root_path).and_then(|mut manifest| {
+        for (path, contents) in &outputs {
+            output::write_if_changed(path, contents)
+                .map_err(|err| io::Error::new(err.kind(), format!("Could not write {}: {err}", path.display())))?;
+        }
+        // A backend that's turned off doesn't leave its last document behind, and
+        // a function that's gone doesn't leave its files
+        let paths: Vec<PathBuf> = outputs.iter().map(|(path, _)| path.clone()).collect();
+        manifest.record(&func_name, file.as_deref(), &paths)?;
+        manifest.remove_stale()?;
+        manifest.save()
+    });
+    if let Err(err) = written {
+        return Error::new(func.span(), format!("Could not update the generated files: {err}")).into_compile_error().into();
+    }
+
+    // Attributes the handler's DynamoDB calls to it while a policy_runtime
+    // CallRecorder records them, done last so the checks above see the body as written
+    #[cfg(feature = "record")]
+    {
+        // a sync handler's calls would be recorded as made outside of any handler
+        if func.sig.asyncness.is_none() {
+            return Error::new(
+                func.sig.fn_token.span,
+                format!("'{func_name}' isn't async, so the record feature can't attribute the calls it makes to it"),
+            )
+            .into_compile_error()
+            .into();
+        }
+        let block = &func.block;
+        func.block = syn::parse_quote!({ ::policy_runtime::record_as(#func_name, async move #block).await });
+    }
+
+    // Registering the files as inputs of the crate makes cargo rebuild it, and so
+    // regenerate them, if one is edited or deleted. The consts go in the body,
+    // since the handler can be a method where an item next to it couldn't.
+    for (path, _) in &outputs {
+        let path = path.to_string_lossy();
+        func.block.stmts.insert(0, syn::parse_quote!(const _: &str = include_str!(#path);));
+    }
+    // Stable proc macros can't emit warnings, but using a deprecated item makes
+    // rustc print its note as one, pointed at the handler
+    let name_span = func.sig.ident.span();
+    for warning in warnings {
+        func.block.stmts.insert(
+            0,
+            syn::parse_quote_spanned!(name_span=> {
+                #[deprecated(note = #warning)]
+                #[allow(non_upper_case_globals)]
+                const policy_warning: () = ();
+                let () = policy_warning;
+            }),
+        );
+    }
+    quote!(#func).into()
+}
+
+// Names a policy so handlers can share it, e.g.
+//
+// define_policy!(read_profile(user) = allow read on table "Users" where key_equals $pk concat("USER#", $user));
+//
+// #[policy_attr(use read_profile($user_id))]
+//
+// policy_attr finds definitions by reading the crate's sources, so they can be
+// in any module. A name defined in more than one module is used with its path
+// from the crate root, e.g. `use profile::read_profile($user_id)`. Expanding
+// this only checks the definition.
+#[proc_macro]
+pub fn define_policy(input: TokenStream) -> TokenStream {
+    parse_macro_input!(input as policy::PolicyDefinition);
+    TokenStream::new()
+}
@@ -0,0 +1,182 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use proc_macro2::{TokenStream, TokenTree};
+use serde::{Deserialize, Serialize};
+
+// Writes generated files through a temporary file and a rename, so a build that
+// dies halfway never leaves a truncated document behind. Files whose contents
+// didn't change are left alone to keep their timestamps stable.
+pub fn write_if_changed(path: &Path, contents: &str) -> io::Result<()> {
+    if fs::read_to_string(path).is_ok_and(|existing| existing == contents) {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file_name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    let tmp_path = path.with_file_name(format!(".{file_name}.{}.tmp", std::process::id()));
+    fs::write(&tmp_path, contents)?;
+    fs::rename(&tmp_path, path)
+}
+
+// Another target of the crate may have removed it first
+pub fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+// What policy_attr wrote, kept in policies/.generated.json so files are only
+// ever removed by the expansion of a function that wrote them or once that
+// function is gone, never because of their name. Each function's entry has the
+// files it generated and the source files it was expanded from, both relative
+// to the crate root.
+#[derive(Default, Serialize, Deserialize)]
+struct Generated {
+    sources: BTreeSet<PathBuf>,
+    files: BTreeSet<PathBuf>,
+}
+
+// The manifest, locked until it's dropped: every target of the crate expands
+// its handlers in its own rustc, and they share the manifest and the files
+pub struct Manifest {
+    file: File,
+    crate_root: PathBuf,
+    functions: BTreeMap<String, Generated>,
+}
+
+impl Manifest {
+    pub fn lock(crate_root: &Path) -> io::Result<Manifest> {
+        let path = crate_root.join("policies").join(".generated.json");
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+        file.lock()?;
+        let mut text = String::new();
+        file.read_to_string(&mut text)?;
+        // a manifest that doesn't parse is started over, which only means files
+        // written before it are left alone
+        let functions = serde_json::from_str(&text).unwrap_or_default();
+        Ok(Manifest { file, crate_root: crate_root.to_path_buf(), functions })
+    }
+
+    fn relative(&self, path: &Path) -> PathBuf {
+        let root = fs::canonicalize(&self.crate_root).unwrap_or_else(|_| self.crate_root.clone());
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        path.strip_prefix(&root).map(Path::to_path_buf).unwrap_or(path)
+    }
+
+    // Records `files` as what `function`, expanded from `source`, generates. The
+    // files it generated before and no longer does, e.g. for a backend that was
+    // turned off, are removed.
+    pub fn record(&mut self, function: &str, source: Option<&Path>, files: &[PathBuf]) -> io::Result<()> {
+        let files: BTreeSet<PathBuf> = files.iter().map(|file| self.relative(file)).collect();
+        let source = source.map(|source| self.relative(source));
+        let previous = self.functions.remove(function).unwrap_or_default();
+        for file in previous.files.difference(&files) {
+            remove_if_exists(&self.crate_root.join(file))?;
+        }
+        // a function moved to another file is still generated from the new one
+        let mut sources: BTreeSet<PathBuf> =
+            previous.sources.into_iter().filter(|old| mentions(&self.crate_root.join(old), function)).collect();
+        sources.extend(source);
+        self.functions.insert(function.to_string(), Generated { sources, files });
+        Ok(())
+    }
+
+    // Removes the files of the functions none of whose source files mention
+    // them anymore, e.g. after a rename. A function that's still named in its
+    // file keeps them even if it's now written by a macro or behind a cfg.
+    pub fn remove_stale(&mut self) -> io::Result<()> {
+        let stale: Vec<String> = self
+            .functions
+            .iter()
+            .filter(|(function, generated)| {
+                !generated.sources.is_empty()
+                    && !generated.sources.iter().any(|source| mentions(&self.crate_root.join(source), function))
+            })
+            .map(|(function, _)| function.clone())
+            .collect();
+        for function in stale {
+            if let Some(generated) = self.functions.remove(&function) {
+                for file in &generated.files {
+                    remove_if_exists(&self.crate_root.join(file))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn save(mut self) -> io::Result<()> {
+        let text = serde_json::to_string_pretty(&self.functions).map_err(io::Error::other)?;
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(text.as_bytes())
+    }
+}
+
+// Whether `name` is an identifier anywhere in the file, including in macro
+// invocations. A file that can't be read or lexed counts as mentioning it.
+fn mentions(file: &Path, name: &str) -> bool {
+    fn any_ident(tokens: TokenStream, name: &str) -> bool {
+        tokens.into_iter().any(|token| match token {
+            TokenTree::Ident(ident) => ident == name,
+            TokenTree::Group(group) => any_ident(group.stream(), name),
+            _ => false,
+        })
+    }
+    match fs::read_to_string(file) {
+        Ok(text) => text.parse::<TokenStream>().map_or(true, |tokens| any_ident(tokens, name)),
+        Err(err) => err.kind() != io::ErrorKind::NotFound,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(dir: &Path) -> Vec<String> {
+        let mut files: Vec<String> =
+            fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().to_string()).collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn only_removes_files_it_recorded() {
+        let root = std::env::temp_dir().join(format!("policy_macros_output_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let policies = root.join("policies");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(&policies).unwrap();
+        let main = root.join("src/main.rs");
+        fs::write(&main, "#[policy_attr(allow read on table \"Users\")] fn kept() {}\nfn renamed() {}").unwrap();
+        for file in ["kept.json", "kept.rego", "renamed.json", "handwritten.json"] {
+            fs::write(policies.join(file), "").unwrap();
+        }
+
+        let mut manifest = Manifest::lock(&root).unwrap();
+        manifest.record("kept", Some(&main), &[policies.join("kept.json"), policies.join("kept.rego")]).unwrap();
+        manifest.record("renamed", Some(&main), &[policies.join("renamed.json")]).unwrap();
+        manifest.save().unwrap();
+
+        // the rego backend was turned off and `renamed` was renamed
+        fs::write(&main, "#[policy_attr(allow read on table \"Users\")] fn kept() {}\nfn other() {}").unwrap();
+        let mut manifest = Manifest::lock(&root).unwrap();
+        manifest.record("kept", Some(&main), &[policies.join("kept.json")]).unwrap();
+        manifest.remove_stale().unwrap();
+        manifest.save().unwrap();
+        assert_eq!(files(&policies), [".generated.json", "handwritten.json", "kept.json"]);
+
+        let manifest = Manifest::lock(&root).unwrap();
+        assert_eq!(manifest.functions.keys().collect::<Vec<_>>(), ["kept"]);
+        assert_eq!(manifest.functions["kept"].sources, BTreeSet::from([PathBuf::from("src/main.rs")]));
+        drop(manifest);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,281 @@
+use crate::compiler::PolicyCompiler;
+use crate::config::PolicyConfig;
+use crate::policy::{Effect, Filter, Key, Policy, PolicyAtom, Resource, StringExpr, Var};
+
+// Compiles a policy into a Rego module whose `allow` answers whether a DynamoDB
+// request is allowed, for an input like
+//
+// {
+//   "table": "Users",
+//   "index": "by_email",
+//   "action": "GetItem",
+//   "key": {"pk": "USER#1", "sk": "PROFILE"},
+//   "attributes": ["full_name", "email"],
+//   "return_values": "ALL_OLD",
+//   "context": {"claims": {"sub": "1"}}
+// }
+//
+// `index` is only there for requests on an index, `attributes` only when the
+// request names the attributes it reads or writes and `return_values` only when
+// a write asks for items back. `$vars` are read from
+// `input.context`. Atoms on other kinds of resources can't match such a request
+// and are left out, and so are IAM `when` conditions, which IAM checks anyway.
+
+// Rego keywords can't be used after a '.'
+const RESERVED: [&str; 14] = [
+    "as", "contains", "default", "else", "every", "false", "if", "import", "in", "not", "null", "package", "some", "true",
+];
+
+fn str_literal(value: &str) -> String {
+    serde_json::to_string(value).expect("Failed to serialize string")
+}
+
+fn string_set<'a>(values: impl IntoIterator<Item = &'a str>) -> String {
+    let values: Vec<String> = values.into_iter().map(str_literal).collect();
+    format!("{{{}}}", values.join(", "))
+}
+
+fn variable(var: &Var) -> String {
+    let mut access = "input.context".to_string();
+    for field in &var.path {
+        if RESERVED.contains(&field.as_str()) {
+            access = format!("{access}[{}]", str_literal(field));
+        } else {
+            access = format!("{access}.{field}");
+        }
+    }
+    access
+}
+
+// Literal parts of a glob pattern keep their meaning, apart from '*' and '?'
+// when they come from a key_like pattern
+fn escape_glob(lit: &str, wildcards: bool) -> String {
+    lit.chars()
+        .map(|c| match c {
+            '*' | '?' if wildcards => c.to_string(),
+            '*' | '?' | '[' | ']' | '{' | '}' | '\\' => format!("\\{c}"),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+fn collect_parts(expr: &StringExpr, parts: &mut Vec<String>, glob: Option<bool>) {
+    match expr {
+        StringExpr::Literal(lit) => parts.push(str_literal(&match glob {
+            Some(wildcards) => escape_glob(lit, wildcards),
+            None => lit.clone(),
+        })),
+        // a variable's value is matched as it is, not as a pattern
+        StringExpr::Variable(var) if glob.is_some() => parts.push(format!("glob.quote_meta({})", variable(var))),
+        StringExpr::Variable(var) => parts.push(variable(var)),
+        StringExpr::Concat(left, right) => {
+            collect_parts(left, parts, glob);
+            collect_parts(right, parts, glob);
+        }
+    }
+}
+
+// The expression as a Rego string. When `glob` is set the literal parts are
+// escaped for glob.match, keeping their wildcards if it's true.
+fn string_expr(expr: &StringExpr, glob: Option<bool>) -> String {
+    let mut expr_parts = vec![];
+    collect_parts(expr, &mut expr_parts, glob);
+    match expr_parts.as_slice() {
+        [part] => part.clone(),
+        _ => format!("concat(\"\", [{}])", expr_parts.join(", ")),
+    }
+}
+
+fn compile_filter(filter: &Filter) -> Option<String> {
+    let key_attribute = |key: &Key| match key {
+        Key::Pk => "input.key.pk",
+        Key::Sk => "input.key.sk",
+    };
+    match filter {
+        Filter::KeyEquals(key, expr) => Some(format!("{} == {}", key_attribute(key), string_expr(expr, None))),
+        Filter::KeyLike(key, expr) => {
+            Some(format!("glob.match({}, null, {})", string_expr(expr, Some(true)), key_attribute(key)))
+        }
+        // only valid on buckets, which are left out
+        Filter::Prefix(_) => None,
+    }
+}
+
+// Each body is a rule on its own, a rule with several bodies holds when any of them does
+fn compile_atom(atom: &PolicyAtom, config: &PolicyConfig) -> Vec<Vec<String>> {
+    let (table, index) = match &atom.resource {
+        Resource::Table(table) => (table, None),
+        Resource::Index { index, table } => (table, Some(index)),
+        _ => return vec![],
+    };
+    let mut body = vec![format!("input.table == {}", str_literal(table))];
+    match index {
+        Some(index) => body.push(format!("input.index == {}", str_literal(index))),
+        None => body.push("not input.index".to_string()),
+    }
+    body.push(format!(
+        "input.action in {}",
+        string_set(atom.action.dynamodb_operations(index.is_some()).iter().copied())
+    ));
+    body.extend(atom.filters.iter().filter_map(compile_filter));
+    let Some(fields) = &atom.attributes else {
+        return vec![body];
+    };
+    let fields: Vec<&str> = fields.iter().map(|field| field.0.as_str()).collect();
+    // Same rules as the IAM backend: a read that doesn't name its attributes gets
+    // all of them, and so does a write returning whole items
+    let whole_items = string_set(["ALL_OLD", "ALL_NEW"]);
+    match atom.effect {
+        Effect::Allow => {
+            body.push("input.attributes".to_string());
+            body.push(format!(
+                "every attribute in input.attributes {{ attribute in {} }}",
+                string_set(config.key_schema(table).attributes().into_iter().chain(fields))
+            ));
+            if !atom.action.is_read() {
+                body.push(format!("not input.return_values in {whole_items}"));
+            }
+            vec![body]
+        }
+        Effect::Deny => {
+            let mut named = body.clone();
+            named.push("some attribute in input.attributes".to_string());
+            named.push(format!("attribute in {}", string_set(fields)));
+            let mut unnamed = body;
+            if atom.action.is_read() {
+                unnamed.push("not input.attributes".to_string());
+            } else {
+                unnamed.push(format!("input.return_values in {whole_items}"));
+            }
+            vec![named, unnamed]
+        }
+    }
+}
+
+pub struct RegoPolicyCompiler {
+    pub config: PolicyConfig,
+    // usually `policies.<function name>`
+    pub package: String,
+}
+
+impl PolicyCompiler for RegoPolicyCompiler {
+    fn compile_policy(&self, policy: &Policy) -> String {
+        let mut module = format!(
+            "package {}\n\nimport rego.v1\n\ndefault allow := false\n\ndefault allowed := false\n\ndefault denied := false\n\nallow if {{\n\tallowed\n\tnot denied\n}}\n",
+            self.package
+        );
+        for atom in policy.atoms() {
+            let bodies = compile_atom(atom, &self.config);
+            if bodies.is_empty() {
+                module.push_str(&format!("\n# {atom}\n# doesn't apply to DynamoDB requests\n"));
+                continue;
+            }
+            // an allow that can't check its conditions would grant more than it says
+            if atom.effect == Effect::Allow && !atom.conditions.is_empty() {
+                module.push_str(&format!("\n# {atom}\n# 'when' conditions can only be checked by IAM\n"));
+                continue;
+            }
+            let rule = match atom.effect {
+                Effect::Allow => "allowed",
+                Effect::Deny => "denied",
+            };
+            module.push_str(&format!("\n# {atom}\n"));
+            for body in bodies {
+                module.push_str(&format!("{rule} if {{\n\t{}\n}}\n", body.join("\n\t")));
+            }
+        }
+        module
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(text: &str) -> String {
+        let policy: Policy = syn::parse_str(text).unwrap();
+        RegoPolicyCompiler { config: PolicyConfig::default(), package: "policies.handler".to_string() }.compile_policy(&policy)
+    }
+
+    const HEADER: &str = "package policies.handler
+
+import rego.v1
+
+default allow := false
+
+default allowed := false
+
+default denied := false
+
+allow if {
+\tallowed
+\tnot denied
+}
+";
+
+    #[test]
+    fn read_with_variables_in_patterns() {
+        let rego = compile(
+            r#"allow read on table "Users" where key_equals $pk concat("USER#", $claims.sub) where key_like $sk concat(concat("FRIEND#", $friend), "*") with attributes ["email"]"#,
+        );
+        let expected = r#"
+# allow read on table "Users" where key_equals $pk concat("USER#", $claims.sub) where key_like $sk concat(concat("FRIEND#", $friend), "*") with attributes ["email"]
+allowed if {
+	input.table == "Users"
+	not input.index
+	input.action in {"GetItem", "BatchGetItem", "Query"}
+	input.key.pk == concat("", ["USER#", input.context.claims.sub])
+	glob.match(concat("", ["FRIEND#", glob.quote_meta(input.context.friend), "*"]), null, input.key.sk)
+	input.attributes
+	every attribute in input.attributes { attribute in {"PK", "SK", "email"} }
+}
+"#;
+        assert_eq!(rego, format!("{HEADER}{expected}"));
+    }
+
+    #[test]
+    fn writes_check_the_items_they_return() {
+        let rego = compile(
+            r#"allow update on table "Users" with attributes ["email"]
+               deny update on table "Users" with attributes ["role"]"#,
+        );
+        let expected = r#"
+# allow update on table "Users" with attributes ["email"]
+allowed if {
+	input.table == "Users"
+	not input.index
+	input.action in {"UpdateItem"}
+	input.attributes
+	every attribute in input.attributes { attribute in {"PK", "SK", "email"} }
+	not input.return_values in {"ALL_OLD", "ALL_NEW"}
+}
+
+# deny update on table "Users" with attributes ["role"]
+denied if {
+	input.table == "Users"
+	not input.index
+	input.action in {"UpdateItem"}
+	some attribute in input.attributes
+	attribute in {"role"}
+}
+denied if {
+	input.table == "Users"
+	not input.index
+	input.action in {"UpdateItem"}
+	input.return_values in {"ALL_OLD", "ALL_NEW"}
+}
+"#;
+        assert_eq!(rego, format!("{HEADER}{expected}"));
+    }
+
+    #[test]
+    fn atoms_rego_cant_check_are_comments() {
+        let rego = compile(
+            r#"allow send on queue "jobs"
+               allow get on table "Users" when aws:SourceIp ip_in "10.0.0.0/8""#,
+        );
+        assert!(rego.contains("# allow send on queue \"jobs\"\n# doesn't apply to DynamoDB requests\n"), "{rego}");
+        assert!(rego.contains("# 'when' conditions can only be checked by IAM\n"), "{rego}");
+        assert!(!rego.contains("allowed if"), "{rego}");
+    }
+}
@@ -0,0 +1,1255 @@
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::path::Path;
+
+use quote::ToTokens;
+use syn::punctuated::Punctuated;
+use syn::visit::{self, Visit};
+use syn::{Error, Expr, ExprCall, ExprMethodCall, FnArg, ItemFn, Lit, Local, Macro, Member, Pat, Stmt, Token};
+
+use crate::config::{KeySchema, PolicyConfig};
+use crate::optimizer::pattern_covers;
+use crate::policy::{Action, Effect, Filter, Key, Policy, PolicyAtom, Resource, StringExpr};
+use crate::sources::{type_name, Function, Sources};
+
+// Checks the DynamoDB calls a handler makes against its policy, so a handler
+// can't do what its policy doesn't say. The handler's body is walked along with
+// every function of the crate it calls, looking for SDK builder chains like
+//
+// client.query().table_name(table_name).key_condition_expression("PK = :pk").expression_attribute_values(":pk", ..).send()
+//
+// Calls are followed into the crate's functions and methods, wherever their
+// modules are and however they're imported. A method call is followed into the
+// methods of its receiver's type when that's known from `self`, a parameter, a
+// typed `let` or a struct literal, or a field of one of those. A receiver of
+// unknown type is only followed when the crate has a single method of that name.
+// Strings are followed through `let`s, parameters, format! and the crate's own
+// functions, anything else stands for any string. Table names that are read
+// from somewhere else, like `state.user_table_name`, are looked up in the
+// `tables` table of the crate's config.
+//
+// A transaction's items are checked one by one as the single-item requests
+// they're authorized as, when they're built in the call, e.g.
+//
+// client.transact_write_items().transact_items(TransactWriteItem::builder().put(Put::builder().table_name("Users")..build()?).build()).send()
+
+// SDK builder methods -> the DynamoDB operation they send
+const OPERATIONS: [(&str, &str); 8] = [
+    ("get_item", "GetItem"),
+    ("put_item", "PutItem"),
+    ("update_item", "UpdateItem"),
+    ("delete_item", "DeleteItem"),
+    ("query", "Query"),
+    ("scan", "Scan"),
+    ("batch_get_item", "BatchGetItem"),
+    ("batch_write_item", "BatchWriteItem"),
+];
+
+// SDK builder methods -> the transaction they send and the action allowing it
+const TRANSACTIONS: [(&str, &str, Action); 2] = [
+    ("transact_get_items", "TransactGetItems", Action::TransactGet),
+    ("transact_write_items", "TransactWriteItems", Action::TransactWrite),
+];
+
+// The builders of a transaction's items -> the request each item is authorized as
+const TRANSACTION_ITEMS: [(&str, &str); 5] = [
+    ("Get", "GetItem"),
+    ("Put", "PutItem"),
+    ("Update", "UpdateItem"),
+    ("Delete", "DeleteItem"),
+    ("ConditionCheck", "ConditionCheckItem"),
+];
+
+// PartiQL statements have no action in the DSL, so no policy can allow them
+const UNCHECKED_OPERATIONS: [(&str, &str); 3] = [
+    ("execute_statement", "ExecuteStatement"),
+    ("batch_execute_statement", "BatchExecuteStatement"),
+    ("execute_transaction", "ExecuteTransaction"),
+];
+
+const READ_OPERATIONS: [&str; 4] = ["GetItem", "Query", "Scan", "BatchGetItem"];
+
+// Calls into the crate are followed this deep
+const MAX_DEPTH: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Any,
+}
+
+// What the code could pass as a string: the parts that are known, and the
+// expression it was read from when nothing is
+#[derive(Debug, Clone)]
+struct Value {
+    parts: Vec<Part>,
+    source: Option<String>,
+}
+
+impl Value {
+    fn literal(lit: &str) -> Self {
+        Value { parts: vec![Part::Literal(lit.to_string())], source: None }
+    }
+
+    fn any(source: Option<String>) -> Self {
+        Value { parts: vec![Part::Any], source }
+    }
+
+    fn concat(values: Vec<Value>) -> Self {
+        Value { parts: values.into_iter().flat_map(|value| value.parts).collect(), source: None }
+    }
+
+    fn as_literal(&self) -> Option<String> {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(lit) => Some(lit.as_str()),
+                Part::Any => None,
+            })
+            .collect()
+    }
+
+    // The value as a glob template for optimizer::pattern_covers
+    fn template(&self) -> Vec<(char, bool)> {
+        self.parts
+            .iter()
+            .flat_map(|part| match part {
+                Part::Literal(lit) => lit.chars().map(|c| (c, false)).collect(),
+                Part::Any => vec![('*', true)],
+            })
+            .collect()
+    }
+
+    fn pattern(&self) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(lit) => lit.as_str(),
+                Part::Any => "*",
+            })
+            .collect()
+    }
+}
+
+fn source_text(expr: &Expr) -> String {
+    expr.to_token_stream().to_string().chars().filter(|c| !c.is_whitespace()).collect()
+}
+
+fn str_literal(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Lit(expr_lit) => match &expr_lit.lit {
+            Lit::Str(lit) => Some(lit.value()),
+            _ => None,
+        },
+        Expr::Reference(reference) => str_literal(&reference.expr),
+        Expr::Paren(paren) => str_literal(&paren.expr),
+        _ => None,
+    }
+}
+
+// Binds the arguments of a call to the callee's parameters. A method called
+// like `Type::method(receiver, ..)` gets its receiver as the first argument.
+fn bind_params(func: &ItemFn, args: Vec<Value>, method_call: bool) -> HashMap<String, Value> {
+    let has_receiver = matches!(func.sig.inputs.first(), Some(FnArg::Receiver(_)));
+    let args = args.into_iter().skip(usize::from(has_receiver && !method_call));
+    let mut env = HashMap::new();
+    for (input, arg) in func.sig.inputs.iter().filter(|input| matches!(input, FnArg::Typed(_))).zip(args) {
+        if let FnArg::Typed(pat_type) = input {
+            if let Pat::Ident(pat_ident) = &*pat_type.pat {
+                env.insert(pat_ident.ident.to_string(), arg);
+            }
+        }
+    }
+    env
+}
+
+fn resolve_self(type_name: String, function: &Function) -> Option<String> {
+    match type_name.as_str() {
+        "Self" => function.self_type.clone(),
+        _ => Some(type_name),
+    }
+}
+
+// The types of `func`'s parameters, which receivers are resolved with
+fn param_types(func: &ItemFn, function: &Function) -> HashMap<String, String> {
+    func.sig
+        .inputs
+        .iter()
+        .filter_map(|input| match input {
+            FnArg::Typed(pat_type) => match &*pat_type.pat {
+                Pat::Ident(pat_ident) => {
+                    Some((pat_ident.ident.to_string(), resolve_self(type_name(&pat_type.ty)?, function)?))
+                }
+                _ => None,
+            },
+            FnArg::Receiver(_) => None,
+        })
+        .collect()
+}
+
+// The type of a `let store: Store = ..` or `let store = Store { .. }`
+fn local_type(local: &Local, function: &Function) -> Option<(String, String)> {
+    let (name, type_name) = match (&local.pat, &local.init) {
+        (Pat::Type(pat_type), _) => match &*pat_type.pat {
+            Pat::Ident(pat_ident) => (pat_ident.ident.to_string(), type_name(&pat_type.ty)?),
+            _ => return None,
+        },
+        (Pat::Ident(pat_ident), Some(init)) => match &*init.expr {
+            Expr::Struct(expr_struct) => {
+                (pat_ident.ident.to_string(), expr_struct.path.segments.last()?.ident.to_string())
+            }
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some((name, resolve_self(type_name, function)?))
+}
+
+// e.g. `util::get_profile`, or `get_profile` for a function of the crate root
+fn location(sources: &Sources, module: usize, function: &Function) -> String {
+    let mut path = sources.modules[module].path.clone();
+    path.extend(function.self_type.clone());
+    path.push(function.name.clone());
+    path.join("::")
+}
+
+#[derive(Debug)]
+enum Attributes {
+    // a read without a projection, which gets every attribute
+    Unnamed,
+    Named(Vec<String>),
+    // update and delete expressions aren't parsed, nor are strings built at runtime
+    Unknown,
+}
+
+// One DynamoDB request found in the code
+#[derive(Debug)]
+struct SdkCall {
+    operation: &'static str,
+    table: Value,
+    index: Option<Value>,
+    // attribute -> value, for the key attributes and the attributes of a put,
+    // which are only told apart once the table is known
+    keys: HashMap<String, Value>,
+    attributes: Attributes,
+    // where the call was found, e.g. `util::get_profile`
+    location: String,
+    // the transaction it's an item of, e.g. "TransactWriteItems"
+    transaction: Option<&'static str>,
+}
+
+impl SdkCall {
+    fn key(&self, keys: &KeySchema, key: Key) -> Value {
+        self.keys.get(keys.attribute(key)).cloned().unwrap_or_else(|| Value::any(None))
+    }
+}
+
+struct CallFinder<'a> {
+    sources: &'a Sources,
+    targets: Vec<usize>,
+    // the function being walked and its module
+    module: usize,
+    function: &'a Function,
+    env: HashMap<String, Value>,
+    // variable -> the name of its type, when it's known
+    types: HashMap<String, String>,
+    stack: Vec<String>,
+    calls: Vec<SdkCall>,
+    // (operation, location) of the requests that can't be checked
+    unchecked: Vec<(&'static str, String)>,
+    // (operation, action, location) of the transactions whose items aren't built
+    // in the call
+    unread: Vec<(&'static str, Action, String)>,
+}
+
+impl<'a> CallFinder<'a> {
+    fn eval(&self, expr: &Expr) -> Value {
+        self.eval_in(expr, (self.module, self.function), &self.env, &self.types, self.stack.len())
+    }
+
+    // The functions a call like `util::get_profile(..)` or `Self::key(..)` can reach
+    fn callees(&self, path: &syn::Path, (module, function): (usize, &Function)) -> Vec<(usize, &'a Function)> {
+        let segments: Vec<String> = path.segments.iter().map(|segment| segment.ident.to_string()).collect();
+        match (segments.as_slice(), &function.self_type) {
+            ([first, name], Some(self_type)) if first == "Self" => self.sources.methods(&self.targets, name, Some(self_type)),
+            (segments, _) => match self.sources.resolve(module, segments) {
+                Some((target, path)) => self.sources.functions(target, &path),
+                None => vec![],
+            },
+        }
+    }
+
+    // The methods `receiver.method(..)` can reach, SDK builder methods are left
+    // to sdk_call. A method its receiver's type doesn't have itself, like a
+    // trait's default method, is treated as one of an unknown type.
+    fn method_callees(&self, call: &ExprMethodCall, function: &Function, types: &HashMap<String, String>) -> Vec<(usize, &'a Function)> {
+        let method = call.method.to_string();
+        let builder = OPERATIONS.iter().chain(&UNCHECKED_OPERATIONS).any(|(name, _)| *name == method)
+            || TRANSACTIONS.iter().any(|(name, ..)| *name == method);
+        if builder || method == "send" {
+            return vec![];
+        }
+        let methods = self.sources.methods(&self.targets, &method, None);
+        let receiver_type = self.receiver_type(&call.receiver, function, types);
+        let typed: Vec<(usize, &'a Function)> = methods
+            .iter()
+            .copied()
+            .filter(|(_, callee)| receiver_type.is_some() && callee.self_type == receiver_type)
+            .collect();
+        match (typed.is_empty(), methods.len()) {
+            (false, _) => typed,
+            (true, 1) => methods,
+            // which of them it is can't be told
+            (true, _) => vec![],
+        }
+    }
+
+    fn receiver_type(&self, receiver: &Expr, function: &Function, types: &HashMap<String, String>) -> Option<String> {
+        match receiver {
+            Expr::Reference(reference) => self.receiver_type(&reference.expr, function, types),
+            Expr::Paren(paren) => self.receiver_type(&paren.expr, function, types),
+            Expr::Group(group) => self.receiver_type(&group.expr, function, types),
+            Expr::Path(path) => match path.path.get_ident()?.to_string().as_str() {
+                "self" => function.self_type.clone(),
+                name => types.get(name).cloned(),
+            },
+            Expr::Field(field) => {
+                let Member::Named(name) = &field.member else {
+                    return None;
+                };
+                let base = self.receiver_type(&field.base, function, types)?;
+                self.sources.field_type(&self.targets, &base, &name.to_string())
+            }
+            _ => None,
+        }
+    }
+
+    fn eval_in(
+        &self,
+        expr: &Expr,
+        scope: (usize, &Function),
+        env: &HashMap<String, Value>,
+        types: &HashMap<String, String>,
+        depth: usize,
+    ) -> Value {
+        let eval = |expr: &Expr| self.eval_in(expr, scope, env, types, depth);
+        let eval_call = |callees: Vec<(usize, &'a Function)>, args: Vec<Value>, method_call: bool| -> Value {
+            // a string is only followed through a call that can reach one function
+            let [(module, callee)] = callees.as_slice() else {
+                return Value::any(None);
+            };
+            match callee.item() {
+                Some(item) if depth < MAX_DEPTH => {
+                    let env = bind_params(&item, args, method_call);
+                    self.eval_body(&item, (*module, callee), env, depth + 1)
+                }
+                _ => Value::any(None),
+            }
+        };
+        match expr {
+            Expr::Lit(expr_lit) => match &expr_lit.lit {
+                Lit::Str(lit) => Value::literal(&lit.value()),
+                _ => Value::any(None),
+            },
+            Expr::Reference(reference) => eval(&reference.expr),
+            Expr::Paren(paren) => eval(&paren.expr),
+            Expr::Group(group) => eval(&group.expr),
+            Expr::Path(path) => match path.path.get_ident() {
+                Some(ident) => env
+                    .get(&ident.to_string())
+                    .cloned()
+                    .unwrap_or_else(|| Value::any(Some(ident.to_string()))),
+                None => Value::any(Some(source_text(expr))),
+            },
+            Expr::Field(_) => Value::any(Some(source_text(expr))),
+            Expr::MethodCall(call)
+                if call.args.is_empty()
+                    && matches!(
+                        call.method.to_string().as_str(),
+                        "to_string" | "to_owned" | "clone" | "into" | "as_str" | "as_ref"
+                    ) =>
+            {
+                eval(&call.receiver)
+            }
+            Expr::MethodCall(call) => {
+                eval_call(self.method_callees(call, scope.1, types), call.args.iter().map(eval).collect(), true)
+            }
+            Expr::Macro(expr_macro) if expr_macro.mac.path.is_ident("format") => {
+                self.eval_format(&expr_macro.mac, &eval).unwrap_or_else(|| Value::any(None))
+            }
+            Expr::Call(call) => {
+                let Expr::Path(func) = &*call.func else {
+                    return Value::any(None);
+                };
+                let last = func.path.segments.last().map(|segment| segment.ident.to_string()).unwrap_or_default();
+                // AttributeValue::S(..), String::from(..)
+                if matches!(last.as_str(), "S" | "from") && call.args.len() == 1 {
+                    return eval(&call.args[0]);
+                }
+                eval_call(self.callees(&func.path, scope), call.args.iter().map(eval).collect(), false)
+            }
+            _ => Value::any(None),
+        }
+    }
+
+    // What a function like `fn user_id(id: &str) -> String { format!("USER#{id}") }` returns
+    fn eval_body(&self, func: &ItemFn, scope: (usize, &Function), mut env: HashMap<String, Value>, depth: usize) -> Value {
+        let mut types = param_types(func, scope.1);
+        for stmt in &func.block.stmts {
+            match stmt {
+                Stmt::Local(local) => {
+                    if let (Pat::Ident(pat_ident), Some(init)) = (&local.pat, &local.init) {
+                        let value = self.eval_in(&init.expr, scope, &env, &types, depth);
+                        env.insert(pat_ident.ident.to_string(), value);
+                    }
+                    types.extend(local_type(local, scope.1));
+                }
+                Stmt::Expr(expr, None) => return self.eval_in(expr, scope, &env, &types, depth),
+                _ => {}
+            }
+        }
+        Value::any(None)
+    }
+
+    // Looks for the requests `callee` makes, with its parameters bound to `args`
+    fn walk(&mut self, (module, callee): (usize, &'a Function), args: Vec<Value>, method_call: bool) {
+        let location = location(self.sources, module, callee);
+        if self.stack.contains(&location) || self.stack.len() >= MAX_DEPTH {
+            return;
+        }
+        let Some(item) = callee.item() else {
+            return;
+        };
+        let env = mem::replace(&mut self.env, bind_params(&item, args, method_call));
+        let types = mem::replace(&mut self.types, param_types(&item, callee));
+        let caller = mem::replace(&mut self.module, module);
+        let function = mem::replace(&mut self.function, callee);
+        self.stack.push(location);
+        self.visit_block(&item.block);
+        self.stack.pop();
+        self.env = env;
+        self.types = types;
+        self.module = caller;
+        self.function = function;
+    }
+
+    fn eval_format(&self, mac: &Macro, eval: &dyn Fn(&Expr) -> Value) -> Option<Value> {
+        let args = mac.parse_body_with(Punctuated::<Expr, Token![,]>::parse_terminated).ok()?;
+        let mut args = args.iter();
+        let format = str_literal(args.next()?)?;
+        let mut positional = vec![];
+        let mut named = HashMap::new();
+        for arg in args {
+            match arg {
+                Expr::Assign(assign) => {
+                    if matches!(&*assign.left, Expr::Path(_)) {
+                        named.insert(source_text(&assign.left), eval(&assign.right));
+                    }
+                }
+                arg => positional.push(eval(arg)),
+            }
+        }
+        let mut values = vec![];
+        let mut next = 0;
+        let mut chars = format.chars().peekable();
+        let mut literal = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let placeholder: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                    let (arg, spec) = placeholder.split_once(':').unwrap_or((&placeholder, ""));
+                    let value = if arg.is_empty() {
+                        next += 1;
+                        positional.get(next - 1).cloned()
+                    } else if let Ok(index) = arg.parse::<usize>() {
+                        positional.get(index).cloned()
+                    } else {
+                        named.get(arg).cloned().or_else(|| {
+                            syn::parse_str::<Expr>(arg).ok().map(|expr| eval(&expr))
+                        })
+                    };
+                    values.push(Value::literal(&std::mem::take(&mut literal)));
+                    // Debug and padding change the text, only plain Display is kept
+                    values.push(match value {
+                        Some(value) if spec.is_empty() => value,
+                        _ => Value::any(None),
+                    });
+                }
+                c => literal.push(c),
+            }
+        }
+        values.push(Value::literal(&literal));
+        Some(Value::concat(values))
+    }
+
+    fn location(&self) -> String {
+        location(self.sources, self.module, self.function)
+    }
+
+    fn unchecked_operation(&self, send: &ExprMethodCall) -> Option<&'static str> {
+        let mut receiver = &*send.receiver;
+        while let Expr::MethodCall(call) = receiver {
+            if let Some((_, operation)) = UNCHECKED_OPERATIONS.iter().find(|(method, _)| call.method == method) {
+                return Some(operation);
+            }
+            receiver = &call.receiver;
+        }
+        None
+    }
+
+    // `send` ends a builder chain, its receivers lead back to the operation
+    fn sdk_call(&self, send: &ExprMethodCall) -> Option<SdkCall> {
+        let mut chain = vec![];
+        let mut receiver = &*send.receiver;
+        while let Expr::MethodCall(call) = receiver {
+            chain.push(call);
+            receiver = &call.receiver;
+        }
+        chain.reverse();
+        let operation = OPERATIONS
+            .iter()
+            .find(|(method, _)| chain.first().is_some_and(|call| call.method == method))
+            .map(|(_, operation)| *operation)?;
+
+        Some(self.parse_chain(operation, &chain[1..], None))
+    }
+
+    // The items of a transaction built with `.transact_items(..)`, None when
+    // they're built somewhere else
+    fn transaction_items(&self, send: &ExprMethodCall) -> Option<Result<Vec<SdkCall>, (&'static str, Action)>> {
+        let mut chain = vec![];
+        let mut receiver = &*send.receiver;
+        while let Expr::MethodCall(call) = receiver {
+            chain.push(call);
+            receiver = &call.receiver;
+        }
+        chain.reverse();
+        let (_, transaction, action) = TRANSACTIONS.iter().find(|(method, ..)| chain.first().is_some_and(|call| call.method == method))?;
+        let mut items = ItemBuilders { finder: self, transaction, calls: vec![], read: true };
+        let mut any = false;
+        for call in &chain[1..] {
+            match (call.method.to_string().as_str(), call.args.first()) {
+                ("transact_items", Some(arg)) => {
+                    let found = items.calls.len();
+                    items.visit_expr(arg);
+                    any = true;
+                    items.read &= items.calls.len() > found;
+                }
+                ("set_transact_items", _) => items.read = false,
+                _ => {}
+            }
+        }
+        Some(match items.read && any {
+            true => Ok(items.calls),
+            false => Err((transaction, *action)),
+        })
+    }
+
+    // The request a builder chain like `table_name(..).key(..)` describes
+    fn parse_chain(&self, operation: &'static str, chain: &[&ExprMethodCall], transaction: Option<&'static str>) -> SdkCall {
+        let mut table = Value::any(None);
+        let mut index = None;
+        let mut keys = HashMap::new();
+        let mut key_condition = None;
+        let mut values = HashMap::new();
+        let mut names = HashMap::new();
+        let mut projection = None;
+        let mut written = Some(vec![]);
+        for call in chain {
+            let args: Vec<&Expr> = call.args.iter().collect();
+            match (call.method.to_string().as_str(), args.as_slice()) {
+                ("table_name", [name]) | ("request_items", [name, _]) => table = self.eval(name),
+                ("index_name", [name]) => index = Some(self.eval(name)),
+                ("key", [attribute, value]) | ("item", [attribute, value]) => {
+                    let attribute = str_literal(attribute);
+                    if let Some(attribute) = &attribute {
+                        keys.insert(attribute.clone(), self.eval(value));
+                    }
+                    if call.method == "item" {
+                        match (&mut written, attribute) {
+                            (Some(written), Some(attribute)) => written.push(attribute),
+                            (written, _) => *written = None,
+                        }
+                    }
+                }
+                ("set_item", _) => written = None,
+                ("key_condition_expression", [expr]) => key_condition = str_literal(expr),
+                ("expression_attribute_values", [placeholder, value]) => {
+                    if let Some(placeholder) = str_literal(placeholder) {
+                        values.insert(placeholder, self.eval(value));
+                    }
+                }
+                ("expression_attribute_names", [placeholder, name]) => {
+                    if let (Some(placeholder), Some(name)) = (str_literal(placeholder), str_literal(name)) {
+                        names.insert(placeholder, name);
+                    }
+                }
+                ("projection_expression", [expr]) => projection = Some(str_literal(expr)),
+                _ => {}
+            }
+        }
+
+        if let Some(key_condition) = key_condition {
+            for clause in key_condition.replace(" and ", " AND ").split(" AND ") {
+                let clause = clause.trim();
+                let (attribute, value) = if let Some(args) =
+                    clause.strip_prefix("begins_with(").and_then(|args| args.strip_suffix(')'))
+                {
+                    let Some((attribute, placeholder)) = args.split_once(',') else {
+                        continue;
+                    };
+                    let prefix = values.get(placeholder.trim()).cloned().unwrap_or_else(|| Value::any(None));
+                    (attribute.trim(), Value::concat(vec![prefix, Value::any(None)]))
+                } else if let Some((attribute, placeholder)) = clause.split_once('=') {
+                    (attribute.trim(), values.get(placeholder.trim()).cloned().unwrap_or_else(|| Value::any(None)))
+                } else {
+                    continue;
+                };
+                let attribute = names.get(attribute).map(String::as_str).unwrap_or(attribute);
+                keys.insert(attribute.to_string(), value);
+            }
+        }
+
+        let attributes = match projection {
+            _ if operation == "PutItem" => written.map_or(Attributes::Unknown, Attributes::Named),
+            _ if !READ_OPERATIONS.contains(&operation) => Attributes::Unknown,
+            None => Attributes::Unnamed,
+            Some(None) => Attributes::Unknown,
+            Some(Some(projection)) => projection
+                .split(',')
+                .map(|field| {
+                    let field = field.trim();
+                    match field.starts_with('#') {
+                        true => names.get(field).cloned(),
+                        false => Some(field.to_string()),
+                    }
+                })
+                .collect::<Option<Vec<String>>>()
+                .map_or(Attributes::Unknown, Attributes::Named),
+        };
+
+        SdkCall { operation, table, index, keys, attributes, location: self.location(), transaction }
+    }
+}
+
+// The request a transaction item's builder, like `Put::builder()..build()?`,
+// is authorized as, and its chain of calls
+fn item_builder(expr: &Expr) -> Option<(&'static str, Vec<&ExprMethodCall>)> {
+    let mut chain = vec![];
+    let mut expr = expr;
+    loop {
+        match expr {
+            Expr::Try(try_expr) => expr = &try_expr.expr,
+            Expr::Paren(paren) => expr = &paren.expr,
+            Expr::MethodCall(call) => {
+                chain.push(call);
+                expr = &call.receiver;
+            }
+            _ => break,
+        }
+    }
+    chain.reverse();
+    let Expr::Call(ExprCall { func, args, .. }) = expr else {
+        return None;
+    };
+    let Expr::Path(path) = &**func else {
+        return None;
+    };
+    let segments: Vec<String> = path.path.segments.iter().map(|segment| segment.ident.to_string()).collect();
+    let [.., kind, builder] = segments.as_slice() else {
+        return None;
+    };
+    let (_, operation) = TRANSACTION_ITEMS.iter().find(|(name, _)| name == kind && builder == "builder" && args.is_empty())?;
+    Some((operation, chain))
+}
+
+// Finds the builders of a transaction's items
+struct ItemBuilders<'f, 'a> {
+    finder: &'f CallFinder<'a>,
+    transaction: &'static str,
+    calls: Vec<SdkCall>,
+    // false once an item is built some other way
+    read: bool,
+}
+
+impl<'ast> Visit<'ast> for ItemBuilders<'_, '_> {
+    fn visit_expr(&mut self, expr: &'ast Expr) {
+        if let Some((operation, chain)) = item_builder(expr) {
+            self.calls.push(self.finder.parse_chain(operation, &chain, Some(self.transaction)));
+            return;
+        }
+        // `TransactWriteItem::builder().put(put)` with an item built before the call
+        if let Expr::MethodCall(call) = expr {
+            let setter = ["get", "put", "update", "delete", "condition_check"].iter().any(|setter| call.method == setter);
+            if setter && call.args.len() == 1 && item_builder(&call.args[0]).is_none() {
+                self.read = false;
+            }
+        }
+        visit::visit_expr(self, expr);
+    }
+}
+
+impl<'ast> Visit<'ast> for CallFinder<'_> {
+    fn visit_local(&mut self, local: &'ast Local) {
+        visit::visit_local(self, local);
+        if let (Pat::Ident(pat_ident), Some(init)) = (&local.pat, &local.init) {
+            let value = self.eval(&init.expr);
+            self.env.insert(pat_ident.ident.to_string(), value);
+        }
+        self.types.extend(local_type(local, self.function));
+    }
+
+    fn visit_expr_method_call(&mut self, call: &'ast ExprMethodCall) {
+        if call.method == "send" {
+            if let Some(sdk_call) = self.sdk_call(call) {
+                self.calls.push(sdk_call);
+                return;
+            }
+            match self.transaction_items(call) {
+                Some(Ok(items)) => {
+                    self.calls.extend(items);
+                    return;
+                }
+                Some(Err((transaction, action))) => {
+                    self.unread.push((transaction, action, self.location()));
+                    return;
+                }
+                None => {}
+            }
+            if let Some(operation) = self.unchecked_operation(call) {
+                self.unchecked.push((operation, self.location()));
+                return;
+            }
+        }
+        visit::visit_expr_method_call(self, call);
+        let callees = self.method_callees(call, self.function, &self.types);
+        let args: Vec<Value> = call.args.iter().map(|arg| self.eval(arg)).collect();
+        for callee in callees {
+            self.walk(callee, args.clone(), true);
+        }
+    }
+
+    fn visit_expr_call(&mut self, call: &'ast ExprCall) {
+        visit::visit_expr_call(self, call);
+        let Expr::Path(func) = &*call.func else {
+            return;
+        };
+        let callees = self.callees(&func.path, (self.module, self.function));
+        let args: Vec<Value> = call.args.iter().map(|arg| self.eval(arg)).collect();
+        for callee in callees {
+            self.walk(callee, args.clone(), false);
+        }
+    }
+}
+
+// Variables aren't known until the request is made, any value could match them.
+// A '*' or '?' in a key_equals literal is read as a wildcard too, which can only
+// let a call through that IAM would still check.
+fn pattern(expr: &StringExpr) -> String {
+    match expr {
+        StringExpr::Literal(lit) => lit.clone(),
+        StringExpr::Variable(_) => "*".to_string(),
+        StringExpr::Concat(left, right) => pattern(left) + &pattern(right),
+    }
+}
+
+fn filter_covers(filter: &Filter, value: &Value) -> bool {
+    let pattern: Vec<char> = pattern(filter.value()).chars().collect();
+    pattern_covers(&pattern, &value.template())
+}
+
+fn applies(atom: &PolicyAtom, call: &SdkCall, table: &str, index: Option<&str>, keys: &KeySchema) -> bool {
+    let on_resource = match (&atom.resource, index) {
+        (Resource::Table(name), None) => name == table,
+        (Resource::Index { index: name, table: index_table }, Some(index)) => index_table == table && name == index,
+        _ => false,
+    };
+    on_resource
+        && atom.action.dynamodb_operations(index.is_some()).contains(&call.operation)
+        && atom.filters.iter().all(|filter| match filter.key() {
+            Some(key) => filter_covers(filter, &call.key(keys, key)),
+            None => true,
+        })
+}
+
+// Attributes that can't be told are left for IAM to check
+fn allows(atom: &PolicyAtom, call: &SdkCall, keys: &KeySchema) -> bool {
+    match (&atom.attributes, &call.attributes) {
+        (None, _) | (_, Attributes::Unknown) => true,
+        (Some(fields), Attributes::Named(attributes)) => attributes
+            .iter()
+            .all(|attribute| keys.key(attribute).is_some() || fields.iter().any(|field| field.0 == *attribute)),
+        (Some(_), Attributes::Unnamed) => false,
+    }
+}
+
+fn denies(atom: &PolicyAtom, call: &SdkCall) -> bool {
+    match (&atom.attributes, &call.attributes) {
+        (None, _) | (_, Attributes::Unnamed) => true,
+        (Some(fields), Attributes::Named(attributes)) => {
+            attributes.iter().any(|attribute| fields.iter().any(|field| field.0 == *attribute))
+        }
+        (_, Attributes::Unknown) => false,
+    }
+}
+
+fn describe(call: &SdkCall, table: &str, index: Option<&str>, keys: &KeySchema) -> String {
+    let mut description = match index {
+        Some(index) => format!("{} on index {index:?} of table {table:?}", call.operation),
+        None => format!("{} on table {table:?}", call.operation),
+    };
+    description.push_str(&format!(
+        " with $pk {:?} and $sk {:?}",
+        call.key(keys, Key::Pk).pattern(),
+        call.key(keys, Key::Sk).pattern()
+    ));
+    match &call.attributes {
+        Attributes::Unnamed => description.push_str(" on every attribute"),
+        Attributes::Named(attributes) => description.push_str(&format!(" on attributes [{}]", attributes.join(", "))),
+        Attributes::Unknown => {}
+    }
+    if let Some(transaction) = call.transaction {
+        description.push_str(&format!(" in a {transaction} request"));
+    }
+    description
+}
+
+fn resolve_name(value: &Value, what: &str, call: &SdkCall, config: &PolicyConfig) -> Result<String, String> {
+    if let Some(name) = value.as_literal() {
+        return Ok(name);
+    }
+    if let Some(name) = value.source.as_ref().and_then(|source| config.tables.get(source)) {
+        return Ok(name.clone());
+    }
+    Err(match &value.source {
+        Some(source) => format!(
+            "can't tell which {what} `{source}` names in the {} call in {}, add it to [package.metadata.policy_macros.tables]",
+            call.operation, call.location
+        ),
+        None => format!("can't tell which {what} the {} call in {} is on", call.operation, call.location),
+    })
+}
+
+fn check_call(policy: &Policy, call: &SdkCall, owner: &str, config: &PolicyConfig) -> Result<(), String> {
+    let table = resolve_name(&call.table, "table", call, config)?;
+    let index = match &call.index {
+        Some(index) => Some(resolve_name(index, "index", call, config)?),
+        None => None,
+    };
+    let index = index.as_deref();
+    let keys = config.key_schema(&table);
+    let atoms: Vec<&PolicyAtom> = policy.atoms().iter().filter(|atom| applies(atom, call, &table, index, &keys)).collect();
+    if let Some(deny) = atoms.iter().find(|atom| atom.effect == Effect::Deny && denies(atom, call)) {
+        return Err(format!(
+            "'{owner}' makes a request its policy denies: {}, called in {}, is denied by `{deny}`",
+            describe(call, &table, index, &keys),
+            call.location
+        ));
+    }
+    if !atoms.iter().any(|atom| atom.effect == Effect::Allow && allows(atom, call, &keys)) {
+        return Err(format!(
+            "'{owner}' makes a request its policy doesn't allow: {}, called in {}",
+            describe(call, &table, index, &keys),
+            call.location
+        ));
+    }
+    Ok(())
+}
+
+// Every DynamoDB request the handler and the crate functions it calls make has
+// to be allowed by its policy. `file` is the file the compiler says the handler
+// is in, if it does. What's left for IAM to check is returned as warnings.
+pub fn check_calls(
+    policy: &Policy,
+    func: &ItemFn,
+    file: Option<&Path>,
+    crate_root: &Path,
+    config: &PolicyConfig,
+) -> syn::Result<Vec<String>> {
+    let loaded = Sources::load(crate_root);
+    let owner = func.sig.ident.to_string();
+    let mut warnings = vec![];
+    let detached;
+    let (sources, module, handler) = match loaded.handler(func, file).map_err(|err| Error::new(func.sig.ident.span(), err))? {
+        Some((module, handler)) => (&*loaded, module, handler),
+        None => {
+            warnings.push(format!(
+                "can't find '{owner}' with its policy_attr in the crate's sources, so only the requests it makes \
+                 itself are checked and the functions it calls are left for IAM to check"
+            ));
+            detached = Sources::detached(func, file);
+            (&detached, 0, &detached.modules[0].functions[0])
+        }
+    };
+    let mut finder = CallFinder {
+        sources,
+        targets: sources.targets_in_scope(),
+        module,
+        function: handler,
+        env: HashMap::new(),
+        types: param_types(func, handler),
+        stack: vec![location(sources, module, handler)],
+        calls: vec![],
+        unchecked: vec![],
+        unread: vec![],
+    };
+    finder.visit_block(&func.block);
+
+    let mut unread = vec![];
+    for (transaction, action, location) in &finder.unread {
+        // a transaction the policy allows on a table is left for IAM to check
+        let allowing = policy
+            .atoms()
+            .iter()
+            .find(|atom| atom.effect == Effect::Allow && atom.action == *action && matches!(atom.resource, Resource::Table(_)));
+        match allowing {
+            Some(atom) => warnings.push(format!(
+                "'{owner}' makes a {transaction} request, called in {location}, whose items aren't built in the call, \
+                 so only IAM checks them against `{atom}`"
+            )),
+            None => unread.push(format!(
+                "'{owner}' makes a {transaction} request, called in {location}, whose items aren't built in the call \
+                 and its policy doesn't allow {} on a table",
+                action.keyword()
+            )),
+        }
+    }
+    let unchecked = finder.unchecked.iter().map(|(operation, location)| {
+        format!("'{owner}' makes a {operation} request, called in {location}, which policies can't allow")
+    });
+    let checked = finder.calls.iter().filter_map(|call| check_call(policy, call, &owner, config).err());
+    let mut errors: Option<Error> = None;
+    let mut reported = HashSet::new();
+    for message in unread.into_iter().chain(unchecked).chain(checked) {
+        // helpers called more than once make the same request each time
+        if !reported.insert(message.clone()) {
+            continue;
+        }
+        let error = Error::new(func.sig.ident.span(), message);
+        match &mut errors {
+            Some(errors) => errors.combine(error),
+            None => errors = Some(error),
+        }
+    }
+    warnings.dedup();
+    match errors {
+        Some(errors) => Err(errors),
+        None => Ok(warnings),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use syn::Item;
+
+    use super::*;
+    use crate::sources::tests::TempCrate;
+
+    const MAIN: &str = r#"
+        mod db;
+        use db::keys::profile_key as key;
+
+        #[policy_attr(allow read on table "Users" where key_equals $pk concat("USER#", $id))]
+        async fn get_profile(client: &Client, id: String) {
+            let store = db::Store { client };
+            store.profile(&id).await;
+        }
+
+        #[policy_attr(allow read on table "Users" where key_equals $pk concat("USER#", $id))]
+        async fn get_key(client: &Client, id: String) {
+            client.get_item().table_name("Users").key("PK", AttributeValue::S(key(&id))).send().await;
+        }
+
+        #[policy_attr(allow transact_write on table "Users")]
+        async fn transfer(client: &Client) {
+            inline::transfer(client).await;
+        }
+
+        mod inline {
+            pub async fn transfer(client: &Client) {
+                client.transact_write_items().send().await;
+            }
+        }
+    "#;
+
+    const DB: &str = r#"
+        pub mod keys {
+            pub fn profile_key(id: &str) -> String {
+                format!("USER#{id}")
+            }
+        }
+
+        pub struct Store<'a> {
+            pub client: &'a Client,
+        }
+
+        impl Store<'_> {
+            pub async fn profile(&self, id: &str) {
+                self.client.get_item().table_name("Users").key("PK", AttributeValue::S(keys::profile_key(id))).send().await;
+            }
+        }
+    "#;
+
+    fn handler(text: &str, name: &str) -> ItemFn {
+        syn::parse_file(text)
+            .unwrap()
+            .items
+            .into_iter()
+            .find_map(|item| match item {
+                Item::Fn(item_fn) if item_fn.sig.ident == name => Some(item_fn),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    fn check(krate: &TempCrate, name: &str, policy: &str) -> Result<Vec<String>, String> {
+        check_in(krate, "src/main.rs", name, policy)
+    }
+
+    fn check_in(krate: &TempCrate, file: &str, name: &str, policy: &str) -> Result<Vec<String>, String> {
+        let func = handler(&fs::read_to_string(krate.root.join(file)).unwrap(), name);
+        let policy: Policy = syn::parse_str(policy).unwrap();
+        check_calls(&policy, &func, None, &krate.root, &PolicyConfig::default()).map_err(|err| err.to_string())
+    }
+
+    fn app(test: &str) -> TempCrate {
+        TempCrate::new(test, &[("Cargo.toml", "[package]\nname = \"app\"\n"), ("src/main.rs", MAIN), ("src/db.rs", DB)])
+    }
+
+    #[test]
+    fn follows_methods_and_imported_functions() {
+        let krate = app("methods");
+        let policy = r#"allow read on table "Users" where key_like $pk "USER#*""#;
+        assert_eq!(check(&krate, "get_profile", policy), Ok(vec![]));
+        assert_eq!(check(&krate, "get_key", policy), Ok(vec![]));
+        let policy = r#"allow read on table "Users" where key_like $pk "ADMIN#*""#;
+        assert_eq!(
+            check(&krate, "get_profile", policy).unwrap_err(),
+            "'get_profile' makes a request its policy doesn't allow: GetItem on table \"Users\" with $pk \"USER#*\" \
+             and $sk \"*\" on every attribute, called in db::Store::profile"
+        );
+        assert_eq!(
+            check(&krate, "get_key", policy).unwrap_err(),
+            "'get_key' makes a request its policy doesn't allow: GetItem on table \"Users\" with $pk \"USER#*\" \
+             and $sk \"*\" on every attribute, called in get_key"
+        );
+    }
+
+    #[test]
+    fn follows_methods_into_the_receivers_type() {
+        let main = r#"
+            struct Users<'a> {
+                client: &'a Client,
+            }
+
+            struct Orders<'a> {
+                client: &'a Client,
+            }
+
+            struct State<'a> {
+                users: Users<'a>,
+            }
+
+            impl Users<'_> {
+                async fn load(&self) {
+                    self.client.get_item().table_name("Users").send().await;
+                }
+            }
+
+            impl Orders<'_> {
+                async fn load(&self) {
+                    self.client.get_item().table_name("Orders").send().await;
+                }
+            }
+
+            #[policy_attr(allow read on table "Users")]
+            async fn by_param(users: &Users<'_>) {
+                users.load().await;
+            }
+
+            #[policy_attr(allow read on table "Users")]
+            async fn by_field(state: Arc<State<'_>>) {
+                state.users.load().await;
+            }
+
+            #[policy_attr(allow read on table "Users")]
+            async fn by_let(client: &Client) {
+                let users: Users = make(client);
+                users.load().await;
+            }
+
+            #[policy_attr(allow read on table "Users")]
+            async fn unknown(client: &Client) {
+                make(client).load().await;
+            }
+        "#;
+        let krate = TempCrate::new("receivers", &[("Cargo.toml", "[package]\nname = \"app\"\n"), ("src/main.rs", main)]);
+        let policy = r#"allow read on table "Users""#;
+        for handler in ["by_param", "by_field", "by_let", "unknown"] {
+            assert_eq!(check(&krate, handler, policy), Ok(vec![]), "{handler}");
+        }
+        assert_eq!(
+            check(&krate, "by_field", r#"allow read on table "Orders""#).unwrap_err(),
+            "'by_field' makes a request its policy doesn't allow: GetItem on table \"Users\" with $pk \"*\" \
+             and $sk \"*\" on every attribute, called in Users::load"
+        );
+    }
+
+    #[test]
+    fn transaction_items_are_checked_one_by_one() {
+        let main = r#"
+            #[policy_attr(allow transact_write on table "Users" where key_like $pk "USER#*")]
+            async fn transfer(client: &Client, id: String) {
+                client
+                    .transact_write_items()
+                    .transact_items(
+                        TransactWriteItem::builder()
+                            .update(Update::builder().table_name("Users").key("PK", AttributeValue::S(format!("USER#{id}"))).build()?)
+                            .build(),
+                    )
+                    .transact_items(
+                        TransactWriteItem::builder()
+                            .put(Put::builder().table_name("Ledger").item("PK", AttributeValue::S(id.clone())).build()?)
+                            .build(),
+                    )
+                    .send()
+                    .await;
+            }
+        "#;
+        let krate = TempCrate::new("transaction_items", &[("Cargo.toml", "[package]\nname = \"app\"\n"), ("src/main.rs", main)]);
+        assert_eq!(
+            check(&krate, "transfer", r#"allow transact_write on table "Users" where key_like $pk "USER#*""#).unwrap_err(),
+            "'transfer' makes a request its policy doesn't allow: PutItem on table \"Ledger\" with $pk \"*\" \
+             and $sk \"*\" on attributes [PK] in a TransactWriteItems request, called in transfer"
+        );
+        let policy = r#"allow transact_write on table "Users" where key_like $pk "USER#*"
+            allow transact_write on table "Ledger""#;
+        assert_eq!(check(&krate, "transfer", policy), Ok(vec![]));
+        let policy = r#"allow transact_write on table "Users" where key_like $pk "ADMIN#*"
+            allow transact_write on table "Ledger""#;
+        assert!(check(&krate, "transfer", policy).unwrap_err().starts_with(
+            "'transfer' makes a request its policy doesn't allow: UpdateItem on table \"Users\" with $pk \"USER#*\""
+        ));
+    }
+
+    #[test]
+    fn transactions_whose_items_arent_built_are_left_to_iam() {
+        let krate = app("unread_transactions");
+        assert_eq!(
+            check(&krate, "transfer", r#"allow transact_write on table "Users""#),
+            Ok(vec!["'transfer' makes a TransactWriteItems request, called in inline::transfer, whose items aren't \
+                     built in the call, so only IAM checks them against `allow transact_write on table \"Users\"`"
+                .to_string()])
+        );
+        assert_eq!(
+            check(&krate, "transfer", r#"allow update on table "Users""#).unwrap_err(),
+            "'transfer' makes a TransactWriteItems request, called in inline::transfer, whose items aren't built in \
+             the call and its policy doesn't allow transact_write on a table"
+        );
+    }
+
+    #[test]
+    fn partiql_statements_are_errors() {
+        let main = r#"
+            #[policy_attr(allow read on table "Users")]
+            async fn report(client: &Client) {
+                client.execute_statement().statement("SELECT * FROM Users").send().await;
+            }
+        "#;
+        let krate = TempCrate::new("partiql", &[("Cargo.toml", "[package]\nname = \"app\"\n"), ("src/main.rs", main)]);
+        assert_eq!(
+            check(&krate, "report", r#"allow read on table "Users""#).unwrap_err(),
+            "'report' makes a ExecuteStatement request, called in report, which policies can't allow"
+        );
+    }
+
+    #[test]
+    fn finds_handlers_in_examples() {
+        let example = r#"
+            #[policy_attr(allow read on table "Users" where key_equals $pk concat("USER#", $id))]
+            async fn get_profile(client: &Client, id: String) {
+                app::db::Store { client }.profile(&id).await;
+            }
+        "#;
+        let krate = TempCrate::new(
+            "examples",
+            &[
+                ("Cargo.toml", "[package]\nname = \"app\"\n"),
+                ("src/lib.rs", "pub mod db;\n"),
+                ("src/db.rs", DB),
+                ("examples/profile.rs", example),
+            ],
+        );
+        let policy = r#"allow read on table "Users" where key_like $pk "ADMIN#*""#;
+        assert_eq!(
+            check_in(&krate, "examples/profile.rs", "get_profile", policy).unwrap_err(),
+            "'get_profile' makes a request its policy doesn't allow: GetItem on table \"Users\" with $pk \"USER#*\" \
+             and $sk \"*\" on every attribute, called in db::Store::profile"
+        );
+    }
+
+    #[test]
+    fn handlers_the_module_tree_doesnt_reach_are_checked_alone() {
+        // written by a macro_rules!, so it isn't in the file as the macro sees it
+        let main = r#"
+            macro_rules! handler {
+                ($name:ident) => {
+                    #[policy_attr(allow read on table "Users")]
+                    async fn $name(client: &Client) {
+                        client.get_item().table_name("Users").send().await;
+                        db::Store { client }.profile("id").await;
+                    }
+                };
+            }
+            handler!(get_profile);
+
+            async fn get_profile(client: &Client) {
+                client.get_item().table_name("Users").send().await;
+                db::Store { client }.profile("id").await;
+            }
+        "#;
+        let krate = TempCrate::new(
+            "unreached",
+            &[("Cargo.toml", "[package]\nname = \"app\"\n"), ("src/main.rs", main), ("src/db.rs", DB)],
+        );
+        let warning = "can't find 'get_profile' with its policy_attr in the crate's sources, so only the requests it \
+                       makes itself are checked and the functions it calls are left for IAM to check";
+        assert_eq!(check(&krate, "get_profile", r#"allow read on table "Users""#), Ok(vec![warning.to_string()]));
+        assert_eq!(
+            check(&krate, "get_profile", r#"allow read on table "Orders""#).unwrap_err(),
+            "'get_profile' makes a request its policy doesn't allow: GetItem on table \"Users\" with $pk \"*\" \
+             and $sk \"*\" on every attribute, called in get_profile"
+        );
+    }
+
+    #[test]
+    fn handlers_that_cant_be_told_apart_are_errors() {
+        let handler = r#"
+            #[policy_attr(allow read on table "Users")]
+            async fn get_profile(client: &Client) {}
+        "#;
+        let krate = TempCrate::new(
+            "ambiguous",
+            &[
+                ("Cargo.toml", "[package]\nname = \"app\"\n"),
+                ("src/main.rs", &format!("mod a;\n{handler}")),
+                ("src/a.rs", handler),
+            ],
+        );
+        assert_eq!(
+            check(&krate, "get_profile", r#"allow read on table "Users""#).unwrap_err(),
+            "can't tell which of the functions called 'get_profile' with a policy_attr this is, so the calls it makes \
+             can't be checked"
+        );
+    }
+}
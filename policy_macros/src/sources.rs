@@ -0,0 +1,710 @@
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::SystemTime;
+
+use quote::quote;
+use syn::{
+    Attribute, Block, Expr, GenericArgument, ImplItem, Item, ItemFn, Lit, Meta, PathArguments, Signature, TraitItem, TraitItemFn, Type,
+    UseTree,
+};
+use toml_edit::{DocumentMut, Item as TomlItem};
+
+use crate::policy::PolicyDefinition;
+
+// What policy_attr needs from the rest of the crate, read from its sources since
+// define_policy! invocations and the functions a handler calls expand in no
+// particular order relative to the handler. The module tree is followed from the
+// package's targets the way rustc does it, so `mod`s anywhere under the crate
+// root, #[path]s and inline modules are found and files that aren't part of the
+// crate aren't.
+//
+// Every handler of the crate expands in the same process, so the index is kept
+// between expansions, and only built again once one of the files it was read
+// from (or looked for) changes. It only holds strings, the tokens of a
+// proc_macro can't outlive the expansion that made them.
+
+// A library, binary, .. of the package, named the way rustc names its crate
+#[derive(Debug)]
+pub struct Target {
+    pub name: String,
+    pub lib: bool,
+}
+
+#[derive(Debug)]
+pub struct Module {
+    pub target: usize,
+    // the path from the crate root, empty for the root itself
+    pub path: Vec<String>,
+    pub file: PathBuf,
+    // alias -> the path it's imported from, as written
+    imports: Vec<(String, Vec<String>)>,
+    // the paths of `use path::*`
+    globs: Vec<Vec<String>>,
+    // structs, enums, traits, .. declared in it, which methods are called through
+    types: Vec<String>,
+    // (struct, field, the name of its type) of the named fields of its structs
+    fields: Vec<(String, String, String)>,
+    pub functions: Vec<Function>,
+    pub definitions: Vec<Definition>,
+}
+
+impl Module {
+    pub fn display_path(&self) -> String {
+        ["crate"].into_iter().chain(self.path.iter().map(String::as_str)).collect::<Vec<&str>>().join("::")
+    }
+}
+
+// A function or method, kept as text and parsed again when a handler calls it
+#[derive(Debug)]
+pub struct Function {
+    pub name: String,
+    // the type (or trait) of the impl block a method is written in
+    pub self_type: Option<String>,
+    pub policy_attr: bool,
+    // what body_text gives for it, to tell a handler from other functions of
+    // the same name
+    body: String,
+    tokens: String,
+}
+
+impl Function {
+    fn new(sig: &Signature, block: &Block, attrs: &[Attribute], self_type: Option<String>) -> Self {
+        Function {
+            name: sig.ident.to_string(),
+            self_type,
+            policy_attr: attrs.iter().any(crate::is_policy_attr),
+            body: body_text(sig, block),
+            tokens: quote!(#sig #block).to_string(),
+        }
+    }
+
+    // Its signature and body, attributes and visibility are left out
+    pub fn item(&self) -> Option<ItemFn> {
+        syn::parse_str(&self.tokens).ok()
+    }
+}
+
+// A function's signature and body without whitespace, which is the same for
+// the tokens policy_attr expands and those read from the file
+pub fn body_text(sig: &Signature, block: &Block) -> String {
+    quote!(#sig #block).to_string().chars().filter(|c| !c.is_whitespace()).collect()
+}
+
+// The name of the type a value of `ty` calls methods on, e.g. `Store` for
+// `&Arc<db::Store>`, "Self" is left for the caller to resolve
+pub fn type_name(ty: &Type) -> Option<String> {
+    match ty {
+        Type::Reference(reference) => type_name(&reference.elem),
+        Type::Paren(paren) => type_name(&paren.elem),
+        Type::Group(group) => type_name(&group.elem),
+        Type::Path(path) => {
+            let segment = path.path.segments.last()?;
+            let inner = match &segment.arguments {
+                PathArguments::AngleBracketed(args) if ["Box", "Arc", "Rc"].contains(&segment.ident.to_string().as_str()) => {
+                    match args.args.first() {
+                        Some(GenericArgument::Type(inner)) => Some(inner),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+            match inner {
+                Some(inner) => type_name(inner),
+                None => Some(segment.ident.to_string()),
+            }
+        }
+        _ => None,
+    }
+}
+
+// A define_policy! invocation, kept as text and parsed again by each use
+#[derive(Debug)]
+pub struct Definition {
+    pub name: String,
+    tokens: String,
+}
+
+impl Definition {
+    pub fn parse(&self) -> syn::Result<PolicyDefinition> {
+        syn::parse_str(&self.tokens)
+    }
+}
+
+#[derive(Debug)]
+pub struct Sources {
+    pub targets: Vec<Target>,
+    pub modules: Vec<Module>,
+}
+
+// a file's modification time and length, None if it doesn't exist
+type Stamp = Option<(SystemTime, u64)>;
+
+fn stamp(path: &Path) -> Stamp {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+// the files an index was built from, and their stamps when they were read
+type Read = Vec<(PathBuf, Stamp)>;
+
+thread_local! {
+    // crate root -> its index
+    static CACHE: RefCell<HashMap<PathBuf, (Read, Rc<Sources>)>> = RefCell::new(HashMap::new());
+}
+
+impl Sources {
+    pub fn load(crate_root: &Path) -> Rc<Sources> {
+        CACHE.with(|cache| {
+            let mut cache = cache.borrow_mut();
+            if let Some((read, sources)) = cache.get(crate_root) {
+                if read.iter().all(|(path, read_stamp)| stamp(path) == *read_stamp) {
+                    return sources.clone();
+                }
+            }
+            let mut loader = Loader { crate_root, modules: vec![], read: vec![], files: BTreeSet::new() };
+            let targets = loader.targets();
+            for (target, (_, root)) in targets.iter().enumerate() {
+                loader.load_file(target, vec![], root, true);
+            }
+            let sources = Rc::new(Sources {
+                targets: targets.into_iter().map(|(target, _)| target).collect(),
+                modules: loader.modules,
+            });
+            cache.insert(crate_root.to_path_buf(), (loader.read, sources.clone()));
+            sources
+        })
+    }
+
+    // The targets of the crate being compiled, a binary also sees the library of
+    // its package. Every target when cargo didn't say which one it builds.
+    pub fn targets_in_scope(&self) -> Vec<usize> {
+        let current = env::var("CARGO_CRATE_NAME").ok();
+        let Some(current) = self.targets.iter().position(|target| Some(&target.name) == current.as_ref()) else {
+            return (0..self.targets.len()).collect();
+        };
+        let mut targets = vec![current];
+        targets.extend(self.targets.iter().position(|target| target.lib).filter(|lib| *lib != current));
+        targets
+    }
+
+    pub fn module_at(&self, target: usize, path: &[String]) -> Option<usize> {
+        self.modules.iter().position(|module| module.target == target && module.path == path)
+    }
+
+    // The item `path` names when it's written in `module`, as the target and the
+    // path from its root. None when it isn't an item of the package, or imports go
+    // round in circles.
+    pub fn resolve(&self, module: usize, path: &[String]) -> Option<(usize, Vec<String>)> {
+        self.resolve_in(module, path, 0)
+    }
+
+    fn resolve_in(&self, module_id: usize, path: &[String], depth: usize) -> Option<(usize, Vec<String>)> {
+        let module = &self.modules[module_id];
+        let (first, rest) = path.split_first()?;
+        if depth > 16 {
+            return None;
+        }
+        let within = |base: &[String]| -> Vec<String> { base.iter().chain(rest).cloned().collect() };
+        match first.as_str() {
+            "crate" => Some((module.target, rest.to_vec())),
+            "self" => Some((module.target, within(&module.path))),
+            "super" => {
+                let (_, parent) = module.path.split_last()?;
+                match rest {
+                    [] => Some((module.target, parent.to_vec())),
+                    rest => self.resolve_in(self.module_at(module.target, parent)?, rest, depth + 1),
+                }
+            }
+            name => {
+                if self.declares(module_id, name) {
+                    return Some((module.target, module.path.iter().chain(path).cloned().collect()));
+                }
+                if let Some((_, import)) = module.imports.iter().find(|(alias, _)| alias == name) {
+                    let import: Vec<String> = import.iter().chain(rest).cloned().collect();
+                    return self.resolve_in(module_id, &import, depth + 1);
+                }
+                // a binary naming its package's library
+                if let Some(lib) = self.targets.iter().position(|target| target.lib && target.name == name) {
+                    return Some((lib, rest.to_vec()));
+                }
+                module.globs.iter().find_map(|glob| {
+                    let (target, base) = self.resolve_in(module_id, glob, depth + 1)?;
+                    let base = self.module_at(target, &base)?;
+                    let found = self.declares(base, name)
+                        || self.modules[base].imports.iter().any(|(alias, _)| alias == name);
+                    found.then(|| self.resolve_in(base, path, depth + 1)).flatten()
+                })
+            }
+        }
+    }
+
+    fn declares(&self, module_id: usize, name: &str) -> bool {
+        let module = &self.modules[module_id];
+        let mut path = module.path.clone();
+        path.push(name.to_string());
+        module.functions.iter().any(|function| function.self_type.is_none() && function.name == name)
+            || module.types.iter().any(|declared| declared == name)
+            || self.module_at(module.target, &path).is_some()
+    }
+
+    // The functions a path resolve gave names: a free function, or the methods
+    // called `Type::method` of every type of that name, since impl blocks can be
+    // anywhere in the crate
+    pub fn functions(&self, target: usize, path: &[String]) -> Vec<(usize, &Function)> {
+        let Some((name, module_path)) = path.split_last() else {
+            return vec![];
+        };
+        let free = self.module_at(target, module_path).and_then(|module| {
+            let function = self.modules[module]
+                .functions
+                .iter()
+                .find(|function| function.self_type.is_none() && function.name == *name)?;
+            Some((module, function))
+        });
+        match (free, module_path.last()) {
+            (Some(free), _) => vec![free],
+            (None, Some(self_type)) => self.methods(&[target], name, Some(self_type)),
+            (None, None) => vec![],
+        }
+    }
+
+    // The type of `field` of the structs called `struct_name` in `targets`, None
+    // unless they agree on one
+    pub fn field_type(&self, targets: &[usize], struct_name: &str, field: &str) -> Option<String> {
+        let mut types = self
+            .modules
+            .iter()
+            .filter(|module| targets.contains(&module.target))
+            .flat_map(|module| &module.fields)
+            .filter(|(name, declared, _)| name == struct_name && declared == field)
+            .map(|(_, _, type_name)| type_name);
+        let first = types.next()?;
+        types.all(|type_name| type_name == first).then(|| first.clone())
+    }
+
+    // The methods called `name` in `targets`, of any type unless `self_type` is given
+    pub fn methods(&self, targets: &[usize], name: &str, self_type: Option<&str>) -> Vec<(usize, &Function)> {
+        self.modules
+            .iter()
+            .enumerate()
+            .filter(|(_, module)| targets.contains(&module.target))
+            .flat_map(|(module, functions)| functions.functions.iter().map(move |function| (module, function)))
+            .filter(|(_, function)| {
+                function.name == name
+                    && function.self_type.is_some()
+                    && self_type.is_none_or(|self_type| function.self_type.as_deref() == Some(self_type))
+            })
+            .collect()
+    }
+
+    // The function policy_attr is expanding, found by the file it's in (when the
+    // compiler tells) and its body. None when the module tree doesn't reach it,
+    // e.g. it's in a file syn can't parse or written by a macro_rules!.
+    // A crate of just the handler, for one the module tree doesn't reach: the
+    // requests it makes itself can be checked, but none of its calls followed
+    pub fn detached(func: &ItemFn, file: Option<&Path>) -> Sources {
+        let module = Module {
+            target: 0,
+            path: vec![],
+            file: file.map(Path::to_path_buf).unwrap_or_default(),
+            imports: vec![],
+            globs: vec![],
+            types: vec![],
+            fields: vec![],
+            functions: vec![Function::new(&func.sig, &func.block, &func.attrs, None)],
+            definitions: vec![],
+        };
+        Sources { targets: vec![], modules: vec![module] }
+    }
+
+    pub fn handler(&self, func: &ItemFn, file: Option<&Path>) -> Result<Option<(usize, &Function)>, String> {
+        let name = func.sig.ident.to_string();
+        let body = body_text(&func.sig, &func.block);
+        let file = file.and_then(|file| fs::canonicalize(file).ok());
+        let mut candidates: Vec<(usize, &Function)> = vec![];
+        for target in self.targets_in_scope() {
+            for (module_id, module) in self.modules.iter().enumerate().filter(|(_, module)| module.target == target) {
+                if file.as_ref().is_some_and(|file| fs::canonicalize(&module.file).ok().as_ref() != Some(file)) {
+                    continue;
+                }
+                // a binary and its library can include the same file
+                if candidates.iter().any(|(known, _)| self.modules[*known].file == module.file && self.modules[*known].path == module.path) {
+                    continue;
+                }
+                candidates.extend(
+                    module.functions.iter().filter(|function| function.policy_attr && function.name == name).map(|function| (module_id, function)),
+                );
+            }
+        }
+        let same_body: Vec<(usize, &Function)> = candidates.iter().copied().filter(|(_, function)| function.body == body).collect();
+        match (same_body.as_slice(), candidates.as_slice()) {
+            ([handler], _) => Ok(Some(*handler)),
+            // an attribute above policy_attr rewrote the body
+            ([], [handler]) => Ok(Some(*handler)),
+            ([], []) => Ok(None),
+            (_, _) => Err(format!(
+                "can't tell which of the functions called '{name}' with a policy_attr this is, so the calls it makes can't be checked"
+            )),
+        }
+    }
+}
+
+struct Loader<'a> {
+    crate_root: &'a Path,
+    modules: Vec<Module>,
+    read: Read,
+    // (target, file), a file is only read once per target
+    files: BTreeSet<(usize, PathBuf)>,
+}
+
+fn toml_str<'a>(table: &'a TomlItem, key: &str) -> Option<&'a str> {
+    table.get(key).and_then(|value| value.as_str())
+}
+
+fn crate_name(name: &str) -> String {
+    name.replace('-', "_")
+}
+
+fn path_attr(attrs: &[Attribute]) -> Option<String> {
+    attrs.iter().find_map(|attr| match &attr.meta {
+        Meta::NameValue(name_value) if name_value.path.is_ident("path") => match &name_value.value {
+            Expr::Lit(expr_lit) => match &expr_lit.lit {
+                Lit::Str(lit) => Some(lit.value()),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+fn imports(tree: &UseTree, prefix: &[String], module: &mut Module) {
+    let joined = |name: &syn::Ident| -> Vec<String> { prefix.iter().cloned().chain([name.to_string()]).collect() };
+    match tree {
+        UseTree::Path(use_path) => imports(&use_path.tree, &joined(&use_path.ident), module),
+        UseTree::Name(use_name) if use_name.ident == "self" => {
+            if let Some(last) = prefix.last() {
+                module.imports.push((last.clone(), prefix.to_vec()));
+            }
+        }
+        UseTree::Name(use_name) => module.imports.push((use_name.ident.to_string(), joined(&use_name.ident))),
+        UseTree::Rename(rename) if rename.rename == "_" => {}
+        UseTree::Rename(rename) => {
+            let path = match rename.ident == "self" {
+                true => prefix.to_vec(),
+                false => joined(&rename.ident),
+            };
+            module.imports.push((rename.rename.to_string(), path));
+        }
+        UseTree::Glob(_) => module.globs.push(prefix.to_vec()),
+        UseTree::Group(group) => {
+            for tree in &group.items {
+                imports(tree, prefix, module);
+            }
+        }
+    }
+}
+
+impl Loader<'_> {
+    fn exists(&mut self, path: &Path) -> bool {
+        let stamp = stamp(path);
+        self.read.push((path.to_path_buf(), stamp));
+        stamp.is_some()
+    }
+
+    // Cargo's targets and their root files, from the manifest and the layout it
+    // discovers targets from
+    fn targets(&mut self) -> Vec<(Target, PathBuf)> {
+        let manifest = self.crate_root.join("Cargo.toml");
+        self.exists(&manifest);
+        let document = fs::read_to_string(&manifest).ok().and_then(|text| text.parse::<DocumentMut>().ok());
+        let document = document.as_ref().map(|document| document.as_item());
+        let package = document.and_then(|document| document.get("package")).and_then(|package| toml_str(package, "name"));
+        let package = package.unwrap_or_default().to_string();
+
+        let mut targets: Vec<(Target, PathBuf)> = vec![];
+        let mut add = |target: Target, root: PathBuf, loader: &mut Self| {
+            if !targets.iter().any(|(_, known)| *known == root) && loader.exists(&root) {
+                targets.push((target, root));
+            }
+        };
+        let lib = document.and_then(|document| document.get("lib"));
+        let lib_root = lib.and_then(|lib| toml_str(lib, "path")).unwrap_or("src/lib.rs");
+        let lib_name = lib.and_then(|lib| toml_str(lib, "name")).unwrap_or(&package);
+        add(Target { name: crate_name(lib_name), lib: true }, self.crate_root.join(lib_root), self);
+
+        // [[bin]]s and the src/bin/ layout, then the examples, tests and benches,
+        // which are crates of their own that can have handlers too
+        for (kind, dir) in [("bin", "src/bin"), ("example", "examples"), ("test", "tests"), ("bench", "benches")] {
+            let dir = self.crate_root.join(dir);
+            let tables = document.and_then(|document| document.get(kind)).and_then(|tables| tables.as_array_of_tables());
+            for table in tables.into_iter().flatten() {
+                let Some(name) = table.get("name").and_then(|name| name.as_str()) else {
+                    continue;
+                };
+                let root = match table.get("path").and_then(|path| path.as_str()) {
+                    Some(path) => self.crate_root.join(path),
+                    None if kind == "bin" && name == package => self.crate_root.join("src/main.rs"),
+                    None if self.exists(&dir.join(name).join("main.rs")) => dir.join(name).join("main.rs"),
+                    None => dir.join(format!("{name}.rs")),
+                };
+                add(Target { name: crate_name(name), lib: false }, root, self);
+            }
+            if kind == "bin" {
+                add(Target { name: crate_name(&package), lib: false }, self.crate_root.join("src/main.rs"), self);
+            }
+            if !self.exists(&dir) {
+                continue;
+            }
+            let mut entries: Vec<PathBuf> = fs::read_dir(&dir)
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| Some(entry.ok()?.path()))
+                .collect();
+            entries.sort();
+            for entry in entries {
+                let (name, root) = match entry.is_dir() {
+                    true => (entry.file_name(), entry.join("main.rs")),
+                    false if entry.extension().is_some_and(|extension| extension == "rs") => (entry.file_stem(), entry.clone()),
+                    false => continue,
+                };
+                let name = crate_name(&name.unwrap_or_default().to_string_lossy());
+                add(Target { name, lib: false }, root, self);
+            }
+        }
+        targets
+    }
+
+    // A file the module tree reaches, `mod_rs` for the files whose `mod`s are
+    // looked for next to them: crate roots, mod.rs and files named by #[path]
+    fn load_file(&mut self, target: usize, path: Vec<String>, file: &Path, mod_rs: bool) {
+        if !self.files.insert((target, file.to_path_buf())) {
+            return;
+        }
+        self.exists(file);
+        // Files that don't parse are skipped, rustc reports them
+        let Some(syntax) = fs::read_to_string(file).ok().and_then(|text| syn::parse_file(&text).ok()) else {
+            return;
+        };
+        let parent = file.parent().unwrap_or(Path::new("")).to_path_buf();
+        let dir = match mod_rs {
+            true => parent.clone(),
+            false => parent.join(file.file_stem().unwrap_or_default()),
+        };
+        self.load_module(target, path, file, &syntax.items, &dir, &parent);
+    }
+
+    // `dir` is where the files of its `mod`s are, `path_dir` what their #[path]s
+    // are relative to
+    fn load_module(&mut self, target: usize, path: Vec<String>, file: &Path, items: &[Item], dir: &Path, path_dir: &Path) {
+        let mut module = Module {
+            target,
+            path: path.clone(),
+            file: file.to_path_buf(),
+            imports: vec![],
+            globs: vec![],
+            types: vec![],
+            fields: vec![],
+            functions: vec![],
+            definitions: vec![],
+        };
+        let mut children = vec![];
+        for item in items {
+            match item {
+                Item::Use(item_use) if item_use.leading_colon.is_none() => imports(&item_use.tree, &[], &mut module),
+                Item::Fn(item_fn) => module.functions.push(Function::new(&item_fn.sig, &item_fn.block, &item_fn.attrs, None)),
+                Item::Impl(item_impl) => {
+                    let Type::Path(self_type) = &*item_impl.self_ty else {
+                        continue;
+                    };
+                    let Some(self_type) = self_type.path.segments.last().map(|segment| segment.ident.to_string()) else {
+                        continue;
+                    };
+                    for impl_item in &item_impl.items {
+                        if let ImplItem::Fn(method) = impl_item {
+                            let function = Function::new(&method.sig, &method.block, &method.attrs, Some(self_type.clone()));
+                            module.functions.push(function);
+                        }
+                    }
+                }
+                Item::Trait(item_trait) => {
+                    module.types.push(item_trait.ident.to_string());
+                    for trait_item in &item_trait.items {
+                        if let TraitItem::Fn(TraitItemFn { sig, default: Some(block), attrs, .. }) = trait_item {
+                            module.functions.push(Function::new(sig, block, attrs, Some(item_trait.ident.to_string())));
+                        }
+                    }
+                }
+                Item::Struct(item) => {
+                    module.types.push(item.ident.to_string());
+                    for field in &item.fields {
+                        if let (Some(name), Some(type_name)) = (&field.ident, type_name(&field.ty)) {
+                            module.fields.push((item.ident.to_string(), name.to_string(), type_name));
+                        }
+                    }
+                }
+                Item::Enum(item) => module.types.push(item.ident.to_string()),
+                Item::Union(item) => module.types.push(item.ident.to_string()),
+                Item::Type(item) => module.types.push(item.ident.to_string()),
+                Item::Macro(item_macro)
+                    if item_macro.mac.path.segments.last().is_some_and(|segment| segment.ident == "define_policy") =>
+                {
+                    // define_policy! reports the definitions that don't parse
+                    if let Ok(definition) = item_macro.mac.parse_body::<PolicyDefinition>() {
+                        module.definitions.push(Definition { name: definition.name, tokens: item_macro.mac.tokens.to_string() });
+                    }
+                }
+                Item::Mod(item_mod) => children.push(item_mod),
+                _ => {}
+            }
+        }
+        self.modules.push(module);
+
+        for item_mod in children {
+            let name = item_mod.ident.to_string();
+            let mut child = path.clone();
+            child.push(name.clone());
+            let attr_path = path_attr(&item_mod.attrs);
+            match &item_mod.content {
+                // an inline module's `mod`s and #[path]s are both relative to
+                // the directory named after it
+                Some((_, items)) => {
+                    let child_dir = match &attr_path {
+                        Some(attr_path) => path_dir.join(attr_path),
+                        None => dir.join(&name),
+                    };
+                    self.load_module(target, child, file, items, &child_dir, &child_dir);
+                }
+                None => match attr_path {
+                    Some(attr_path) => self.load_file(target, child, &path_dir.join(attr_path), true),
+                    None => {
+                        let file = dir.join(format!("{name}.rs"));
+                        match self.exists(&file) {
+                            true => self.load_file(target, child, &file, false),
+                            false => self.load_file(target, child, &dir.join(&name).join("mod.rs"), true),
+                        }
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // A crate under the system's temp directory, removed when it's dropped
+    pub struct TempCrate {
+        pub root: PathBuf,
+    }
+
+    impl TempCrate {
+        pub fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let root = env::temp_dir().join(format!("policy_macros_sources_{name}_{}", std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            for (path, text) in files {
+                let path = root.join(path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, text).unwrap();
+            }
+            TempCrate { root }
+        }
+    }
+
+    impl Drop for TempCrate {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn paths(sources: &Sources, target: usize) -> Vec<(String, PathBuf)> {
+        sources
+            .modules
+            .iter()
+            .filter(|module| module.target == target)
+            .map(|module| (module.display_path(), module.file.clone()))
+            .collect()
+    }
+
+    fn strings(path: &str) -> Vec<String> {
+        path.split("::").map(str::to_string).collect()
+    }
+
+    #[test]
+    fn follows_the_module_tree_from_each_target() {
+        let krate = TempCrate::new(
+            "tree",
+            &[
+                ("Cargo.toml", "[package]\nname = \"my-app\"\n"),
+                ("src/lib.rs", "mod a;\n#[path = \"other/c.rs\"] mod c;\nmod inline { mod d; }\n"),
+                ("src/a.rs", "mod b;\n"),
+                ("src/a/b.rs", ""),
+                ("src/other/c.rs", "mod e;\n"),
+                ("src/other/e.rs", ""),
+                ("src/inline/d/mod.rs", ""),
+                ("src/main.rs", ""),
+                ("src/bin/tool.rs", ""),
+                ("src/unused.rs", "define_policy!(unused = allow read on table \"Users\");"),
+                ("examples/example.rs", ""),
+            ],
+        );
+        let sources = Sources::load(&krate.root);
+        let names: Vec<(&str, bool)> = sources.targets.iter().map(|target| (target.name.as_str(), target.lib)).collect();
+        assert_eq!(names, [("my_app", true), ("my_app", false), ("tool", false), ("example", false)]);
+        let src = krate.root.join("src");
+        assert_eq!(
+            paths(&sources, 0),
+            [
+                ("crate".to_string(), src.join("lib.rs")),
+                ("crate::a".to_string(), src.join("a.rs")),
+                ("crate::a::b".to_string(), src.join("a/b.rs")),
+                ("crate::c".to_string(), src.join("other/c.rs")),
+                ("crate::c::e".to_string(), src.join("other/e.rs")),
+                ("crate::inline".to_string(), src.join("lib.rs")),
+                ("crate::inline::d".to_string(), src.join("inline/d/mod.rs")),
+            ]
+        );
+        assert_eq!(paths(&sources, 2), [("crate".to_string(), src.join("bin/tool.rs"))]);
+        assert_eq!(paths(&sources, 3), [("crate".to_string(), krate.root.join("examples/example.rs"))]);
+        assert!(sources.modules.iter().all(|module| module.definitions.is_empty()));
+    }
+
+    #[test]
+    fn resolves_paths_through_imports() {
+        let krate = TempCrate::new(
+            "imports",
+            &[
+                ("Cargo.toml", "[package]\nname = \"app\"\n"),
+                ("src/lib.rs", "pub mod util;\n"),
+                ("src/main.rs", "mod handlers;\nuse app::util::{self as helpers};\nuse handlers::*;\n"),
+                ("src/handlers.rs", "pub mod profile {}\nuse super::helpers::keys;\n"),
+                ("src/util.rs", "pub mod keys {}\n"),
+            ],
+        );
+        let sources = Sources::load(&krate.root);
+        let main = sources.module_at(1, &[]).unwrap();
+        let handlers = sources.module_at(1, &strings("handlers")).unwrap();
+        assert_eq!(sources.resolve(main, &strings("helpers::keys::user")), Some((0, strings("util::keys::user"))));
+        assert_eq!(sources.resolve(main, &strings("profile::get")), Some((1, strings("handlers::profile::get"))));
+        assert_eq!(sources.resolve(handlers, &strings("keys::user")), Some((0, strings("util::keys::user"))));
+        assert_eq!(sources.resolve(handlers, &strings("super::handlers::profile")), Some((1, strings("handlers::profile"))));
+        assert_eq!(sources.resolve(handlers, &strings("serde::Serialize")), None);
+    }
+
+    #[test]
+    fn reloads_once_a_file_changes() {
+        let krate = TempCrate::new("reload", &[("Cargo.toml", "[package]\nname = \"app\"\n"), ("src/lib.rs", "")]);
+        let first = Sources::load(&krate.root);
+        assert!(Rc::ptr_eq(&first, &Sources::load(&krate.root)));
+        // a file that was looked for and didn't exist counts as well
+        fs::write(krate.root.join("src/main.rs"), "").unwrap();
+        let second = Sources::load(&krate.root);
+        assert!(!Rc::ptr_eq(&first, &second));
+        assert_eq!(second.targets.len(), 2);
+    }
+}
@@ -0,0 +1,168 @@
+use serde_json::{json, Value};
+
+use crate::compiler::PolicyCompiler;
+use crate::config::PolicyConfig;
+use crate::iam_policy_compiler::{compile_document, PolicyDocument, Statement};
+use crate::policy::Policy;
+
+// Compiles a policy into a Terraform JSON file holding the IAM document as a
+// `data.aws_iam_policy_document` block, and the role policy attaching it to the
+// function's role from the main template. The ARN placeholders are filled in from
+// Terraform data sources, so `terraform plan` shows the policy as it'll be applied.
+
+// `${...}` placeholders of ARN templates -> the Terraform expression filling them in
+const PLACEHOLDERS: [(&str, &str); 4] = [
+    ("partition", "${data.aws_partition.current.partition}"),
+    ("region", "${data.aws_region.current.name}"),
+    ("account", "${data.aws_caller_identity.current.account_id}"),
+    ("stage", "${var.stage}"),
+];
+
+// IAM policy variables and the `${*}` escapes use the same syntax as Terraform
+// interpolation, so everything but the placeholders has to be escaped
+fn terraform_string(value: &str) -> String {
+    let escaped = value.replace("${", "$${").replace("%{", "%%{");
+    PLACEHOLDERS
+        .iter()
+        .fold(escaped, |value, (placeholder, expr)| value.replace(&format!("$${{{placeholder}}}"), expr))
+}
+
+fn terraform_strings(values: &[String]) -> Vec<String> {
+    values.iter().map(|value| terraform_string(value)).collect()
+}
+
+fn statement_block(statement: &Statement) -> Value {
+    let conditions: Vec<Value> = statement
+        .condition
+        .iter()
+        .flat_map(|(test, keys)| {
+            keys.iter().map(move |(variable, values)| {
+                json!({
+                    "test": test,
+                    "variable": variable,
+                    "values": terraform_strings(values),
+                })
+            })
+        })
+        .collect();
+    let mut block = json!({
+        "effect": statement.effect,
+        "actions": statement.action,
+        "resources": terraform_strings(&statement.resource),
+    });
+    if !conditions.is_empty() {
+        block["condition"] = Value::Array(conditions);
+    }
+    block
+}
+
+pub struct TerraformPolicyCompiler {
+    pub config: PolicyConfig,
+    // the function's `name` in the main template's `lambda_functions`, which
+    // its resources there are keyed by
+    pub function: String,
+}
+
+impl TerraformPolicyCompiler {
+    // policy_attr hands over the document it already compiled for IAM
+    pub(crate) fn compile_iam_document(&self, document: &PolicyDocument) -> String {
+        let statements: Vec<Value> = document.statement.iter().map(statement_block).collect();
+        let function = &self.function;
+        let terraform = json!({
+            "data": {
+                "aws_iam_policy_document": {
+                    function: {
+                        "version": document.version,
+                        "statement": statements,
+                    }
+                }
+            },
+            "resource": {
+                "aws_iam_role_policy": {
+                    format!("{function}_policy"): {
+                        "name": format!("{function}-policy"),
+                        "role": format!("${{aws_iam_role.function_roles[\"{function}\"].id}}"),
+                        "policy": format!("${{data.aws_iam_policy_document.{function}.json}}"),
+                    }
+                }
+            }
+        });
+        serde_json::to_string_pretty(&terraform).expect("Failed to serialize terraform")
+    }
+}
+
+impl PolicyCompiler for TerraformPolicyCompiler {
+    fn compile_policy(&self, policy: &Policy) -> String {
+        self.compile_iam_document(&compile_document(policy, &self.config))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terraform(policy: &str, config: PolicyConfig) -> Value {
+        let compiler = TerraformPolicyCompiler { config, function: "get_profile".to_string() };
+        serde_json::from_str(&compiler.compile_policy(&syn::parse_str(policy).unwrap())).unwrap()
+    }
+
+    #[test]
+    fn document_and_role_policy() {
+        let mut config = PolicyConfig::default();
+        config.variables.insert("claims.sub".to_string(), "${aws:PrincipalTag/sub}".to_string());
+        config
+            .arn_templates
+            .insert("table".to_string(), "arn:${partition}:dynamodb:${region}:${account}:table/${stage}-{name}".to_string());
+        let policy = r#"allow read on table "Users" where key_equals $pk concat("USER#", $claims.sub)
+                        deny delete on table "Users""#;
+        assert_eq!(
+            terraform(policy, config),
+            json!({
+                "data": {
+                    "aws_iam_policy_document": {
+                        "get_profile": {
+                            "version": "2012-10-17",
+                            "statement": [
+                                {
+                                    "effect": "Allow",
+                                    "actions": ["dynamodb:GetItem", "dynamodb:BatchGetItem", "dynamodb:Query"],
+                                    "resources": [
+                                        "arn:${data.aws_partition.current.partition}:dynamodb:${data.aws_region.current.name}:${data.aws_caller_identity.current.account_id}:table/${var.stage}-Users"
+                                    ],
+                                    "condition": [{
+                                        "test": "ForAllValues:StringEquals",
+                                        "variable": "dynamodb:LeadingKeys",
+                                        "values": ["USER#$${aws:PrincipalTag/sub}"]
+                                    }]
+                                },
+                                {
+                                    "effect": "Deny",
+                                    "actions": ["dynamodb:DeleteItem"],
+                                    "resources": [
+                                        "arn:${data.aws_partition.current.partition}:dynamodb:${data.aws_region.current.name}:${data.aws_caller_identity.current.account_id}:table/${var.stage}-Users"
+                                    ]
+                                }
+                            ]
+                        }
+                    }
+                },
+                "resource": {
+                    "aws_iam_role_policy": {
+                        "get_profile_policy": {
+                            "name": "get_profile-policy",
+                            "role": "${aws_iam_role.function_roles[\"get_profile\"].id}",
+                            "policy": "${data.aws_iam_policy_document.get_profile.json}"
+                        }
+                    }
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn escapes_iam_wildcards_and_template_directives() {
+        assert_eq!(terraform_string("USER#${*}"), "USER#$${*}");
+        assert_eq!(terraform_string("%{if}"), "%%{if}");
+        assert_eq!(terraform_string("arn:${partition}:s3:::${stage}-uploads/*"), "arn:${data.aws_partition.current.partition}:s3:::${var.stage}-uploads/*");
+    }
+}
@@ -0,0 +1,34 @@
+use std::env;
+use std::process;
+
+use policy_runtime::{reconcile, CallRecorder};
+
+// Reconciles a recording with a crate's policies, e.g. after running the
+// integration tests against messaging-app built with its `record` feature:
+//
+// cargo run -p policy_runtime --bin policy_report -- messaging-app messaging-app/policy_calls.jsonl
+//
+// Exits with 1 when a handler made a request its policy doesn't grant.
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let json = args.iter().any(|arg| arg == "--json");
+    let paths: Vec<&String> = args.iter().filter(|arg| *arg != "--json").collect();
+    let [crate_root, recording] = paths.as_slice() else {
+        eprintln!("Usage: policy_report <crate_root> <recording.jsonl> [--json]");
+        process::exit(2);
+    };
+    let report = match CallRecorder::load(recording).and_then(|calls| reconcile(crate_root, &calls)) {
+        Ok(report) => report,
+        Err(err) => {
+            eprintln!("{err}");
+            process::exit(2);
+        }
+    };
+    match json {
+        true => println!("{}", report.to_json()),
+        false => print!("{report}"),
+    }
+    if report.has_not_granted() {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,386 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::path::Path;
+
+use aws_sdk_dynamodb::config::interceptors::BeforeSerializationInterceptorContextRef;
+use aws_sdk_dynamodb::config::{ConfigBag, Intercept, RuntimeComponents};
+use aws_sdk_dynamodb::error::BoxError;
+use aws_sdk_dynamodb::Client;
+use aws_smithy_runtime_api::client::orchestrator::Metadata;
+use policy_dsl::config::PolicyConfig;
+use policy_dsl::optimizer::pattern_covers;
+use policy_dsl::policy::{Condition, Effect, Filter, Key, Policy, PolicyAtom, Resource, StringExpr};
+
+use crate::requests::{self, KeyValue, Request};
+
+// Evaluates DynamoDB requests against a handler's policy the way the DSL reads,
+// with its `$vars` bound to the values of one invocation, e.g.
+//
+// let db = PolicyEnforcer::for_function(env!("CARGO_MANIFEST_DIR"), "get_profile")?
+//     .bind("user_id", "1")
+//     .bind("claims.sub", "auth0|1")
+//     .client(&db);
+//
+// Requests made through the returned client that the policy doesn't allow fail
+// with an AccessDenied before they're sent. The enforcer checks what IAM checks
+// by default:
+//
+// - a variable that isn't bound denies the requests it would be checked against,
+//   unless the crate lists it in widen_variables and so IAM matches anything
+// - `$sk` filters are ignored, as IAM can't restrict sort keys, unless
+//   `.check_sort_keys()` asks for the stricter check
+#[derive(Debug, Clone)]
+pub struct PolicyEnforcer {
+    policy: Policy,
+    // `$var` path, e.g. "claims.sub" -> its value
+    variables: HashMap<String, String>,
+    // IAM condition key, e.g. "aws:SourceIp" -> its value, for `when` clauses
+    conditions: HashMap<String, String>,
+    // the crate's settings, for the names of each table's key attributes and the
+    // variables that widen
+    config: PolicyConfig,
+    // whether `$sk` filters are checked
+    sort_keys: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct AccessDenied {
+    // None for operations a policy can't grant at all
+    pub request: Option<Box<Request>>,
+    pub message: String,
+}
+
+impl Display for AccessDenied {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "AccessDenied: {}", self.message)
+    }
+}
+
+impl Error for AccessDenied {}
+
+impl AccessDenied {
+    // The AccessDenied an SdkError (or any error wrapping one) was caused by
+    pub fn find<'a>(err: &'a (dyn Error + 'static)) -> Option<&'a AccessDenied> {
+        let mut err = Some(err);
+        while let Some(current) = err {
+            if let Some(access_denied) = current.downcast_ref::<AccessDenied>() {
+                return Some(access_denied);
+            }
+            err = current.source();
+        }
+        None
+    }
+}
+
+// A glob against what the request pins down, a prefix stands for every value
+// starting with it
+fn template(value: &KeyValue) -> Vec<(char, bool)> {
+    match value {
+        KeyValue::Exact(value) => value.chars().map(|c| (c, false)).collect(),
+        KeyValue::Prefix(prefix) => prefix.chars().map(|c| (c, false)).chain([('*', true)]).collect(),
+    }
+}
+
+fn like(pattern: &str, value: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    pattern_covers(&pattern, &template(&KeyValue::Exact(value.to_string())))
+}
+
+impl PolicyEnforcer {
+    pub fn new(policy: Policy) -> Self {
+        PolicyEnforcer {
+            policy,
+            variables: HashMap::new(),
+            conditions: HashMap::new(),
+            config: PolicyConfig::default(),
+            sort_keys: false,
+        }
+    }
+
+    // Reads the policy policy_attr wrote for `function` under `crate_root`, and
+    // the crate's settings it was compiled with
+    pub fn for_function(crate_root: impl AsRef<Path>, function: &str) -> Result<Self, String> {
+        let crate_root = crate_root.as_ref();
+        let path = crate_root.join("policies").join(format!("{function}.policy.json"));
+        let text = fs::read_to_string(&path).map_err(|err| format!("Could not read {}: {err}", path.display()))?;
+        let policy = serde_json::from_str(&text).map_err(|err| format!("Could not parse {}: {err}", path.display()))?;
+        Ok(PolicyEnforcer::new(policy).config(PolicyConfig::load(crate_root)?))
+    }
+
+    pub fn config(mut self, config: PolicyConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn bind(mut self, variable: &str, value: impl Into<String>) -> Self {
+        self.variables.insert(variable.to_string(), value.into());
+        self
+    }
+
+    pub fn condition(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.conditions.insert(key.to_string(), value.into());
+        self
+    }
+
+    pub fn check_sort_keys(mut self) -> Self {
+        self.sort_keys = true;
+        self
+    }
+
+    // A copy of `client` whose requests are checked against the policy
+    pub fn client(&self, client: &Client) -> Client {
+        let interceptor = PolicyInterceptor {
+            policy: serde_json::to_string(&self.policy).expect("Failed to serialize policy"),
+            variables: self.variables.clone(),
+            conditions: self.conditions.clone(),
+            config: self.config.clone(),
+            sort_keys: self.sort_keys,
+        };
+        let config = client.config().to_builder().interceptor(interceptor).build();
+        Client::from_conf(config)
+    }
+
+    // The expression with its variables filled in, and whether they all were. Only
+    // the variables in widen_variables are left unbound by the time it's called.
+    fn substitute(&self, expr: &StringExpr) -> (String, bool) {
+        match expr {
+            StringExpr::Literal(lit) => (lit.clone(), true),
+            StringExpr::Variable(var) => match self.variables.get(&var.name()) {
+                Some(value) => (value.clone(), true),
+                None => ("*".to_string(), false),
+            },
+            StringExpr::Concat(left, right) => {
+                let (left, left_bound) = self.substitute(left);
+                let (right, right_bound) = self.substitute(right);
+                (left + &right, left_bound && right_bound)
+            }
+        }
+    }
+
+    fn filter_matches(&self, filter: &Filter, value: Option<&KeyValue>) -> bool {
+        let Some(value) = value else {
+            return false;
+        };
+        let (pattern, bound) = self.substitute(filter.value());
+        match (filter, value) {
+            (Filter::KeyEquals(..), KeyValue::Exact(value)) if bound => pattern == *value,
+            (Filter::KeyEquals(..), KeyValue::Prefix(_)) if bound => false,
+            _ => {
+                let pattern: Vec<char> = pattern.chars().collect();
+                pattern_covers(&pattern, &template(value))
+            }
+        }
+    }
+
+    // IfExists operators hold when the key is missing, the others don't. Operators
+    // the enforcer doesn't know never hold.
+    fn condition_holds(&self, condition: &Condition) -> bool {
+        let (operator, if_exists) = match condition.operator.strip_suffix("IfExists") {
+            Some(operator) => (operator, true),
+            None => (condition.operator.as_str(), false),
+        };
+        let Some(value) = self.conditions.get(&condition.key) else {
+            return if_exists;
+        };
+        let values = &condition.values;
+        match operator {
+            "StringEquals" => values.iter().any(|expected| expected == value),
+            "StringNotEquals" => !values.iter().any(|expected| expected == value),
+            "StringEqualsIgnoreCase" => values.iter().any(|expected| expected.eq_ignore_ascii_case(value)),
+            "StringLike" => values.iter().any(|pattern| like(pattern, value)),
+            "StringNotLike" => !values.iter().any(|pattern| like(pattern, value)),
+            "Bool" => values.iter().any(|expected| expected.eq_ignore_ascii_case(value)),
+            _ => false,
+        }
+    }
+
+    // Whether the atom is about the request's resource and operation, whatever its
+    // filters say
+    fn applies(&self, atom: &PolicyAtom, request: &Request) -> bool {
+        let on_resource = match (&atom.resource, &request.index) {
+            (Resource::Table(table), None) => *table == request.table,
+            (Resource::Index { index, table }, Some(request_index)) => *table == request.table && index == request_index,
+            _ => false,
+        };
+        on_resource && atom.action.dynamodb_operations(request.index.is_some()).contains(&request.operation.as_str())
+    }
+
+    fn checked_filters<'a>(&'a self, atom: &'a PolicyAtom) -> impl Iterator<Item = &'a Filter> + 'a {
+        atom.filters.iter().filter(|filter| self.sort_keys || filter.key() != Some(Key::Sk))
+    }
+
+    fn matches(&self, atom: &PolicyAtom, request: &Request) -> bool {
+        self.checked_filters(atom).all(|filter| match filter.key() {
+            Some(Key::Pk) => self.filter_matches(filter, request.pk.as_ref()),
+            Some(Key::Sk) => self.filter_matches(filter, request.sk.as_ref()),
+            None => true,
+        }) && atom.conditions.iter().all(|condition| self.condition_holds(condition))
+    }
+
+    pub fn check(&self, request: &Request) -> Result<(), AccessDenied> {
+        let returns_items = matches!(request.return_values.as_deref(), Some("ALL_OLD") | Some("ALL_NEW"));
+        let applicable: Vec<&PolicyAtom> = self.policy.atoms().iter().filter(|atom| self.applies(atom, request)).collect();
+        for atom in &applicable {
+            let unbound = self
+                .checked_filters(atom)
+                .flat_map(|filter| filter.value().variables())
+                .map(|var| var.name())
+                .find(|name| !self.variables.contains_key(name) && !self.config.widen_variables.contains(name));
+            if let Some(name) = unbound {
+                return Err(AccessDenied {
+                    request: Some(Box::new(request.clone())),
+                    message: format!("{request} can't be checked against `{atom}`, ${name} isn't bound"),
+                });
+            }
+        }
+        let atoms: Vec<&PolicyAtom> = applicable.into_iter().filter(|atom| self.matches(atom, request)).collect();
+        // a deny limited to some attributes fires when the request touches one of
+        // them, or can't say which it touches
+        let denied_by = atoms.iter().find(|atom| {
+            atom.effect == Effect::Deny
+                && match &atom.attributes {
+                    None => true,
+                    Some(fields) => {
+                        returns_items
+                            || match &request.attributes {
+                                Some(attributes) => attributes.iter().any(|attribute| fields.iter().any(|field| field.0 == *attribute)),
+                                None => request.is_read(),
+                            }
+                    }
+                }
+        });
+        if let Some(atom) = denied_by {
+            return Err(AccessDenied { request: Some(Box::new(request.clone())), message: format!("{request} is denied by `{atom}`") });
+        }
+        // an allow limited to some attributes needs the request to name them, and
+        // writes can't return whole items
+        let keys = self.config.key_schema(&request.table);
+        let allowed = atoms.iter().any(|atom| {
+            atom.effect == Effect::Allow
+                && match &atom.attributes {
+                    None => true,
+                    Some(fields) => {
+                        !returns_items
+                            && match &request.attributes {
+                                Some(attributes) => attributes.iter().all(|attribute| {
+                                    keys.key(attribute).is_some() || fields.iter().any(|field| field.0 == *attribute)
+                                }),
+                                None => !request.is_read(),
+                            }
+                    }
+                }
+        });
+        if !allowed {
+            return Err(AccessDenied { request: Some(Box::new(request.clone())), message: format!("no statement of the policy allows {request}") });
+        }
+        Ok(())
+    }
+}
+
+// The AST carries proc_macro2 spans, which aren't Send, so the interceptor keeps
+// the policy as JSON and reads it back for each request
+#[derive(Debug)]
+struct PolicyInterceptor {
+    policy: String,
+    variables: HashMap<String, String>,
+    conditions: HashMap<String, String>,
+    config: PolicyConfig,
+    sort_keys: bool,
+}
+
+impl Intercept for PolicyInterceptor {
+    fn name(&self) -> &'static str {
+        "PolicyInterceptor"
+    }
+
+    // client interceptors' read_before_execution runs before the operation's
+    // metadata is in the config bag
+    fn read_before_serialization(
+        &self,
+        context: &BeforeSerializationInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        let Some(requests) = requests::from_input(context.input(), &self.config) else {
+            let operation = cfg.load::<Metadata>().map(|metadata| metadata.name()).unwrap_or("this operation");
+            return Err(Box::new(AccessDenied { request: None, message: format!("policies can't grant {operation}") }));
+        };
+        let enforcer = PolicyEnforcer {
+            policy: serde_json::from_str(&self.policy)?,
+            variables: self.variables.clone(),
+            conditions: self.conditions.clone(),
+            config: self.config.clone(),
+            sort_keys: self.sort_keys,
+        };
+        for request in &requests {
+            enforcer.check(request)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enforcer(policy: &str) -> PolicyEnforcer {
+        PolicyEnforcer::new(syn::parse_str(policy).unwrap())
+    }
+
+    fn request(operation: &str, pk: &str, sk: KeyValue) -> Request {
+        Request {
+            operation: operation.to_string(),
+            table: "Users".to_string(),
+            index: None,
+            pk: Some(KeyValue::Exact(pk.to_string())),
+            sk: Some(sk),
+            attributes: None,
+            return_values: None,
+        }
+    }
+
+    #[test]
+    fn unbound_variables_deny_unless_they_widen() {
+        let policy = r#"allow read on table "Users" where key_equals $pk concat("USER#", $user_id)"#;
+        let get = request("GetItem", "USER#1", KeyValue::Exact("PROFILE".to_string()));
+        let err = enforcer(policy).check(&get).unwrap_err();
+        assert!(err.message.ends_with("$user_id isn't bound"), "{err}");
+
+        enforcer(policy).bind("user_id", "1").check(&get).unwrap();
+        assert!(enforcer(policy).bind("user_id", "2").check(&get).is_err());
+
+        let mut config = PolicyConfig::default();
+        config.widen_variables.insert("user_id".to_string());
+        enforcer(policy).config(config).check(&get).unwrap();
+    }
+
+    #[test]
+    fn sort_keys_are_only_checked_when_asked_to() {
+        let policy = r#"allow read on table "Users" where key_equals $pk "USER#1" where key_like $sk concat("FRIEND#", $friend)"#;
+        let query = request("Query", "USER#1", KeyValue::Prefix("POST#".to_string()));
+        // like IAM, which can't see the sort key
+        enforcer(policy).check(&query).unwrap();
+
+        let strict = enforcer(policy).check_sort_keys();
+        assert!(strict.check(&query).unwrap_err().message.ends_with("$friend isn't bound"));
+        let strict = strict.bind("friend", "2");
+        assert!(strict.check(&query).is_err());
+        strict.check(&request("Query", "USER#1", KeyValue::Exact("FRIEND#2".to_string()))).unwrap();
+    }
+
+    #[test]
+    fn attribute_limits_cover_returned_items() {
+        let policy = r#"allow update on table "Users" deny update on table "Users" with attributes ["role"]"#;
+        let mut update = request("UpdateItem", "USER#1", KeyValue::Exact("PROFILE".to_string()));
+        update.attributes = Some(vec!["email".to_string()]);
+        enforcer(policy).check(&update).unwrap();
+
+        update.return_values = Some("ALL_NEW".to_string());
+        assert!(enforcer(policy).check(&update).unwrap_err().message.contains("is denied by"));
+        update.return_values = None;
+        update.attributes = Some(vec!["role".to_string()]);
+        assert!(enforcer(policy).check(&update).is_err());
+    }
+}
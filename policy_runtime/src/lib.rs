@@ -0,0 +1,21 @@
+// Test-time counterparts of policy_macros: they check what a handler's DynamoDB
+// client actually sends against the policy its policy_attr was given, without
+// deploying anything. The AST and its printer come from policy_dsl, like they
+// do for policy_macros, which writes each handler's policy to
+// policies/<fn>.policy.json. The simulator evaluates the IAM document it
+// compiles that policy to, policies/<fn>.json. The recorder and report compare
+// the calls a test run made with those documents.
+mod requests;
+mod enforcer;
+mod simulator;
+mod recorder;
+mod report;
+
+pub use policy_dsl::policy;
+pub use policy_dsl::config::{KeySchema, PolicyConfig};
+
+pub use enforcer::{AccessDenied, PolicyEnforcer};
+pub use requests::{KeyValue, Request};
+pub use recorder::{record_as, CallRecorder, RecordedCall, Recorded, RECORDING_VAR};
+pub use report::{reconcile, FunctionReport, NeverUsed, NotGranted, RecordedRequest, Report};
+pub use simulator::{Decision, PolicySimulator, RequestContext, Statement};
@@ -0,0 +1,154 @@
+use std::cell::Cell;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::future::Future;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use aws_sdk_dynamodb::config::interceptors::BeforeSerializationInterceptorContextRef;
+use aws_sdk_dynamodb::config::{ConfigBag, Intercept, RuntimeComponents};
+use aws_sdk_dynamodb::error::BoxError;
+use aws_sdk_dynamodb::Client;
+use aws_smithy_runtime_api::client::orchestrator::Metadata;
+use policy_dsl::config::PolicyConfig;
+use serde::{Deserialize, Serialize};
+
+use crate::requests::{self, Request};
+
+// Records the DynamoDB calls handlers make, one JSON line per request, so a
+// test run can be reconciled with the handlers' policies afterwards. Built with
+// policy_macros' `record` feature, policy_attr runs each handler's body under
+// record_as so its calls are attributed to it, and the client only has to go
+// through the recorder, e.g.
+//
+// #[cfg(feature = "record")]
+// let db = policy_runtime::CallRecorder::from_env().client(&db);
+//
+// The calls are appended to the file POLICY_RECORDING names, policy_calls.jsonl
+// when it isn't set. Crates whose tables' keys aren't called PK and SK pass their
+// settings with `.config(PolicyConfig::load(..)?)`, so the keys are recorded.
+
+pub const RECORDING_VAR: &str = "POLICY_RECORDING";
+const DEFAULT_RECORDING: &str = "policy_calls.jsonl";
+
+thread_local! {
+    // the handler whose future is being polled on this thread
+    static FUNCTION: Cell<Option<&'static str>> = const { Cell::new(None) };
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedCall {
+    // None for calls made outside of record_as
+    pub function: Option<String>,
+    pub operation: String,
+    // None for operations that don't work on items, like ListTables
+    pub request: Option<Request>,
+}
+
+// Attributes the calls `future` makes to `function`. The SDK runs interceptors
+// inside the poll of `send()`, so they see the function as long as the call is
+// awaited rather than spawned onto another task.
+pub fn record_as<F: Future>(function: &'static str, future: F) -> Recorded<F> {
+    Recorded { function, future: Box::pin(future) }
+}
+
+pub struct Recorded<F> {
+    function: &'static str,
+    future: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for Recorded<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let previous = FUNCTION.replace(Some(self.function));
+        let poll = self.future.as_mut().poll(cx);
+        FUNCTION.set(previous);
+        poll
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CallRecorder {
+    path: PathBuf,
+    config: PolicyConfig,
+}
+
+impl CallRecorder {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        CallRecorder { path: path.into(), config: PolicyConfig::default() }
+    }
+
+    pub fn from_env() -> Self {
+        CallRecorder::new(env::var(RECORDING_VAR).unwrap_or_else(|_| DEFAULT_RECORDING.to_string()))
+    }
+
+    pub fn config(mut self, config: PolicyConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    // A copy of `client` whose calls are appended to the recording
+    pub fn client(&self, client: &Client) -> Client {
+        let interceptor = RecordingInterceptor { path: self.path.clone(), config: self.config.clone() };
+        let config = client.config().to_builder().interceptor(interceptor).build();
+        Client::from_conf(config)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Vec<RecordedCall>, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|err| format!("Could not read {}: {err}", path.display()))?;
+        text.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(number, line)| {
+                serde_json::from_str(line).map_err(|err| format!("Could not parse {}:{}: {err}", path.display(), number + 1))
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+struct RecordingInterceptor {
+    path: PathBuf,
+    config: PolicyConfig,
+}
+
+impl Intercept for RecordingInterceptor {
+    fn name(&self) -> &'static str {
+        "RecordingInterceptor"
+    }
+
+    // see PolicyInterceptor for why this isn't read_before_execution
+    fn read_before_serialization(
+        &self,
+        context: &BeforeSerializationInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        let function = FUNCTION.get().map(str::to_string);
+        let operation = cfg.load::<Metadata>().map(|metadata| metadata.name()).unwrap_or("Unknown").to_string();
+        let calls: Vec<RecordedCall> = match requests::from_input(context.input(), &self.config) {
+            Some(requests) => requests
+                .into_iter()
+                .map(|request| RecordedCall { function: function.clone(), operation: operation.clone(), request: Some(request) })
+                .collect(),
+            None => vec![RecordedCall { function, operation, request: None }],
+        };
+        let mut lines = String::new();
+        for call in &calls {
+            lines.push_str(&serde_json::to_string(call)?);
+            lines.push('\n');
+        }
+        // one write per call, so concurrent handlers don't interleave their lines
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|err| format!("Could not open {}: {err}", self.path.display()))?;
+        file.write_all(lines.as_bytes()).map_err(|err| format!("Could not write {}: {err}", self.path.display()))?;
+        Ok(())
+    }
+}
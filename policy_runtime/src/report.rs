@@ -0,0 +1,295 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::recorder::RecordedCall;
+use crate::simulator::{action_matches, Decision, PolicySimulator, RequestContext};
+
+// Compares recorded calls with the IAM documents under a crate's policies/: the
+// requests a handler made that its document doesn't allow, and the actions its
+// document allows that no recorded request needed. Deny statements are guards
+// rather than grants, so they're never reported as unused, and neither are the
+// grants of handlers the recording has no calls from, as nothing can be said
+// about what they need.
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub functions: Vec<FunctionReport>,
+    // calls made outside of any handler
+    pub unattributed: Vec<RecordedRequest>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FunctionReport {
+    pub function: String,
+    // false when there's no policies/<fn>.json, so nothing it calls is granted
+    pub has_policy: bool,
+    // false when the recording has no calls from it, because it wasn't run, made
+    // no DynamoDB calls or wasn't built with the record feature
+    pub recorded: bool,
+    pub calls: usize,
+    pub not_granted: Vec<NotGranted>,
+    pub never_used: Vec<NeverUsed>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordedRequest {
+    pub request: String,
+    pub calls: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NotGranted {
+    pub request: String,
+    pub decision: Decision,
+    pub calls: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NeverUsed {
+    // index of the statement in the document
+    pub statement: usize,
+    pub action: String,
+    pub resources: Vec<String>,
+}
+
+impl Report {
+    pub fn has_not_granted(&self) -> bool {
+        self.functions.iter().any(|function| !function.not_granted.is_empty())
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Failed to serialize report")
+    }
+}
+
+fn context(call: &RecordedCall) -> RequestContext {
+    match &call.request {
+        Some(request) => RequestContext::from(request),
+        None => RequestContext::new(format!("dynamodb:{}", call.operation), "*"),
+    }
+}
+
+// The same request made several times is reported once
+fn distinct(calls: &[&RecordedCall]) -> Vec<(RequestContext, usize)> {
+    let mut counts: BTreeMap<String, (RequestContext, usize)> = BTreeMap::new();
+    for call in calls {
+        let context = context(call);
+        counts.entry(context.to_string()).or_insert((context, 0)).1 += 1;
+    }
+    counts.into_values().collect()
+}
+
+// The functions policy_attr wrote an IAM document for, policies/<fn>.json next
+// to the other backends' <fn>.policy.json, <fn>.cedar and <fn>.rego. Its
+// manifest, .generated.json, isn't one.
+fn policy_functions(crate_root: &Path) -> Result<BTreeSet<String>, String> {
+    let policies = crate_root.join("policies");
+    if !policies.exists() {
+        return Ok(BTreeSet::new());
+    }
+    let entries = fs::read_dir(&policies).map_err(|err| format!("Could not read {}: {err}", policies.display()))?;
+    Ok(entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| !name.starts_with('.') && !name.ends_with(".policy.json"))
+        .filter_map(|name| Some(name.strip_suffix(".json")?.to_string()))
+        .collect())
+}
+
+fn reconcile_function(function: &str, calls: &[&RecordedCall], simulator: Option<&PolicySimulator>) -> FunctionReport {
+    let mut not_granted = vec![];
+    // (statement, action) pairs some allowed request needed
+    let mut used = BTreeSet::new();
+    for (context, count) in distinct(calls) {
+        let decision = simulator.map_or(Decision::ImplicitDeny, |simulator| simulator.evaluate(&context));
+        let Some(simulator) = simulator.filter(|_| decision == Decision::Allow) else {
+            not_granted.push(NotGranted { request: context.to_string(), decision, calls: count });
+            continue;
+        };
+        for index in simulator.matching(&context) {
+            let statement = &simulator.statements()[index];
+            for action in statement.action.iter().filter(|action| action_matches(action, &context.action)) {
+                used.insert((index, action.clone()));
+            }
+        }
+    }
+    let recorded = !calls.is_empty();
+    let never_used = simulator
+        .filter(|_| recorded)
+        .map(|simulator| simulator.statements())
+        .unwrap_or_default()
+        .iter()
+        .enumerate()
+        .filter(|(_, statement)| statement.effect == "Allow")
+        .flat_map(|(index, statement)| {
+            statement.action.iter().map(move |action| (index, action, &statement.resource))
+        })
+        .filter(|(index, action, _)| !used.contains(&(*index, action.to_string())))
+        .map(|(statement, action, resources)| NeverUsed { statement, action: action.clone(), resources: resources.clone() })
+        .collect();
+    FunctionReport {
+        function: function.to_string(),
+        has_policy: simulator.is_some(),
+        recorded,
+        calls: calls.len(),
+        not_granted,
+        never_used,
+    }
+}
+
+// Every function with a policy is reported, including those the recording never
+// saw
+pub fn reconcile(crate_root: impl AsRef<Path>, calls: &[RecordedCall]) -> Result<Report, String> {
+    let crate_root = crate_root.as_ref();
+    let mut functions = policy_functions(crate_root)?;
+    functions.extend(calls.iter().filter_map(|call| call.function.clone()));
+    let mut reports = vec![];
+    for function in &functions {
+        let simulator = match crate_root.join("policies").join(format!("{function}.json")).exists() {
+            true => Some(PolicySimulator::for_function(crate_root, function)?),
+            false => None,
+        };
+        let function_calls: Vec<&RecordedCall> =
+            calls.iter().filter(|call| call.function.as_deref() == Some(function.as_str())).collect();
+        reports.push(reconcile_function(function, &function_calls, simulator.as_ref()));
+    }
+    let unattributed: Vec<&RecordedCall> = calls.iter().filter(|call| call.function.is_none()).collect();
+    Ok(Report {
+        functions: reports,
+        unattributed: distinct(&unattributed)
+            .into_iter()
+            .map(|(context, calls)| RecordedRequest { request: context.to_string(), calls })
+            .collect(),
+    })
+}
+
+fn plural(count: usize, word: &str) -> String {
+    match count {
+        1 => format!("1 {word}"),
+        count => format!("{count} {word}s"),
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for function in &self.functions {
+            writeln!(f, "{} ({})", function.function, plural(function.calls, "call"))?;
+            if !function.has_policy {
+                writeln!(f, "  no policies/{}.json", function.function)?;
+            }
+            if !function.recorded {
+                writeln!(f, "  not recorded, so whether its grants are used is unknown")?;
+            }
+            if !function.not_granted.is_empty() {
+                writeln!(f, "  exercised but not granted:")?;
+                for not_granted in &function.not_granted {
+                    writeln!(f, "    {} ({}, {})", not_granted.request, not_granted.decision, plural(not_granted.calls, "call"))?;
+                }
+            }
+            if !function.never_used.is_empty() {
+                writeln!(f, "  granted but never used:")?;
+                for never_used in &function.never_used {
+                    writeln!(
+                        f,
+                        "    {} on {} (statement {})",
+                        never_used.action,
+                        never_used.resources.join(", "),
+                        never_used.statement
+                    )?;
+                }
+            }
+            if function.has_policy && function.recorded && function.not_granted.is_empty() && function.never_used.is_empty() {
+                writeln!(f, "  every call was granted and every grant was used")?;
+            }
+        }
+        if !self.unattributed.is_empty() {
+            writeln!(f, "made outside of a handler:")?;
+            for request in &self.unattributed {
+                writeln!(f, "  {} ({})", request.request, plural(request.calls, "call"))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::requests::{KeyValue, Request};
+
+    const DOCUMENT: &str = r#"{"Statement": [
+        {"Effect": "Allow", "Action": ["dynamodb:GetItem", "dynamodb:PutItem"], "Resource": "arn:aws:dynamodb:*:*:table/Users"},
+        {"Effect": "Deny", "Action": "dynamodb:DeleteItem", "Resource": "*"}
+    ]}"#;
+
+    fn call(function: Option<&str>, operation: &str) -> RecordedCall {
+        let request = Request {
+            operation: operation.to_string(),
+            table: "Users".to_string(),
+            index: None,
+            pk: Some(KeyValue::Exact("USER#1".to_string())),
+            sk: None,
+            attributes: Some(vec!["email".to_string()]),
+            return_values: None,
+        };
+        RecordedCall { function: function.map(str::to_string), operation: operation.to_string(), request: Some(request) }
+    }
+
+    #[test]
+    fn reconciles_recorded_calls_with_the_documents() {
+        let crate_root = env::temp_dir().join(format!("policy_runtime_report_{}", std::process::id()));
+        fs::create_dir_all(crate_root.join("policies")).unwrap();
+        for function in ["get_user", "idle"] {
+            fs::write(crate_root.join("policies").join(format!("{function}.json")), DOCUMENT).unwrap();
+            fs::write(crate_root.join("policies").join(format!("{function}.policy.json")), "[]").unwrap();
+        }
+        let calls = [
+            call(Some("get_user"), "GetItem"),
+            call(Some("get_user"), "GetItem"),
+            call(Some("get_user"), "DeleteItem"),
+            call(Some("untracked"), "GetItem"),
+            call(None, "Query"),
+        ];
+        let report = reconcile(&crate_root, &calls).unwrap();
+        fs::remove_dir_all(&crate_root).unwrap();
+
+        assert!(report.has_not_granted());
+        assert_eq!(
+            report.to_string(),
+            r#"get_user (3 calls)
+  exercised but not granted:
+    dynamodb:DeleteItem on arn:aws:dynamodb:us-east-1:123456789012:table/Users dynamodb:Attributes=[email] dynamodb:LeadingKeys=[USER#1] (ExplicitDeny, 1 call)
+  granted but never used:
+    dynamodb:PutItem on arn:aws:dynamodb:*:*:table/Users (statement 0)
+idle (0 calls)
+  not recorded, so whether its grants are used is unknown
+untracked (1 call)
+  no policies/untracked.json
+  exercised but not granted:
+    dynamodb:GetItem on arn:aws:dynamodb:us-east-1:123456789012:table/Users dynamodb:Attributes=[email] dynamodb:LeadingKeys=[USER#1] dynamodb:Select=[SPECIFIC_ATTRIBUTES] (ImplicitDeny, 1 call)
+made outside of a handler:
+  dynamodb:Query on arn:aws:dynamodb:us-east-1:123456789012:table/Users dynamodb:Attributes=[email] dynamodb:LeadingKeys=[USER#1] dynamodb:Select=[SPECIFIC_ATTRIBUTES] (1 call)
+"#
+        );
+        let idle = &report.functions[1];
+        assert!(idle.has_policy && !idle.recorded && idle.never_used.is_empty());
+    }
+
+    #[test]
+    fn fully_used_grants_are_reported_as_such() {
+        let crate_root = env::temp_dir().join(format!("policy_runtime_report_used_{}", std::process::id()));
+        fs::create_dir_all(crate_root.join("policies")).unwrap();
+        let document = r#"{"Statement": {"Effect": "Allow", "Action": "dynamodb:GetItem", "Resource": "*"}}"#;
+        fs::write(crate_root.join("policies").join("get_user.json"), document).unwrap();
+        let report = reconcile(&crate_root, &[call(Some("get_user"), "GetItem")]).unwrap();
+        fs::remove_dir_all(&crate_root).unwrap();
+
+        assert!(!report.has_not_granted());
+        assert_eq!(report.to_string(), "get_user (1 call)\n  every call was granted and every grant was used\n");
+    }
+}
@@ -0,0 +1,438 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+use aws_sdk_dynamodb::operation::batch_get_item::BatchGetItemInput;
+use aws_sdk_dynamodb::operation::batch_write_item::BatchWriteItemInput;
+use aws_sdk_dynamodb::operation::delete_item::DeleteItemInput;
+use aws_sdk_dynamodb::operation::get_item::GetItemInput;
+use aws_sdk_dynamodb::operation::put_item::PutItemInput;
+use aws_sdk_dynamodb::operation::query::QueryInput;
+use aws_sdk_dynamodb::operation::scan::ScanInput;
+use aws_sdk_dynamodb::operation::transact_get_items::TransactGetItemsInput;
+use aws_sdk_dynamodb::operation::transact_write_items::TransactWriteItemsInput;
+use aws_sdk_dynamodb::operation::update_item::UpdateItemInput;
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue};
+use aws_smithy_runtime_api::client::interceptors::context::Input;
+use policy_dsl::config::{KeySchema, PolicyConfig};
+use policy_dsl::policy::Key;
+use serde::{Deserialize, Serialize};
+
+// Turns the inputs of the SDK's item operations into what a policy talks about:
+// the table, the operation, the key values and the attributes. Which attributes
+// are `$pk` and `$sk` comes from the crate's key schemas.
+
+pub const READ_OPERATIONS: [&str; 4] = ["GetItem", "Query", "Scan", "BatchGetItem"];
+
+type Item = HashMap<String, AttributeValue>;
+type Names = HashMap<String, String>;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum KeyValue {
+    Exact(String),
+    // a query's `begins_with` on the sort key
+    Prefix(String),
+}
+
+// One item operation, batches and transactions are split into one request per item
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Request {
+    // the DynamoDB operation, e.g. "GetItem"
+    pub operation: String,
+    pub table: String,
+    pub index: Option<String>,
+    pub pk: Option<KeyValue>,
+    pub sk: Option<KeyValue>,
+    // None when the request doesn't name them: reads without a projection get
+    // every attribute, deletes don't touch any
+    pub attributes: Option<Vec<String>>,
+    pub return_values: Option<String>,
+}
+
+impl Request {
+    fn new(operation: &str, table: &str) -> Self {
+        Request {
+            operation: operation.to_string(),
+            table: table.to_string(),
+            index: None,
+            pk: None,
+            sk: None,
+            attributes: None,
+            return_values: None,
+        }
+    }
+
+    fn with_key(mut self, key: &Item, config: &PolicyConfig) -> Self {
+        let keys = config.key_schema(&self.table);
+        self.pk = key_value(key, keys.attribute(Key::Pk));
+        self.sk = key_value(key, keys.attribute(Key::Sk));
+        self
+    }
+
+    pub fn is_read(&self) -> bool {
+        READ_OPERATIONS.contains(&self.operation.as_str())
+    }
+}
+
+impl Display for KeyValue {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            KeyValue::Exact(value) => write!(f, "{value:?}"),
+            KeyValue::Prefix(prefix) => write!(f, "{prefix:?}..."),
+        }
+    }
+}
+
+impl Display for Request {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match &self.index {
+            Some(index) => write!(f, "{} on index {index:?} of table {:?}", self.operation, self.table)?,
+            None => write!(f, "{} on table {:?}", self.operation, self.table)?,
+        }
+        let keys: Vec<String> = [("$pk", &self.pk), ("$sk", &self.sk)]
+            .into_iter()
+            .filter_map(|(key, value)| Some(format!("{key} {}", value.as_ref()?)))
+            .collect();
+        if !keys.is_empty() {
+            write!(f, " with {}", keys.join(" and "))?;
+        }
+        match &self.attributes {
+            Some(attributes) => write!(f, " on attributes [{}]", attributes.join(", "))?,
+            None if self.is_read() => write!(f, " on every attribute")?,
+            None => {}
+        }
+        if let Some(return_values) = &self.return_values {
+            write!(f, " returning {return_values}")?;
+        }
+        Ok(())
+    }
+}
+
+fn key_value(item: &Item, attribute: &str) -> Option<KeyValue> {
+    match item.get(attribute)? {
+        AttributeValue::S(value) | AttributeValue::N(value) => Some(KeyValue::Exact(value.clone())),
+        _ => None,
+    }
+}
+
+fn resolve_name(name: &str, names: Option<&Names>) -> Option<String> {
+    match name.starts_with('#') {
+        true => names?.get(name).cloned(),
+        false => Some(name.to_string()),
+    }
+}
+
+// `a.b[0]` reads attribute `a`
+fn top_level(path: &str, names: Option<&Names>) -> Option<String> {
+    let name = path.trim().split(['.', '[']).next()?.trim();
+    resolve_name(name, names)
+}
+
+fn item_attributes(item: &Item) -> Option<Vec<String>> {
+    let mut attributes: Vec<String> = item.keys().cloned().collect();
+    attributes.sort();
+    Some(attributes)
+}
+
+fn projection(expr: Option<&str>, attributes_to_get: &[String], names: Option<&Names>) -> Option<Vec<String>> {
+    match expr {
+        Some(expr) => expr.split(',').map(|path| top_level(path, names)).collect(),
+        None if !attributes_to_get.is_empty() => Some(attributes_to_get.to_vec()),
+        None => None,
+    }
+}
+
+// The attributes `SET a = :a, b = list_append(b, :b) REMOVE c` writes
+fn update_attributes(expr: &str, names: Option<&Names>) -> Option<Vec<String>> {
+    let mut attributes = vec![];
+    // commas inside function calls leave pieces without a path in front, which
+    // are skipped
+    for (keyword, body) in split_clauses(expr) {
+        for action in body.split(',') {
+            let path = match keyword.as_str() {
+                "SET" => match action.split_once('=') {
+                    Some((path, _)) => path,
+                    None => continue,
+                },
+                "REMOVE" => action,
+                _ => match action.split_whitespace().next() {
+                    Some(path) => path,
+                    None => continue,
+                },
+            };
+            if path.trim().is_empty() || path.contains(')') {
+                continue;
+            }
+            attributes.push(top_level(path, names)?);
+        }
+    }
+    attributes.sort();
+    attributes.dedup();
+    Some(attributes)
+}
+
+fn split_clauses(expr: &str) -> Vec<(String, String)> {
+    let mut clauses: Vec<(String, String)> = vec![];
+    for word in expr.split_whitespace() {
+        let keyword = word.to_ascii_uppercase();
+        if matches!(keyword.as_str(), "SET" | "REMOVE" | "ADD" | "DELETE") {
+            clauses.push((keyword, String::new()));
+        } else if let Some((_, body)) = clauses.last_mut() {
+            body.push(' ');
+            body.push_str(word);
+        }
+    }
+    clauses
+}
+
+// `PK = :pk AND begins_with(SK, :prefix)`, range conditions on the sort key
+// don't pin it down
+fn key_condition(request: &mut Request, expr: &str, values: Option<&Item>, names: Option<&Names>, keys: &KeySchema) {
+    let value = |placeholder: &str| match values?.get(placeholder.trim())? {
+        AttributeValue::S(value) | AttributeValue::N(value) => Some(value.clone()),
+        _ => None,
+    };
+    for clause in expr.replace(" and ", " AND ").split(" AND ") {
+        let clause = clause.trim();
+        let (attribute, key_value) = if let Some(args) = clause
+            .strip_prefix("begins_with")
+            .map(str::trim)
+            .and_then(|args| args.strip_prefix('('))
+            .and_then(|args| args.strip_suffix(')'))
+        {
+            let Some((attribute, placeholder)) = args.split_once(',') else {
+                continue;
+            };
+            (attribute, value(placeholder).map(KeyValue::Prefix))
+        } else if let Some((attribute, placeholder)) = clause.split_once('=') {
+            (attribute, value(placeholder).map(KeyValue::Exact))
+        } else {
+            continue;
+        };
+        match resolve_name(attribute.trim(), names).and_then(|attribute| keys.key(&attribute)) {
+            Some(Key::Pk) => request.pk = key_value,
+            Some(Key::Sk) => request.sk = key_value,
+            None => {}
+        }
+    }
+}
+
+fn return_values(return_values: Option<&ReturnValue>) -> Option<String> {
+    return_values.map(|return_values| return_values.as_str().to_string())
+}
+
+// None for operations that don't work on items, like CreateTable or ListTables
+pub fn from_input(input: &Input, config: &PolicyConfig) -> Option<Vec<Request>> {
+    if let Some(input) = input.downcast_ref::<GetItemInput>() {
+        let mut request = Request::new("GetItem", input.table_name()?).with_key(input.key()?, config);
+        request.attributes =
+            projection(input.projection_expression(), input.attributes_to_get(), input.expression_attribute_names());
+        return Some(vec![request]);
+    }
+    if let Some(input) = input.downcast_ref::<PutItemInput>() {
+        let mut request = Request::new("PutItem", input.table_name()?).with_key(input.item()?, config);
+        request.attributes = item_attributes(input.item()?);
+        request.return_values = return_values(input.return_values());
+        return Some(vec![request]);
+    }
+    if let Some(input) = input.downcast_ref::<UpdateItemInput>() {
+        let mut request = Request::new("UpdateItem", input.table_name()?).with_key(input.key()?, config);
+        request.attributes = input
+            .update_expression()
+            .and_then(|expr| update_attributes(expr, input.expression_attribute_names()));
+        request.return_values = return_values(input.return_values());
+        return Some(vec![request]);
+    }
+    if let Some(input) = input.downcast_ref::<DeleteItemInput>() {
+        let mut request = Request::new("DeleteItem", input.table_name()?).with_key(input.key()?, config);
+        request.return_values = return_values(input.return_values());
+        return Some(vec![request]);
+    }
+    if let Some(input) = input.downcast_ref::<QueryInput>() {
+        let mut request = Request::new("Query", input.table_name()?);
+        request.index = input.index_name().map(str::to_string);
+        if let Some(expr) = input.key_condition_expression() {
+            let keys = config.key_schema(&request.table);
+            key_condition(&mut request, expr, input.expression_attribute_values(), input.expression_attribute_names(), &keys);
+        }
+        request.attributes =
+            projection(input.projection_expression(), input.attributes_to_get(), input.expression_attribute_names());
+        return Some(vec![request]);
+    }
+    if let Some(input) = input.downcast_ref::<ScanInput>() {
+        let mut request = Request::new("Scan", input.table_name()?);
+        request.index = input.index_name().map(str::to_string);
+        request.attributes =
+            projection(input.projection_expression(), input.attributes_to_get(), input.expression_attribute_names());
+        return Some(vec![request]);
+    }
+    if let Some(input) = input.downcast_ref::<BatchGetItemInput>() {
+        let mut requests = vec![];
+        for (table, keys_and_attributes) in input.request_items()? {
+            let attributes = projection(
+                keys_and_attributes.projection_expression(),
+                keys_and_attributes.attributes_to_get(),
+                keys_and_attributes.expression_attribute_names(),
+            );
+            for key in keys_and_attributes.keys() {
+                let mut request = Request::new("BatchGetItem", table).with_key(key, config);
+                request.attributes = attributes.clone();
+                requests.push(request);
+            }
+        }
+        return Some(requests);
+    }
+    if let Some(input) = input.downcast_ref::<BatchWriteItemInput>() {
+        let mut requests = vec![];
+        for (table, write_requests) in input.request_items()? {
+            for write_request in write_requests {
+                if let Some(put) = write_request.put_request() {
+                    let mut request = Request::new("BatchWriteItem", table).with_key(put.item(), config);
+                    request.attributes = item_attributes(put.item());
+                    requests.push(request);
+                }
+                if let Some(delete) = write_request.delete_request() {
+                    requests.push(Request::new("BatchWriteItem", table).with_key(delete.key(), config));
+                }
+            }
+        }
+        return Some(requests);
+    }
+    // transactions are authorized item by item as the equivalent single-item request
+    if let Some(input) = input.downcast_ref::<TransactGetItemsInput>() {
+        let mut requests = vec![];
+        for get in input.transact_items().iter().filter_map(|item| item.get()) {
+            let mut request = Request::new("GetItem", get.table_name()).with_key(get.key(), config);
+            request.attributes = projection(get.projection_expression(), &[], get.expression_attribute_names());
+            requests.push(request);
+        }
+        return Some(requests);
+    }
+    if let Some(input) = input.downcast_ref::<TransactWriteItemsInput>() {
+        let mut requests = vec![];
+        for item in input.transact_items() {
+            if let Some(put) = item.put() {
+                let mut request = Request::new("PutItem", put.table_name()).with_key(put.item(), config);
+                request.attributes = item_attributes(put.item());
+                requests.push(request);
+            }
+            if let Some(update) = item.update() {
+                let mut request = Request::new("UpdateItem", update.table_name()).with_key(update.key(), config);
+                request.attributes = update_attributes(update.update_expression(), update.expression_attribute_names());
+                requests.push(request);
+            }
+            if let Some(delete) = item.delete() {
+                requests.push(Request::new("DeleteItem", delete.table_name()).with_key(delete.key(), config));
+            }
+            if let Some(check) = item.condition_check() {
+                requests.push(Request::new("ConditionCheckItem", check.table_name()).with_key(check.key(), config));
+            }
+        }
+        return Some(requests);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use aws_sdk_dynamodb::types::{Put, TransactWriteItem, Update};
+    use aws_smithy_runtime_api::client::interceptors::context::Input;
+
+    use super::*;
+
+    fn s(value: &str) -> AttributeValue {
+        AttributeValue::S(value.to_string())
+    }
+
+    fn item(values: &[(&str, &str)]) -> Item {
+        values.iter().map(|(name, value)| (name.to_string(), s(value))).collect()
+    }
+
+    fn requests(input: impl std::fmt::Debug + Send + Sync + 'static, config: &PolicyConfig) -> Vec<Request> {
+        from_input(&Input::erase(input), config).unwrap()
+    }
+
+    #[test]
+    fn get_item_reads_its_projection() {
+        let input = GetItemInput::builder()
+            .table_name("Users")
+            .set_key(Some(item(&[("PK", "USER#1"), ("SK", "PROFILE")])))
+            .projection_expression("#name, email, address.city")
+            .expression_attribute_names("#name", "full_name")
+            .build()
+            .unwrap();
+        let [request] = requests(input, &PolicyConfig::default()).try_into().unwrap();
+        assert_eq!(request.pk, Some(KeyValue::Exact("USER#1".to_string())));
+        assert_eq!(request.sk, Some(KeyValue::Exact("PROFILE".to_string())));
+        assert_eq!(request.attributes, Some(vec!["full_name".to_string(), "email".to_string(), "address".to_string()]));
+        assert_eq!(request.to_string(), r#"GetItem on table "Users" with $pk "USER#1" and $sk "PROFILE" on attributes [full_name, email, address]"#);
+    }
+
+    #[test]
+    fn queries_pin_down_what_their_key_condition_does() {
+        let mut config = PolicyConfig::default();
+        config
+            .key_schemas
+            .insert("Messages".to_string(), KeySchema { pk: "ChannelId".to_string(), sk: "SentAt".to_string() });
+        let query = |condition: &str| {
+            let input = QueryInput::builder()
+                .table_name("Messages")
+                .key_condition_expression(condition)
+                .expression_attribute_values(":channel", s("general"))
+                .expression_attribute_values(":since", s("2024"))
+                .build()
+                .unwrap();
+            let [request] = requests(input, &config).try_into().unwrap();
+            request
+        };
+        let request = query("ChannelId = :channel and begins_with(SentAt, :since)");
+        assert_eq!(request.pk, Some(KeyValue::Exact("general".to_string())));
+        assert_eq!(request.sk, Some(KeyValue::Prefix("2024".to_string())));
+        assert_eq!(request.attributes, None);
+        assert_eq!(request.to_string(), r#"Query on table "Messages" with $pk "general" and $sk "2024"... on every attribute"#);
+        // a range doesn't pin the sort key down
+        assert_eq!(query("ChannelId = :channel AND SentAt > :since").sk, None);
+    }
+
+    #[test]
+    fn updates_name_the_attributes_they_write() {
+        let input = UpdateItemInput::builder()
+            .table_name("Users")
+            .set_key(Some(item(&[("PK", "USER#1"), ("SK", "PROFILE")])))
+            .update_expression("SET #n = :n, tags = list_append(tags, :t) REMOVE email ADD visits :one")
+            .expression_attribute_names("#n", "full_name")
+            .return_values(ReturnValue::AllNew)
+            .build()
+            .unwrap();
+        let [request] = requests(input, &PolicyConfig::default()).try_into().unwrap();
+        assert_eq!(
+            request.attributes,
+            Some(vec!["email".to_string(), "full_name".to_string(), "tags".to_string(), "visits".to_string()])
+        );
+        assert_eq!(request.return_values.as_deref(), Some("ALL_NEW"));
+    }
+
+    #[test]
+    fn transactions_are_split_into_single_item_requests() {
+        let put = Put::builder().table_name("Users").set_item(Some(item(&[("PK", "USER#1"), ("SK", "FRIEND#2")]))).build().unwrap();
+        let update = Update::builder()
+            .table_name("Users")
+            .set_key(Some(item(&[("PK", "USER#2"), ("SK", "PROFILE")])))
+            .update_expression("SET friends = :friends")
+            .build()
+            .unwrap();
+        let input = TransactWriteItemsInput::builder()
+            .transact_items(TransactWriteItem::builder().put(put).build())
+            .transact_items(TransactWriteItem::builder().update(update).build())
+            .build()
+            .unwrap();
+        let requests = requests(input, &PolicyConfig::default());
+        let summary: Vec<String> = requests.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            summary,
+            [
+                r#"PutItem on table "Users" with $pk "USER#1" and $sk "FRIEND#2" on attributes [PK, SK]"#,
+                r#"UpdateItem on table "Users" with $pk "USER#2" and $sk "PROFILE" on attributes [friends]"#,
+            ]
+        );
+    }
+}
@@ -0,0 +1,578 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+use crate::requests::{KeyValue, Request};
+
+// Evaluates a compiled IAM document the way IAM does for an identity policy, so
+// a generated policy can be checked without deploying it, e.g.
+//
+// let simulator = PolicySimulator::for_function(env!("CARGO_MANIFEST_DIR"), "get_profile")?;
+// assert_allows!(
+//     simulator,
+//     RequestContext::new("dynamodb:GetItem", "arn:aws:dynamodb:us-east-1:123456789012:table/Users")
+//         .key("dynamodb:LeadingKeys", "USER#1")
+//         .keys("dynamodb:Attributes", ["full_name", "email"])
+//         .key("dynamodb:Select", "SPECIFIC_ATTRIBUTES")
+// );
+//
+// An explicit deny wins over any allow, and a request no statement allows is
+// implicitly denied. Operators the simulator doesn't know (the Date ones) never
+// hold.
+
+// Deployment placeholders of configured ARN templates and the values they're
+// filled in with unless the simulator is told otherwise, they match the ARNs
+// RequestContext::from builds
+const PLACEHOLDERS: [(&str, &str); 4] =
+    [("partition", "aws"), ("region", "us-east-1"), ("account", "123456789012"), ("stage", "dev")];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Decision {
+    Allow,
+    ExplicitDeny,
+    ImplicitDeny,
+}
+
+impl Display for Decision {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Decision::Allow => write!(f, "Allow"),
+            Decision::ExplicitDeny => write!(f, "ExplicitDeny"),
+            Decision::ImplicitDeny => write!(f, "ImplicitDeny"),
+        }
+    }
+}
+
+// What IAM knows about a request: the action, the resource and the condition
+// keys, multi-valued keys like dynamodb:LeadingKeys hold several values
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestContext {
+    pub action: String,
+    pub resource: String,
+    pub keys: BTreeMap<String, Vec<String>>,
+}
+
+impl RequestContext {
+    pub fn new(action: impl Into<String>, resource: impl Into<String>) -> Self {
+        RequestContext { action: action.into(), resource: resource.into(), keys: BTreeMap::new() }
+    }
+
+    pub fn key(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.keys.entry(key.to_string()).or_default().push(value.into());
+        self
+    }
+
+    pub fn keys<V: Into<String>>(mut self, key: &str, values: impl IntoIterator<Item = V>) -> Self {
+        self.keys.entry(key.to_string()).or_default().extend(values.into_iter().map(Into::into));
+        self
+    }
+
+    // The value a policy variable stands for, only single-valued keys can be used
+    fn variable(&self, key: &str) -> Option<&str> {
+        match self.keys.get(key)?.as_slice() {
+            [value] => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl Display for RequestContext {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} on {}", self.action, self.resource)?;
+        for (key, values) in &self.keys {
+            write!(f, " {key}=[{}]", values.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+// The context IAM builds for a request the client sends, in the default account
+// and region of PLACEHOLDERS. Reads that don't name their attributes select all
+// of them.
+impl From<&Request> for RequestContext {
+    fn from(request: &Request) -> Self {
+        let mut resource = format!("arn:aws:dynamodb:{}:{}:table/{}", PLACEHOLDERS[1].1, PLACEHOLDERS[2].1, request.table);
+        if let Some(index) = &request.index {
+            resource = format!("{resource}/index/{index}");
+        }
+        let mut context = RequestContext::new(format!("dynamodb:{}", request.operation), resource);
+        if let Some(KeyValue::Exact(pk)) = &request.pk {
+            context = context.key("dynamodb:LeadingKeys", pk.clone());
+        }
+        if let Some(attributes) = &request.attributes {
+            context = context.keys("dynamodb:Attributes", attributes.iter().cloned());
+        }
+        if request.is_read() {
+            let select = match request.attributes {
+                Some(_) => "SPECIFIC_ATTRIBUTES",
+                None => "ALL_ATTRIBUTES",
+            };
+            context = context.key("dynamodb:Select", select);
+        }
+        if let Some(return_values) = &request.return_values {
+            context = context.key("dynamodb:ReturnValues", return_values.clone());
+        }
+        context
+    }
+}
+
+impl From<Request> for RequestContext {
+    fn from(request: Request) -> Self {
+        RequestContext::from(&request)
+    }
+}
+
+// IAM accepts a single string wherever it takes a list, and booleans or numbers
+// as condition values
+fn strings<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    let scalar = |value: Value| match value {
+        Value::String(value) => value,
+        value => value.to_string(),
+    };
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Null => vec![],
+        Value::Array(values) => values.into_iter().map(scalar).collect(),
+        value => vec![scalar(value)],
+    })
+}
+
+fn statements<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Statement>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(Box<Statement>),
+        Many(Vec<Statement>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(statement) => vec![*statement],
+        OneOrMany::Many(statements) => statements,
+    })
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+struct Values(#[serde(deserialize_with = "strings")] Vec<String>);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Statement {
+    #[serde(default)]
+    pub sid: Option<String>,
+    pub effect: String,
+    #[serde(default, deserialize_with = "strings")]
+    pub action: Vec<String>,
+    #[serde(default, deserialize_with = "strings")]
+    pub not_action: Vec<String>,
+    #[serde(default, deserialize_with = "strings")]
+    pub resource: Vec<String>,
+    #[serde(default, deserialize_with = "strings")]
+    pub not_resource: Vec<String>,
+    // operator -> condition key -> values
+    #[serde(default)]
+    condition: BTreeMap<String, BTreeMap<String, Values>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PolicyDocument {
+    #[serde(deserialize_with = "statements")]
+    statement: Vec<Statement>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PolicySimulator {
+    statements: Vec<Statement>,
+    // deployment placeholder -> the value it's filled in with
+    placeholders: HashMap<String, String>,
+}
+
+impl PolicySimulator {
+    pub fn new(document: &str) -> Result<Self, String> {
+        let document: PolicyDocument =
+            serde_json::from_str(document).map_err(|err| format!("Could not parse policy document: {err}"))?;
+        let placeholders = PLACEHOLDERS.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        Ok(PolicySimulator { statements: document.statement, placeholders })
+    }
+
+    // Reads the IAM document policy_attr wrote for `function` under `crate_root`
+    pub fn for_function(crate_root: impl AsRef<Path>, function: &str) -> Result<Self, String> {
+        let path = crate_root.as_ref().join("policies").join(format!("{function}.json"));
+        let text = fs::read_to_string(&path).map_err(|err| format!("Could not read {}: {err}", path.display()))?;
+        PolicySimulator::new(&text).map_err(|err| format!("{}: {err}", path.display()))
+    }
+
+    // Fills `${name}` in the document's resources with `value` instead of its default
+    pub fn placeholder(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.placeholders.insert(name.to_string(), value.into());
+        self
+    }
+
+    pub fn statements(&self) -> &[Statement] {
+        &self.statements
+    }
+
+    // The indexes of the statements that apply to the request, whatever their effect
+    pub fn matching(&self, context: &RequestContext) -> Vec<usize> {
+        (0..self.statements.len()).filter(|&index| self.applies(&self.statements[index], context)).collect()
+    }
+
+    pub fn evaluate(&self, context: &RequestContext) -> Decision {
+        let matching = self.matching(context);
+        let effects = matching.iter().map(|&index| self.statements[index].effect.as_str());
+        if effects.clone().any(|effect| effect == "Deny") {
+            Decision::ExplicitDeny
+        } else if effects.clone().any(|effect| effect == "Allow") {
+            Decision::Allow
+        } else {
+            Decision::ImplicitDeny
+        }
+    }
+
+    fn fill_placeholders(&self, resource: &str) -> String {
+        self.placeholders
+            .iter()
+            .fold(resource.to_string(), |resource, (name, value)| resource.replace(&format!("${{{name}}}"), value))
+    }
+
+    fn applies(&self, statement: &Statement, context: &RequestContext) -> bool {
+        let action_matches = |pattern: &String| action_matches(pattern, &context.action);
+        let resource_matches = |pattern: &String| {
+            parse_pattern(&self.fill_placeholders(pattern), Some(context), true)
+                .is_some_and(|pattern| glob(&pattern, &context.resource))
+        };
+        let actions = match statement.action.is_empty() {
+            false => statement.action.iter().any(action_matches),
+            true => !statement.not_action.is_empty() && !statement.not_action.iter().any(action_matches),
+        };
+        let resources = match statement.resource.is_empty() {
+            false => statement.resource.iter().any(resource_matches),
+            true => !statement.not_resource.is_empty() && !statement.not_resource.iter().any(resource_matches),
+        };
+        actions
+            && resources
+            && statement.condition.iter().all(|(operator, keys)| {
+                keys.iter().all(|(key, values)| condition_holds(operator, key, &values.0, context))
+            })
+    }
+}
+
+// Actions are matched ignoring case, e.g. "dynamodb:get*" matches "dynamodb:GetItem"
+pub(crate) fn action_matches(pattern: &str, action: &str) -> bool {
+    parse_pattern(&pattern.to_ascii_lowercase(), None, true).is_some_and(|pattern| glob(&pattern, &action.to_ascii_lowercase()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+    Char(char),
+    // `*`
+    Any,
+    // `?`
+    One,
+}
+
+// A policy string with its `${key}` variables filled in from the context, and
+// `${*}`, `${?}` and `${$}` standing for the characters themselves. None when
+// it uses a variable the context has no value for (and no `${key, 'default'}`).
+fn parse_pattern(text: &str, context: Option<&RequestContext>, wildcards: bool) -> Option<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '$' if chars.peek() == Some(&'{') => {
+                chars.next();
+                let variable: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                let value = match variable.as_str() {
+                    "*" | "?" | "$" => variable.clone(),
+                    _ => {
+                        let (key, default) = match variable.split_once(',') {
+                            Some((key, default)) => (key.trim(), Some(default.trim().trim_matches('\''))),
+                            None => (variable.trim(), None),
+                        };
+                        context.and_then(|context| context.variable(key)).or(default)?.to_string()
+                    }
+                };
+                tokens.extend(value.chars().map(Token::Char));
+            }
+            '*' if wildcards => tokens.push(Token::Any),
+            '?' if wildcards => tokens.push(Token::One),
+            c => tokens.push(Token::Char(c)),
+        }
+    }
+    Some(tokens)
+}
+
+fn glob(pattern: &[Token], value: &str) -> bool {
+    let value: Vec<char> = value.chars().collect();
+    // the last `*` seen and where in the value it started matching
+    let (mut p, mut v, mut star) = (0, 0, None);
+    while v < value.len() {
+        match pattern.get(p) {
+            Some(Token::Any) => {
+                star = Some((p, v));
+                p += 1;
+            }
+            Some(Token::One) => (p, v) = (p + 1, v + 1),
+            Some(Token::Char(c)) if *c == value[v] => (p, v) = (p + 1, v + 1),
+            _ => match star {
+                Some((star_p, star_v)) => {
+                    star = Some((star_p, star_v + 1));
+                    (p, v) = (star_p + 1, star_v + 1);
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|token| *token == Token::Any)
+}
+
+fn in_network(value: &str, network: &str) -> Option<bool> {
+    let address: IpAddr = value.parse().ok()?;
+    let (network, bits) = match network.split_once('/') {
+        Some((network, bits)) => (network.parse::<IpAddr>().ok()?, Some(bits.parse::<u32>().ok()?)),
+        None => (network.parse::<IpAddr>().ok()?, None),
+    };
+    match (address, network) {
+        (IpAddr::V4(address), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - bits.unwrap_or(32).min(32)).unwrap_or(0);
+            Some(u32::from(address) & mask == u32::from(network) & mask)
+        }
+        (IpAddr::V6(address), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - bits.unwrap_or(128).min(128)).unwrap_or(0);
+            Some(u128::from(address) & mask == u128::from(network) & mask)
+        }
+        _ => Some(false),
+    }
+}
+
+// The operators compare knows, negated ones are compared through positive
+const OPERATORS: [&str; 13] = [
+    "StringEquals",
+    "BinaryEquals",
+    "StringEqualsIgnoreCase",
+    "StringLike",
+    "ArnEquals",
+    "ArnLike",
+    "Bool",
+    "NumericEquals",
+    "NumericLessThan",
+    "NumericLessThanEquals",
+    "NumericGreaterThan",
+    "NumericGreaterThanEquals",
+    "IpAddress",
+];
+
+// Whether one value of the request compares true against one value of the policy
+fn compare(operator: &str, value: &str, expected: &str, context: &RequestContext) -> bool {
+    let number = |text: &str| text.parse::<f64>().ok();
+    let numeric = |holds: fn(f64, f64) -> bool| matches!((number(value), number(expected)), (Some(a), Some(b)) if holds(a, b));
+    match operator {
+        "StringEquals" | "BinaryEquals" => parse_pattern(expected, Some(context), false).is_some_and(|p| glob(&p, value)),
+        "StringEqualsIgnoreCase" => {
+            parse_pattern(&expected.to_lowercase(), Some(context), false).is_some_and(|p| glob(&p, &value.to_lowercase()))
+        }
+        "StringLike" | "ArnEquals" | "ArnLike" => parse_pattern(expected, Some(context), true).is_some_and(|p| glob(&p, value)),
+        "Bool" => value.eq_ignore_ascii_case(expected),
+        "NumericEquals" => numeric(|a, b| a == b),
+        "NumericLessThan" => numeric(|a, b| a < b),
+        "NumericLessThanEquals" => numeric(|a, b| a <= b),
+        "NumericGreaterThan" => numeric(|a, b| a > b),
+        "NumericGreaterThanEquals" => numeric(|a, b| a >= b),
+        "IpAddress" => in_network(value, expected).unwrap_or(false),
+        _ => false,
+    }
+}
+
+// Negated operators hold when a value matches none of the policy's values
+fn positive(operator: &str) -> (&str, bool) {
+    match operator {
+        "StringNotEquals" => ("StringEquals", true),
+        "StringNotEqualsIgnoreCase" => ("StringEqualsIgnoreCase", true),
+        "StringNotLike" => ("StringLike", true),
+        "NumericNotEquals" => ("NumericEquals", true),
+        "ArnNotEquals" => ("ArnEquals", true),
+        "ArnNotLike" => ("ArnLike", true),
+        "NotIpAddress" => ("IpAddress", true),
+        _ => (operator, false),
+    }
+}
+
+// A missing key makes a condition false, unless the operator is negated or ends
+// in IfExists. ForAllValues holds when every value of the request matches (so
+// also when there are none), ForAnyValue when at least one does.
+fn condition_holds(operator: &str, key: &str, expected: &[String], context: &RequestContext) -> bool {
+    let (set, operator) = match operator.split_once(':') {
+        Some((set @ ("ForAllValues" | "ForAnyValue"), operator)) => (Some(set), operator),
+        _ => (None, operator),
+    };
+    let (operator, if_exists) = match operator.strip_suffix("IfExists") {
+        Some(operator) => (operator, true),
+        None => (operator, false),
+    };
+    let values = context.keys.get(key).map(Vec::as_slice).unwrap_or_default();
+    if operator == "Null" {
+        return expected.iter().any(|expected| expected.eq_ignore_ascii_case("true") == values.is_empty());
+    }
+    let (operator, negated) = positive(operator);
+    if !OPERATORS.contains(&operator) {
+        return false;
+    }
+    let value_holds = |value: &String| expected.iter().any(|expected| compare(operator, value, expected, context)) != negated;
+    match set {
+        Some("ForAllValues") => values.iter().all(value_holds),
+        Some(_) => values.iter().any(value_holds),
+        None if values.is_empty() => if_exists || negated,
+        None => values.iter().any(value_holds),
+    }
+}
+
+// Fails the test unless the simulator allows the request, which is a
+// RequestContext or a Request
+#[macro_export]
+macro_rules! assert_allows {
+    ($simulator:expr, $context:expr $(,)?) => {{
+        let context = $crate::RequestContext::from($context);
+        let decision = $simulator.evaluate(&context);
+        if decision != $crate::Decision::Allow {
+            panic!("expected the policy to allow {context}, got {decision}");
+        }
+    }};
+    ($simulator:expr, $context:expr, $($message:tt)+) => {{
+        let context = $crate::RequestContext::from($context);
+        let decision = $simulator.evaluate(&context);
+        if decision != $crate::Decision::Allow {
+            panic!("expected the policy to allow {context}, got {decision}: {}", format!($($message)+));
+        }
+    }};
+}
+
+// Fails the test if the simulator allows the request, whether it's denied
+// explicitly or implicitly
+#[macro_export]
+macro_rules! assert_denies {
+    ($simulator:expr, $context:expr $(,)?) => {{
+        let context = $crate::RequestContext::from($context);
+        let decision = $simulator.evaluate(&context);
+        if decision == $crate::Decision::Allow {
+            panic!("expected the policy to deny {context}, got {decision}");
+        }
+    }};
+    ($simulator:expr, $context:expr, $($message:tt)+) => {{
+        let context = $crate::RequestContext::from($context);
+        let decision = $simulator.evaluate(&context);
+        if decision == $crate::Decision::Allow {
+            panic!("expected the policy to deny {context}, got {decision}: {}", format!($($message)+));
+        }
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TABLE: &str = "arn:aws:dynamodb:us-east-1:123456789012:table/dev-Users";
+
+    // What policy_macros compiles `allow read on table "Users" where key_equals
+    // $pk concat("USER#", $claims.sub) with attributes ["email"]` and a deny of
+    // the `role` attribute to, with an ARN template
+    const DOCUMENT: &str = r#"{
+        "Version": "2012-10-17",
+        "Statement": [
+            {
+                "Effect": "Allow",
+                "Action": ["dynamodb:GetItem", "dynamodb:Query"],
+                "Resource": "arn:${partition}:dynamodb:${region}:${account}:table/${stage}-Users",
+                "Condition": {
+                    "ForAllValues:StringEquals": {
+                        "dynamodb:LeadingKeys": "USER#${aws:PrincipalTag/sub}",
+                        "dynamodb:Attributes": ["PK", "SK", "email", "role"]
+                    },
+                    "StringEqualsIfExists": {"dynamodb:Select": "SPECIFIC_ATTRIBUTES"}
+                }
+            },
+            {
+                "Effect": "Deny",
+                "Action": "dynamodb:*",
+                "Resource": "*",
+                "Condition": {"ForAnyValue:StringEquals": {"dynamodb:Attributes": "role"}}
+            }
+        ]
+    }"#;
+
+    fn get(attributes: &[&str]) -> RequestContext {
+        RequestContext::new("dynamodb:GetItem", TABLE)
+            .key("aws:PrincipalTag/sub", "1")
+            .key("dynamodb:LeadingKeys", "USER#1")
+            .keys("dynamodb:Attributes", attributes.iter().copied())
+            .key("dynamodb:Select", "SPECIFIC_ATTRIBUTES")
+    }
+
+    #[test]
+    fn conditions_and_policy_variables() {
+        let simulator = PolicySimulator::new(DOCUMENT).unwrap();
+        assert_allows!(simulator, get(&["email"]));
+        assert_allows!(simulator, get(&[]), "ForAllValues holds without any values");
+        assert_denies!(simulator, get(&["email", "phone"]));
+        assert_denies!(simulator, get(&["email"]).key("aws:PrincipalTag/sub", "2"), "another user's partition");
+        let mut all_attributes = get(&["email"]);
+        all_attributes.keys.insert("dynamodb:Select".to_string(), vec!["ALL_ATTRIBUTES".to_string()]);
+        assert_denies!(simulator, all_attributes);
+        // the policy variable has no value without the tag
+        let mut untagged = get(&["email"]);
+        untagged.keys.remove("aws:PrincipalTag/sub");
+        assert_eq!(simulator.evaluate(&untagged), Decision::ImplicitDeny);
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let simulator = PolicySimulator::new(DOCUMENT).unwrap();
+        let role = get(&["role"]);
+        assert_eq!(simulator.evaluate(&role), Decision::ExplicitDeny);
+        assert_eq!(simulator.matching(&role), [0, 1]);
+        assert_denies!(simulator, role);
+    }
+
+    #[test]
+    fn placeholders_and_actions() {
+        let simulator = PolicySimulator::new(DOCUMENT).unwrap();
+        assert_denies!(simulator, RequestContext::new("dynamodb:PutItem", TABLE).key("aws:PrincipalTag/sub", "1"));
+        let prod = "arn:aws:dynamodb:us-east-1:123456789012:table/prod-Users";
+        assert_denies!(simulator, RequestContext { resource: prod.to_string(), ..get(&["email"]) });
+        let simulator = simulator.placeholder("stage", "prod");
+        assert_allows!(simulator, RequestContext { resource: prod.to_string(), ..get(&["email"]) });
+        // actions ignore case, resources don't
+        assert_allows!(simulator, RequestContext { action: "dynamodb:getitem".to_string(), resource: prod.to_string(), ..get(&["email"]) });
+        assert_denies!(simulator, RequestContext { resource: prod.to_uppercase(), ..get(&["email"]) });
+    }
+
+    #[test]
+    fn requests_are_evaluated_as_iam_sees_them() {
+        let document = r#"{"Statement": {"Effect": "Allow", "NotAction": "dynamodb:Scan", "Resource": "arn:aws:dynamodb:*:*:table/Users*",
+            "Condition": {"IpAddress": {"aws:SourceIp": "10.0.0.0/8"}}}}"#;
+        let simulator = PolicySimulator::new(document).unwrap();
+        let request = Request {
+            operation: "Query".to_string(),
+            table: "Users".to_string(),
+            index: Some("by_email".to_string()),
+            pk: Some(KeyValue::Exact("a@example.com".to_string())),
+            sk: None,
+            attributes: None,
+            return_values: None,
+        };
+        let context = RequestContext::from(&request);
+        assert_eq!(
+            context.to_string(),
+            "dynamodb:Query on arn:aws:dynamodb:us-east-1:123456789012:table/Users/index/by_email \
+             dynamodb:LeadingKeys=[a@example.com] dynamodb:Select=[ALL_ATTRIBUTES]"
+        );
+        assert_denies!(simulator, request.clone(), "without aws:SourceIp");
+        assert_allows!(simulator, context.clone().key("aws:SourceIp", "10.1.2.3"));
+        assert_denies!(simulator, context.clone().key("aws:SourceIp", "192.168.0.1"));
+        let scan = Request { operation: "Scan".to_string(), ..request };
+        assert_denies!(simulator, RequestContext::from(scan).key("aws:SourceIp", "10.1.2.3"));
+    }
+}
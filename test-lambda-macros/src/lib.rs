@@ -2,7 +2,7 @@ use lambda_macros;
 use policy_macros;
 
 
-#[policy_macros::policy_attr(allow get mybucket)]
+#[policy_macros::policy_attr(allow get on bucket "mybucket")]
 #[lambda_macros::lambda(GET "mypath")]
 pub async fn my_test(
     myarg: String